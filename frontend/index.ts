@@ -18,6 +18,33 @@ document.addEventListener("click", function (e) {
   }
 });
 
+async function refreshLiveForm(formElement: HTMLFormElement) {
+  const formData = new FormData(formElement);
+  const url = formElement.getAttribute("action")!;
+
+  const searchParams = new URLSearchParams(formData as any);
+
+  const fullUrl = `${url}?${searchParams.toString()}`;
+
+  // Keep the current view in the URL so it can be shared
+  if (formElement.hasAttribute("data-sync-url")) {
+    window.history.replaceState(
+      null,
+      "",
+      `${window.location.pathname}?${searchParams.toString()}`
+    );
+  }
+
+  const resp = await fetch(fullUrl);
+
+  const body = await resp.text();
+
+  const targetSelector = formElement.getAttribute("data-target")!;
+  const targetElement = formElement.querySelector(targetSelector);
+
+  targetElement!.innerHTML = body;
+}
+
 document.addEventListener("change", async function (e) {
   if (!e.target) return;
 
@@ -25,20 +52,161 @@ document.addEventListener("change", async function (e) {
     "[data-app='LiveForm']"
   );
   if (formElement) {
-    const formData = new FormData(formElement as HTMLFormElement);
-    const url = formElement.getAttribute("action")!;
+    await refreshLiveForm(formElement as HTMLFormElement);
+  }
+});
 
-    const searchParams = new URLSearchParams(formData as any);
+type CheckinEvent = {
+  checkin_id: string;
+  page_id: string;
+  site_id: string;
+  outcome: string;
+  status_changed: boolean;
+};
 
-    const fullUrl = `${url}?${searchParams.toString()}`;
+// Checkins tend to arrive in bursts, one per page, so wait for the burst to
+// finish before refreshing
+const LIVE_REFRESH_DELAY_MS = 2000;
 
-    const resp = await fetch(fullUrl);
+document.addEventListener("DOMContentLoaded", function () {
+  const liveForms = document.querySelectorAll<HTMLFormElement>(
+    "[data-app='LiveForm'][data-live-site], [data-app='LiveForm'][data-live-page]"
+  );
+  if (liveForms.length === 0) return;
+
+  const pendingRefreshes = new Map<HTMLFormElement, number>();
+
+  const events = new EventSource("/my/events");
+  events.addEventListener("checkin", function (e) {
+    const checkin: CheckinEvent = JSON.parse((e as MessageEvent).data);
+
+    liveForms.forEach((formElement) => {
+      const siteId = formElement.getAttribute("data-live-site");
+      const pageId = formElement.getAttribute("data-live-page");
+      if (siteId !== checkin.site_id && pageId !== checkin.page_id) return;
+      if (pendingRefreshes.has(formElement)) return;
+
+      pendingRefreshes.set(
+        formElement,
+        window.setTimeout(async () => {
+          pendingRefreshes.delete(formElement);
+          await refreshLiveForm(formElement);
+        }, LIVE_REFRESH_DELAY_MS)
+      );
+    });
+  });
+});
 
-    const body = await resp.text();
+// Formats `time` the way a `datetime-local` input expects it, in `timeZone`.
+// The Swedish locale happens to use the same ordering as ISO 8601
+function toLocalInputValue(time: Date, timeZone: string) {
+  return time
+    .toLocaleString("sv-SE", { timeZone, hour12: false })
+    .replace(" ", "T")
+    .slice(0, 16);
+}
 
-    const targetSelector = formElement.getAttribute("data-target")!;
-    const targetElement = formElement.querySelector(targetSelector);
+document.addEventListener("DOMContentLoaded", async function () {
+  const browserTimeZone = Intl.DateTimeFormat().resolvedOptions().timeZone;
 
-    targetElement!.innerHTML = body;
+  const timezoneInputs = document.querySelectorAll<HTMLInputElement>(
+    "input[data-browser-timezone]"
+  );
+  for (const input of Array.from(timezoneInputs)) {
+    if (input.value) continue;
+
+    input.value = browserTimeZone;
+
+    const formElement = input.closest<HTMLFormElement>(
+      "[data-app='LiveForm']"
+    );
+    if (formElement) {
+      await refreshLiveForm(formElement);
+    }
   }
 });
+
+// Dragging across a graph zooms the form it's in to the selected range
+document.addEventListener("mousedown", function (e) {
+  if (!e.target) return;
+
+  const graph = (e.target as Element).closest<SVGSVGElement>(
+    "svg[data-app='ZoomableGraph']"
+  );
+  if (!graph) return;
+
+  const formElement = graph.closest<HTMLFormElement>("[data-app='LiveForm']");
+  const startInput = formElement?.querySelector<HTMLInputElement>(
+    "input[name='start']"
+  );
+  const endInput = formElement?.querySelector<HTMLInputElement>(
+    "input[name='end']"
+  );
+  if (!formElement || !startInput || !endInput) return;
+
+  e.preventDefault();
+
+  const rangeStart = Number(graph.getAttribute("data-range-start"));
+  const rangeEnd = Number(graph.getAttribute("data-range-end"));
+  const viewBoxWidth = graph.viewBox.baseVal.width;
+
+  const fractionAt = (clientX: number) => {
+    const bounds = graph.getBoundingClientRect();
+    return Math.min(Math.max((clientX - bounds.left) / bounds.width, 0), 1);
+  };
+
+  const dragStart = fractionAt(e.clientX);
+
+  const selection = document.createElementNS(
+    "http://www.w3.org/2000/svg",
+    "rect"
+  );
+  selection.setAttribute("y", "0");
+  selection.setAttribute("height", "100%");
+  selection.setAttribute("fill", "#6366f1");
+  selection.setAttribute("fill-opacity", "0.2");
+  graph.appendChild(selection);
+
+  const onMove = (moveEvent: MouseEvent) => {
+    const dragEnd = fractionAt(moveEvent.clientX);
+    selection.setAttribute(
+      "x",
+      String(Math.min(dragStart, dragEnd) * viewBoxWidth)
+    );
+    selection.setAttribute(
+      "width",
+      String(Math.abs(dragEnd - dragStart) * viewBoxWidth)
+    );
+  };
+
+  const onUp = async (upEvent: MouseEvent) => {
+    document.removeEventListener("mousemove", onMove);
+    document.removeEventListener("mouseup", onUp);
+    selection.remove();
+
+    const dragEnd = fractionAt(upEvent.clientX);
+    // Ignore clicks and tiny drags, which are more likely to be accidental
+    if (Math.abs(dragEnd - dragStart) < 0.02) return;
+
+    const toTime = (fraction: number) =>
+      new Date(rangeStart + fraction * (rangeEnd - rangeStart));
+
+    const timeZone =
+      formElement.querySelector<HTMLInputElement>("input[name='tz']")?.value ||
+      "UTC";
+
+    startInput.value = toLocalInputValue(
+      toTime(Math.min(dragStart, dragEnd)),
+      timeZone
+    );
+    endInput.value = toLocalInputValue(
+      toTime(Math.max(dragStart, dragEnd)),
+      timeZone
+    );
+
+    await refreshLiveForm(formElement);
+  };
+
+  document.addEventListener("mousemove", onMove);
+  document.addEventListener("mouseup", onUp);
+});
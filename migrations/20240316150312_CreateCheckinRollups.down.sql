@@ -0,0 +1,5 @@
+DROP INDEX idx_checkins_created_at;
+
+DROP INDEX idx_checkins_page_id_created_at;
+
+DROP TABLE CheckinRollups;
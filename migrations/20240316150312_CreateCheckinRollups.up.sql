@@ -0,0 +1,25 @@
+-- Rollups aggregate Checkins into per-page buckets so long ranges can be
+-- queried without scanning raw rows. `resolution` is the `date_trunc` field
+-- used to build the bucket, either 'hour' or 'day'
+CREATE TABLE
+  CheckinRollups (
+    page_id UUID NOT NULL REFERENCES Pages (page_id),
+    resolution TEXT NOT NULL CHECK (resolution IN ('hour', 'day')),
+    bucket_start TIMESTAMP
+    WITH
+      TIME ZONE NOT NULL,
+      checkin_count BIGINT NOT NULL,
+      success_count BIGINT NOT NULL,
+      min_duration_nanos BIGINT,
+      avg_duration_nanos BIGINT,
+      max_duration_nanos BIGINT,
+      p95_duration_nanos BIGINT,
+      PRIMARY KEY (page_id, resolution, bucket_start)
+  );
+
+CREATE INDEX idx_checkin_rollups_resolution_bucket_start ON CheckinRollups (resolution, bucket_start);
+
+CREATE INDEX idx_checkins_page_id_created_at ON Checkins (page_id, created_at);
+
+-- Rolling up and pruning go by `created_at` across every page
+CREATE INDEX idx_checkins_created_at ON Checkins (created_at);
@@ -0,0 +1,3 @@
+ALTER TABLE CheckinRollups
+DROP COLUMN p50_duration_nanos,
+DROP COLUMN p99_duration_nanos;
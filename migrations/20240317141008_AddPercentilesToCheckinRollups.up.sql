@@ -0,0 +1,5 @@
+-- Rollups created before this migration only have the p95 so these stay NULL
+-- for those buckets
+ALTER TABLE CheckinRollups
+ADD COLUMN p50_duration_nanos BIGINT,
+ADD COLUMN p99_duration_nanos BIGINT;
@@ -0,0 +1,6 @@
+ALTER TABLE Checkins
+DROP COLUMN dns_nanos,
+DROP COLUMN connect_nanos,
+DROP COLUMN tls_nanos,
+DROP COLUMN ttfb_nanos,
+DROP COLUMN download_nanos;
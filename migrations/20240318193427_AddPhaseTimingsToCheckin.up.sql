@@ -0,0 +1,8 @@
+-- Per phase timings of the request made for the checkin, all in nanoseconds
+-- These are NULL for checkins that errored or were made before we recorded them
+ALTER TABLE Checkins
+ADD COLUMN dns_nanos BIGINT,
+ADD COLUMN connect_nanos BIGINT,
+ADD COLUMN tls_nanos BIGINT,
+ADD COLUMN ttfb_nanos BIGINT,
+ADD COLUMN download_nanos BIGINT;
@@ -0,0 +1,3 @@
+DROP TABLE SloAlerts;
+
+DROP TABLE Slos;
@@ -0,0 +1,37 @@
+-- Service level objectives for a whole site, or a single page when page_id is
+-- set. A checkin counts as good when it succeeded and, if there is a latency
+-- threshold, finished within it
+CREATE TABLE
+  Slos (
+    slo_id UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid (),
+    site_id UUID NOT NULL REFERENCES Sites (site_id),
+    page_id UUID REFERENCES Pages (page_id),
+    name TEXT NOT NULL,
+    target_percent DOUBLE PRECISION NOT NULL CHECK (
+      target_percent > 0
+      AND target_percent < 100
+    ),
+    window_days INTEGER NOT NULL CHECK (window_days > 0),
+    latency_threshold_ms INTEGER CHECK (latency_threshold_ms > 0),
+    created_at TIMESTAMP
+    WITH
+      TIME ZONE NOT NULL DEFAULT now ()
+  );
+
+-- An alert is open while resolved_at is NULL
+CREATE TABLE
+  SloAlerts (
+    slo_alert_id UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid (),
+    slo_id UUID NOT NULL REFERENCES Slos (slo_id) ON DELETE CASCADE,
+    burn_rate DOUBLE PRECISION NOT NULL,
+    fired_at TIMESTAMP
+    WITH
+      TIME ZONE NOT NULL DEFAULT now (),
+      resolved_at TIMESTAMP
+    WITH
+      TIME ZONE
+  );
+
+CREATE UNIQUE INDEX idx_slo_alerts_open ON SloAlerts (slo_id)
+WHERE
+  resolved_at IS NULL;
@@ -0,0 +1,3 @@
+DROP TABLE ReportDeliveries;
+
+DROP TABLE ReportSubscriptions;
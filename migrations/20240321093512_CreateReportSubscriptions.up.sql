@@ -0,0 +1,24 @@
+-- Addresses that get the monthly uptime report for a site emailed to them
+CREATE TABLE
+  ReportSubscriptions (
+    report_subscription_id UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid (),
+    site_id UUID NOT NULL REFERENCES Sites (site_id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    created_at TIMESTAMP
+    WITH
+      TIME ZONE NOT NULL DEFAULT now ()
+  );
+
+CREATE UNIQUE INDEX idx_report_subscriptions_site_email ON ReportSubscriptions (site_id, email);
+
+-- One row per subscription per month once that month's report has been sent,
+-- so the cron job can run often without sending duplicates
+CREATE TABLE
+  ReportDeliveries (
+    report_subscription_id UUID NOT NULL REFERENCES ReportSubscriptions (report_subscription_id) ON DELETE CASCADE,
+    period_start DATE NOT NULL,
+    sent_at TIMESTAMP
+    WITH
+      TIME ZONE NOT NULL DEFAULT now (),
+      PRIMARY KEY (report_subscription_id, period_start)
+  );
@@ -0,0 +1,2 @@
+ALTER TABLE Sites
+DROP COLUMN propagate_trace_context;
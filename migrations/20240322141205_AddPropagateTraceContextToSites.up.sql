@@ -0,0 +1,4 @@
+-- When set, checkins for the site's pages send a W3C traceparent header so the
+-- monitored services can tie our probes into their own traces
+ALTER TABLE Sites
+ADD COLUMN propagate_trace_context BOOLEAN NOT NULL DEFAULT false;
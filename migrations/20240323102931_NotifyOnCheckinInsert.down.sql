@@ -0,0 +1,3 @@
+DROP TRIGGER checkin_created ON Checkins;
+
+DROP FUNCTION notify_checkin_created;
@@ -0,0 +1,38 @@
+-- Publishes every new checkin on the checkin_created channel, so each web
+-- instance can push it to the browsers of the site's owner. status_changed is
+-- true when the outcome differs from the page's previous checkin
+CREATE FUNCTION notify_checkin_created () RETURNS TRIGGER AS $$
+DECLARE
+  previous_outcome TEXT;
+BEGIN
+  SELECT outcome INTO previous_outcome
+  FROM Checkins
+  WHERE page_id = NEW.page_id
+    AND checkin_id <> NEW.checkin_id
+    AND created_at <= NEW.created_at
+  ORDER BY created_at DESC
+  LIMIT 1;
+
+  PERFORM pg_notify(
+    'checkin_created',
+    json_build_object(
+      'checkin_id', NEW.checkin_id,
+      'page_id', NEW.page_id,
+      'site_id', Sites.site_id,
+      'user_id', Sites.user_id,
+      'outcome', NEW.outcome,
+      'status_changed', previous_outcome IS DISTINCT FROM NEW.outcome
+    )::text
+  )
+  FROM Pages
+  JOIN Sites ON Sites.site_id = Pages.site_id
+  WHERE Pages.page_id = NEW.page_id;
+
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER checkin_created
+AFTER INSERT ON Checkins
+FOR EACH ROW
+EXECUTE FUNCTION notify_checkin_created ();
@@ -0,0 +1,81 @@
+-- Each site goes back to the earliest owner of its organization, or its
+-- earliest member when it has no owners left
+ALTER TABLE Sites
+ADD COLUMN user_id UUID REFERENCES Users (user_id);
+
+UPDATE Sites
+SET
+  user_id = (
+    SELECT
+      OrganizationMemberships.user_id
+    FROM
+      OrganizationMemberships
+    WHERE
+      OrganizationMemberships.organization_id = Sites.organization_id
+    ORDER BY
+      OrganizationMemberships.role = 'owner' DESC,
+      OrganizationMemberships.created_at ASC
+    LIMIT
+      1
+  );
+
+-- A site in an organization nobody is a member of any more has no one to go
+-- back to. Stop with a clear message rather than a NOT NULL violation, so
+-- they can be deleted or given a member first
+DO $$
+BEGIN
+  IF EXISTS (SELECT 1 FROM Sites WHERE user_id IS NULL) THEN
+    RAISE EXCEPTION 'Some sites belong to organizations without any members, delete them or add a member before reverting';
+  END IF;
+END;
+$$;
+
+ALTER TABLE Sites
+ALTER COLUMN user_id
+SET NOT NULL;
+
+ALTER TABLE Sites
+DROP CONSTRAINT sites_organization_id_domain_key;
+
+ALTER TABLE Sites
+ADD CONSTRAINT sites_user_id_domain_key UNIQUE (user_id, domain);
+
+ALTER TABLE Sites
+DROP COLUMN organization_id;
+
+DROP TABLE OrganizationInvitations;
+
+DROP TABLE OrganizationMemberships;
+
+DROP TABLE Organizations;
+
+CREATE OR REPLACE FUNCTION notify_checkin_created () RETURNS TRIGGER AS $$
+DECLARE
+  previous_outcome TEXT;
+BEGIN
+  SELECT outcome INTO previous_outcome
+  FROM Checkins
+  WHERE page_id = NEW.page_id
+    AND checkin_id <> NEW.checkin_id
+    AND created_at <= NEW.created_at
+  ORDER BY created_at DESC
+  LIMIT 1;
+
+  PERFORM pg_notify(
+    'checkin_created',
+    json_build_object(
+      'checkin_id', NEW.checkin_id,
+      'page_id', NEW.page_id,
+      'site_id', Sites.site_id,
+      'user_id', Sites.user_id,
+      'outcome', NEW.outcome,
+      'status_changed', previous_outcome IS DISTINCT FROM NEW.outcome
+    )::text
+  )
+  FROM Pages
+  JOIN Sites ON Sites.site_id = Pages.site_id
+  WHERE Pages.page_id = NEW.page_id;
+
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
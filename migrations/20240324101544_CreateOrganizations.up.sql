@@ -0,0 +1,115 @@
+-- Sites belong to an organization instead of a single user, and users get
+-- access to them through their membership of it. role is one of owner (can
+-- manage members), editor (can change sites) or viewer (read only)
+CREATE TABLE
+  Organizations (
+    organization_id UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid (),
+    name TEXT NOT NULL,
+    created_at TIMESTAMP
+    WITH
+      TIME ZONE NOT NULL DEFAULT now ()
+  );
+
+CREATE TABLE
+  OrganizationMemberships (
+    organization_id UUID NOT NULL REFERENCES Organizations (organization_id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES Users (user_id) ON DELETE CASCADE,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'editor', 'viewer')),
+    -- The address the member was invited at, since Users don't have one
+    email TEXT,
+    created_at TIMESTAMP
+    WITH
+      TIME ZONE NOT NULL DEFAULT now (),
+      PRIMARY KEY (organization_id, user_id)
+  );
+
+CREATE INDEX idx_organization_memberships_user_id ON OrganizationMemberships (user_id);
+
+-- Anyone with the token can accept an invitation until it expires, which is
+-- why it's only ever sent to the invited address
+CREATE TABLE
+  OrganizationInvitations (
+    organization_invitation_id UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid (),
+    organization_id UUID NOT NULL REFERENCES Organizations (organization_id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'editor', 'viewer')),
+    token TEXT NOT NULL UNIQUE,
+    invited_by_user_id UUID NOT NULL REFERENCES Users (user_id),
+    created_at TIMESTAMP
+    WITH
+      TIME ZONE NOT NULL DEFAULT now (),
+      expires_at TIMESTAMP
+    WITH
+      TIME ZONE NOT NULL,
+      accepted_at TIMESTAMP
+    WITH
+      TIME ZONE
+  );
+
+-- Every existing user gets a personal organization for the sites they already
+-- own. Reusing the user's id for it keeps the backfill a few plain statements
+INSERT INTO
+  Organizations (organization_id, name)
+SELECT
+  user_id,
+  'Personal'
+FROM
+  Users;
+
+INSERT INTO
+  OrganizationMemberships (organization_id, user_id, role)
+SELECT
+  user_id,
+  user_id,
+  'owner'
+FROM
+  Users;
+
+ALTER TABLE Sites
+ADD COLUMN organization_id UUID REFERENCES Organizations (organization_id);
+
+UPDATE Sites
+SET
+  organization_id = user_id;
+
+ALTER TABLE Sites
+ALTER COLUMN organization_id
+SET NOT NULL;
+
+ALTER TABLE Sites
+DROP COLUMN user_id;
+
+ALTER TABLE Sites
+ADD CONSTRAINT sites_organization_id_domain_key UNIQUE (organization_id, domain);
+
+-- Live updates go to every member of the site's organization now
+CREATE OR REPLACE FUNCTION notify_checkin_created () RETURNS TRIGGER AS $$
+DECLARE
+  previous_outcome TEXT;
+BEGIN
+  SELECT outcome INTO previous_outcome
+  FROM Checkins
+  WHERE page_id = NEW.page_id
+    AND checkin_id <> NEW.checkin_id
+    AND created_at <= NEW.created_at
+  ORDER BY created_at DESC
+  LIMIT 1;
+
+  PERFORM pg_notify(
+    'checkin_created',
+    json_build_object(
+      'checkin_id', NEW.checkin_id,
+      'page_id', NEW.page_id,
+      'site_id', Sites.site_id,
+      'organization_id', Sites.organization_id,
+      'outcome', NEW.outcome,
+      'status_changed', previous_outcome IS DISTINCT FROM NEW.outcome
+    )::text
+  )
+  FROM Pages
+  JOIN Sites ON Sites.site_id = Pages.site_id
+  WHERE Pages.page_id = NEW.page_id;
+
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,21 @@
+-- Who changed a site's configuration and how. before and after are snapshots
+-- of the changed row, with before NULL for creates and after NULL for deletes.
+-- site_id and entity_id aren't foreign keys so entries outlive what they
+-- describe
+CREATE TABLE
+  AuditLogEntries (
+    audit_log_entry_id UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid (),
+    organization_id UUID NOT NULL REFERENCES Organizations (organization_id) ON DELETE CASCADE,
+    site_id UUID,
+    actor_user_id UUID REFERENCES Users (user_id) ON DELETE SET NULL,
+    action TEXT NOT NULL CHECK (action IN ('create', 'update', 'delete')),
+    entity_type TEXT NOT NULL,
+    entity_id UUID NOT NULL,
+    before JSONB,
+    after JSONB,
+    created_at TIMESTAMP
+    WITH
+      TIME ZONE NOT NULL DEFAULT now ()
+  );
+
+CREATE INDEX idx_audit_log_entries_site_id_created_at ON AuditLogEntries (site_id, created_at);
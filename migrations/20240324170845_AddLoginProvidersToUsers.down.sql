@@ -0,0 +1,21 @@
+-- Only coreyja IdP users can be kept
+DELETE FROM Users
+WHERE
+  coreyja_user_id IS NULL;
+
+ALTER TABLE Users
+DROP CONSTRAINT users_has_identity;
+
+DROP INDEX idx_users_username;
+
+DROP INDEX idx_users_oidc_identity;
+
+ALTER TABLE Users
+DROP COLUMN oidc_issuer,
+DROP COLUMN oidc_subject,
+DROP COLUMN username,
+DROP COLUMN password_hash;
+
+ALTER TABLE Users
+ALTER COLUMN coreyja_user_id
+SET NOT NULL;
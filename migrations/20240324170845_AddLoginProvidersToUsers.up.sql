@@ -0,0 +1,29 @@
+-- Users can come from the coreyja IdP, any OpenID Connect provider or a local
+-- username and password, depending on AUTH_PROVIDER. Each user only has the
+-- columns for the provider they signed up with
+ALTER TABLE Users
+ALTER COLUMN coreyja_user_id
+DROP NOT NULL;
+
+ALTER TABLE Users
+ADD COLUMN oidc_issuer TEXT,
+ADD COLUMN oidc_subject TEXT,
+ADD COLUMN username TEXT,
+ADD COLUMN password_hash TEXT;
+
+CREATE UNIQUE INDEX idx_users_oidc_identity ON Users (oidc_issuer, oidc_subject);
+
+CREATE UNIQUE INDEX idx_users_username ON Users (lower(username));
+
+ALTER TABLE Users
+ADD CONSTRAINT users_has_identity CHECK (
+  coreyja_user_id IS NOT NULL
+  OR (
+    oidc_issuer IS NOT NULL
+    AND oidc_subject IS NOT NULL
+  )
+  OR (
+    username IS NOT NULL
+    AND password_hash IS NOT NULL
+  )
+);
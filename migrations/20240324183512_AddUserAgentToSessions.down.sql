@@ -0,0 +1,4 @@
+DROP INDEX idx_sessions_user_id;
+
+ALTER TABLE Sessions
+DROP COLUMN user_agent;
@@ -0,0 +1,6 @@
+-- Lets the sessions page tell devices apart. Sessions are created by cja, so
+-- this is filled in the first time the session is used rather than at login
+ALTER TABLE Sessions
+ADD COLUMN user_agent TEXT;
+
+CREATE INDEX idx_sessions_user_id ON Sessions (user_id);
@@ -0,0 +1,9 @@
+-- Each session gets its own CSRF token. Sessions are created by cja, so the
+-- token comes from the default. Two UUIDs' worth of randomness, without
+-- needing pgcrypto
+ALTER TABLE Sessions
+ADD COLUMN csrf_token TEXT NOT NULL DEFAULT replace(
+  gen_random_uuid ()::text || gen_random_uuid ()::text,
+  '-',
+  ''
+);
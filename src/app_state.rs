@@ -1,12 +1,30 @@
 use cja::server::cookies::CookieKey;
-use miette::{miette, IntoDiagnostic};
+use miette::{miette, Context, IntoDiagnostic};
 use sqlx::PgPool;
+use tokio::sync::broadcast;
+
+use crate::{
+    auth::AuthProvider,
+    live::{checkin_events, CheckinEvent},
+    mailer::Mailer,
+    prometheus::Metrics,
+    sessions::SessionTimeouts,
+};
 
 #[derive(Debug, Clone)]
 pub struct AppState {
     pool: PgPool,
     cookie_key: CookieKey,
     pub font_awesome_kit_id: String,
+    /// Where the app is served from, for links that leave the browser like
+    /// the ones in emails
+    pub base_url: String,
+    pub auth: AuthProvider,
+    pub session_timeouts: SessionTimeouts,
+    pub checkin_retention: chrono::Duration,
+    pub mailer: Option<Mailer>,
+    pub metrics: Metrics,
+    pub checkin_events: broadcast::Sender<CheckinEvent>,
 }
 
 impl AppState {
@@ -15,15 +33,60 @@ impl AppState {
         let cookie_key = CookieKey::from_env_or_generate().into_diagnostic()?;
         let font_awesome_kit_id = std::env::var("FONT_AWESOME_KIT_ID")
             .map_err(|_| miette!("FONT_AWESOME_KIT_ID must be set"))?;
+        let base_url = std::env::var("APP_BASE_URL")
+            .unwrap_or_else(|_| "http://localhost:3001".into())
+            .trim_end_matches('/')
+            .to_string();
+        // The default keeps enough raw checkins to cover all of last month when
+        // the monthly reports go out
+        let checkin_retention_days = std::env::var("CHECKIN_RETENTION_DAYS")
+            .unwrap_or_else(|_| "35".into())
+            .parse::<i64>()
+            .into_diagnostic()
+            .wrap_err("CHECKIN_RETENTION_DAYS must be a number of days")?;
+        let auth = AuthProvider::from_env()?;
+        let session_timeouts = SessionTimeouts::from_env()?;
+        let mailer = Mailer::from_env()?;
+        let metrics = Metrics::install_from_env()?;
 
         Ok(Self {
             pool,
             cookie_key,
             font_awesome_kit_id,
+            base_url,
+            auth,
+            session_timeouts,
+            checkin_retention: chrono::Duration::days(checkin_retention_days),
+            mailer,
+            metrics,
+            checkin_events: checkin_events(),
         })
     }
 }
 
+#[cfg(test)]
+impl AppState {
+    /// A state around a test database, logging in with `auth` and without
+    /// email or metrics
+    pub fn for_tests(pool: PgPool, auth: AuthProvider) -> Self {
+        Self {
+            pool,
+            cookie_key: CookieKey::from_env_or_generate().unwrap(),
+            font_awesome_kit_id: "test".to_string(),
+            base_url: "http://localhost:3001".to_string(),
+            auth,
+            session_timeouts: SessionTimeouts {
+                idle: chrono::Duration::days(7),
+                absolute: chrono::Duration::days(30),
+            },
+            checkin_retention: chrono::Duration::days(35),
+            mailer: None,
+            metrics: Metrics::disabled(),
+            checkin_events: checkin_events(),
+        }
+    }
+}
+
 impl cja::app_state::AppState for AppState {
     fn version(&self) -> &str {
         "dev"
@@ -0,0 +1,236 @@
+use std::{ops::Range, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use miette::IntoDiagnostic;
+use serde_json::Value;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 3] = [
+        AuditAction::Create,
+        AuditAction::Update,
+        AuditAction::Delete,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Create => "create",
+            AuditAction::Update => "update",
+            AuditAction::Delete => "delete",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AuditAction::Create => "Created",
+            AuditAction::Update => "Updated",
+            AuditAction::Delete => "Deleted",
+        }
+    }
+}
+
+impl FromStr for AuditAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AuditAction::ALL
+            .into_iter()
+            .find(|action| action.as_str() == s)
+            .ok_or_else(|| format!("Unknown audit action {s}"))
+    }
+}
+
+/// The kinds of configuration whose changes are logged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditEntity {
+    Site,
+    Page,
+    Slo,
+    ReportSubscription,
+}
+
+impl AuditEntity {
+    pub const ALL: [AuditEntity; 4] = [
+        AuditEntity::Site,
+        AuditEntity::Page,
+        AuditEntity::Slo,
+        AuditEntity::ReportSubscription,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEntity::Site => "site",
+            AuditEntity::Page => "page",
+            AuditEntity::Slo => "slo",
+            AuditEntity::ReportSubscription => "report_subscription",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AuditEntity::Site => "Site",
+            AuditEntity::Page => "Page",
+            AuditEntity::Slo => "SLO",
+            AuditEntity::ReportSubscription => "Report subscription",
+        }
+    }
+}
+
+impl FromStr for AuditEntity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AuditEntity::ALL
+            .into_iter()
+            .find(|entity| entity.as_str() == s)
+            .ok_or_else(|| format!("Unknown audit entity {s}"))
+    }
+}
+
+/// A change to record. `before` and `after` are `to_jsonb` snapshots of the
+/// row, taken in the same transaction as the change so they can't drift from
+/// what was actually written
+pub struct AuditChange {
+    pub organization_id: Uuid,
+    pub site_id: Uuid,
+    pub actor_user_id: Uuid,
+    pub action: AuditAction,
+    pub entity: AuditEntity,
+    pub entity_id: Uuid,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+impl AuditChange {
+    pub async fn record(self, db: impl PgExecutor<'_>) -> miette::Result<()> {
+        sqlx::query!(
+            r#"
+      INSERT INTO AuditLogEntries (organization_id, site_id, actor_user_id, action, entity_type, entity_id, before, after)
+      VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+    "#,
+            self.organization_id,
+            self.site_id,
+            self.actor_user_id,
+            self.action.as_str(),
+            self.entity.as_str(),
+            self.entity_id,
+            self.before,
+            self.after
+        )
+        .execute(db)
+        .await
+        .into_diagnostic()?;
+
+        Ok(())
+    }
+}
+
+pub struct AuditLogEntry {
+    pub actor_user_id: Option<Uuid>,
+    /// The address the actor joined the organization with, when we know it
+    pub actor_email: Option<String>,
+    pub action: AuditAction,
+    pub entity: AuditEntity,
+    pub entity_id: Uuid,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AuditLogEntry {
+    pub fn actor(&self) -> String {
+        match (&self.actor_email, self.actor_user_id) {
+            (Some(email), _) => email.clone(),
+            (None, Some(user_id)) => format!("User {user_id}"),
+            (None, None) => "Deleted user".to_string(),
+        }
+    }
+
+    /// The fields that differ between the before and after snapshots, with
+    /// their old and new values. Creates and deletes list every field
+    pub fn changed_fields<'a>(&'a self) -> Vec<(String, Option<&'a Value>, Option<&'a Value>)> {
+        let before = self.before.as_ref().and_then(Value::as_object);
+        let after = self.after.as_ref().and_then(Value::as_object);
+        let field = |snapshot: Option<&'a serde_json::Map<String, Value>>, key: &str| {
+            snapshot.and_then(|snapshot| snapshot.get(key))
+        };
+
+        let mut keys = before
+            .into_iter()
+            .chain(after)
+            .flat_map(|snapshot| snapshot.keys())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+
+        keys.into_iter()
+            .filter(|key| field(before, key) != field(after, key))
+            .map(|key| (key.clone(), field(before, key), field(after, key)))
+            .collect()
+    }
+}
+
+/// The site's audit log, newest first. `limit` caps how many entries come back
+/// when there's no range to bound them
+pub async fn entries_for_site(
+    db: &PgPool,
+    site_id: Uuid,
+    range: Option<Range<DateTime<Utc>>>,
+    limit: Option<i64>,
+) -> miette::Result<Vec<AuditLogEntry>> {
+    let rows = sqlx::query!(
+        r#"
+    SELECT
+      AuditLogEntries.actor_user_id,
+      OrganizationMemberships.email as "actor_email?",
+      AuditLogEntries.action,
+      AuditLogEntries.entity_type,
+      AuditLogEntries.entity_id,
+      AuditLogEntries.before,
+      AuditLogEntries.after,
+      AuditLogEntries.created_at
+    FROM AuditLogEntries
+    LEFT JOIN OrganizationMemberships
+      ON OrganizationMemberships.organization_id = AuditLogEntries.organization_id
+      AND OrganizationMemberships.user_id = AuditLogEntries.actor_user_id
+    WHERE AuditLogEntries.site_id = $1
+      AND ($2::timestamptz IS NULL OR AuditLogEntries.created_at >= $2)
+      AND ($3::timestamptz IS NULL OR AuditLogEntries.created_at < $3)
+    ORDER BY AuditLogEntries.created_at DESC
+    LIMIT $4
+  "#,
+        site_id,
+        range.as_ref().map(|range| range.start),
+        range.as_ref().map(|range| range.end),
+        limit
+    )
+    .fetch_all(db)
+    .await
+    .into_diagnostic()?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(AuditLogEntry {
+                actor_user_id: row.actor_user_id,
+                actor_email: row.actor_email,
+                action: row.action.parse().map_err(|e: String| miette::miette!(e))?,
+                entity: row
+                    .entity_type
+                    .parse()
+                    .map_err(|e: String| miette::miette!(e))?,
+                entity_id: row.entity_id,
+                before: row.before,
+                after: row.after,
+                created_at: row.created_at,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use maud::html;
+
+use crate::{app_state::AppState, templates::IntoTemplate};
+
+/// Everything that can go wrong while logging someone in. Each variant maps
+/// to a status code and a message that's safe to show the user, the details
+/// only go to the logs
+#[derive(Debug)]
+pub enum AuthError {
+    /// Something this app needs to log people in isn't set, like a signing key
+    Misconfigured(String),
+    /// The callback doesn't belong to a login this browser started, or that
+    /// login has expired
+    StateMismatch,
+    /// The callback is missing something the provider always sends
+    InvalidCallback(&'static str),
+    /// The provider refused the login, or the user cancelled it there
+    Rejected(String),
+    /// The provider couldn't be reached or had an error of its own
+    ProviderUnavailable(miette::Report),
+    /// The provider answered with something we can't use, like an ID token
+    /// that doesn't verify
+    InvalidProviderResponse(miette::Report),
+    /// Our own database or session handling failed
+    Internal(miette::Report),
+}
+
+impl AuthError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AuthError::Misconfigured(_) | AuthError::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            AuthError::StateMismatch | AuthError::InvalidCallback(_) => StatusCode::BAD_REQUEST,
+            AuthError::Rejected(_) => StatusCode::FORBIDDEN,
+            AuthError::ProviderUnavailable(_) | AuthError::InvalidProviderResponse(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+        }
+    }
+
+    fn user_message(&self) -> &'static str {
+        match self {
+            AuthError::Misconfigured(_) | AuthError::Internal(_) => {
+                "Something went wrong on our end while logging you in."
+            }
+            AuthError::StateMismatch => "This login expired or was started in a different browser.",
+            AuthError::InvalidCallback(_) => "The login provider sent back an incomplete response.",
+            AuthError::Rejected(_) => "The login provider didn't let you log in.",
+            AuthError::ProviderUnavailable(_) => "We couldn't reach the login provider.",
+            AuthError::InvalidProviderResponse(_) => {
+                "The login provider sent back a response we couldn't verify."
+            }
+        }
+    }
+
+    /// Logs the error and renders the page telling the user what happened
+    pub async fn into_page(self, app_state: &AppState) -> Response {
+        if self.status().is_server_error() {
+            tracing::error!(err = ?self, "Login failed");
+        } else {
+            tracing::warn!(err = ?self, "Login failed");
+        }
+
+        let status = self.status();
+        let page = html! {
+          h1 { "Couldn't log you in" }
+
+          p { (self.user_message()) }
+
+          p { a href="/login" { "Try logging in again" } }
+        }
+        .into_template(app_state.clone(), None)
+        .await
+        .unwrap();
+
+        (status, page).into_response()
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Misconfigured(what) => write!(f, "Login is misconfigured: {what}"),
+            AuthError::StateMismatch => write!(f, "Login state didn't match"),
+            AuthError::InvalidCallback(what) => write!(f, "Invalid login callback: {what}"),
+            AuthError::Rejected(why) => write!(f, "Login rejected by the provider: {why}"),
+            AuthError::ProviderUnavailable(err) => write!(f, "Login provider unavailable: {err}"),
+            AuthError::InvalidProviderResponse(err) => {
+                write!(f, "Invalid response from the login provider: {err}")
+            }
+            AuthError::Internal(err) => write!(f, "Login failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl From<miette::Report> for AuthError {
+    fn from(err: miette::Report) -> Self {
+        AuthError::Internal(err)
+    }
+}
@@ -0,0 +1,62 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use miette::{miette, IntoDiagnostic};
+
+/// Shortest password a local account can be created with
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct LocalConfig {
+    /// Whether anyone can create an account. Off unless
+    /// `LOCAL_ALLOW_SIGNUPS=true`, so a new install isn't open to the world.
+    /// Turn it on while the people who need accounts sign up
+    pub allow_signups: bool,
+}
+
+impl LocalConfig {
+    pub fn from_env() -> Self {
+        Self {
+            allow_signups: std::env::var("LOCAL_ALLOW_SIGNUPS").as_deref() == Ok("true"),
+        }
+    }
+}
+
+/// Hashing is deliberately slow, so it runs on the blocking pool rather than
+/// holding up the other requests on this worker
+pub async fn hash_password(password: String) -> miette::Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| miette!("Couldn't hash password: {e}"))
+    })
+    .await
+    .into_diagnostic()?
+}
+
+/// Checks a password against a stored hash. Pass `None` when there's no user
+/// with that name, so unknown usernames take as long to reject as wrong
+/// passwords do
+pub async fn verify_password(password: String, password_hash: Option<String>) -> bool {
+    // A hash of an arbitrary password, only ever compared against to spend the
+    // same time as a real check
+    const DUMMY_HASH: &str =
+        "$argon2id$v=19$m=19456,t=2,p=1$c29tZXNhbHRzb21lc2FsdA$DM6n1Nrs3cKuDLCJvWWIJAVeLfdbkI3sxxVmLvNFs9A";
+
+    tokio::task::spawn_blocking(move || {
+        let Ok(hash) = PasswordHash::new(password_hash.as_deref().unwrap_or(DUMMY_HASH)) else {
+            return false;
+        };
+
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+            && password_hash.is_some()
+    })
+    .await
+    .unwrap_or(false)
+}
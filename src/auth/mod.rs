@@ -0,0 +1,37 @@
+use miette::miette;
+
+pub(crate) mod coreyja;
+mod error;
+pub(crate) mod local;
+pub(crate) mod oidc;
+
+pub use error::AuthError;
+
+/// How users log in. Only one provider is active at a time, and users created
+/// through one can't log in through another
+#[derive(Debug, Clone)]
+pub enum AuthProvider {
+    /// The coreyja.com IdP, which also tells us who's an active sponsor
+    Coreyja(coreyja::CoreyjaConfig),
+    /// Any OpenID Connect provider, using the authorization code flow with PKCE
+    Oidc(oidc::OidcConfig),
+    /// Usernames and passwords stored here
+    Local(local::LocalConfig),
+}
+
+impl AuthProvider {
+    /// Picks the provider from `AUTH_PROVIDER`, which is one of `coreyja` (the
+    /// default), `oidc` or `local`
+    pub fn from_env() -> miette::Result<Self> {
+        let provider = std::env::var("AUTH_PROVIDER").unwrap_or_else(|_| "coreyja".into());
+
+        match provider.as_str() {
+            "coreyja" => Ok(AuthProvider::Coreyja(coreyja::CoreyjaConfig::from_env())),
+            "oidc" => Ok(AuthProvider::Oidc(oidc::OidcConfig::from_env()?)),
+            "local" => Ok(AuthProvider::Local(local::LocalConfig::from_env())),
+            other => Err(miette!(
+                "AUTH_PROVIDER must be one of coreyja, oidc or local, not {other}"
+            )),
+        }
+    }
+}
@@ -0,0 +1,467 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use jsonwebtoken::{jwk::JwkSet, DecodingKey, Validation};
+use miette::{miette, IntoDiagnostic};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use super::AuthError;
+
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    /// The issuer identifier, which the discovery document is fetched
+    /// relative to. Plain http is allowed so a local mock provider works
+    pub issuer_url: String,
+    pub client_id: String,
+    /// Left out for public clients, which only have PKCE to go on
+    pub client_secret: Option<String>,
+    pub scopes: String,
+}
+
+/// The parts of the provider's discovery document we use
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    iss: String,
+    sub: String,
+    nonce: Option<String>,
+}
+
+/// Who the provider says logged in
+pub struct OidcIdentity {
+    pub issuer: String,
+    pub subject: String,
+}
+
+/// The secrets for one login attempt, kept in a private cookie between
+/// sending the user to the provider and them coming back
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingLogin {
+    pub state: String,
+    nonce: String,
+    code_verifier: String,
+}
+
+impl PendingLogin {
+    pub fn generate() -> Self {
+        // Two v4 UUIDs give a 64 character verifier with 244 random bits,
+        // inside the 43 to 128 characters PKCE allows
+        Self {
+            state: Uuid::new_v4().simple().to_string(),
+            nonce: Uuid::new_v4().simple().to_string(),
+            code_verifier: format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple()),
+        }
+    }
+
+    fn code_challenge(&self) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(self.code_verifier.as_bytes()))
+    }
+}
+
+impl OidcConfig {
+    pub fn from_env() -> miette::Result<Self> {
+        Ok(Self {
+            issuer_url: std::env::var("OIDC_ISSUER_URL")
+                .map_err(|_| miette!("OIDC_ISSUER_URL must be set when AUTH_PROVIDER is oidc"))?
+                .trim_end_matches('/')
+                .to_string(),
+            client_id: std::env::var("OIDC_CLIENT_ID")
+                .map_err(|_| miette!("OIDC_CLIENT_ID must be set when AUTH_PROVIDER is oidc"))?,
+            client_secret: std::env::var("OIDC_CLIENT_SECRET").ok(),
+            scopes: std::env::var("OIDC_SCOPES").unwrap_or_else(|_| "openid email profile".into()),
+        })
+    }
+
+    /// Fetched on every login rather than at startup, so the app still boots
+    /// when the provider is down and picks up any changes to it
+    async fn metadata(&self, client: &reqwest::Client) -> Result<ProviderMetadata, AuthError> {
+        let metadata = client
+            .get(format!(
+                "{}/.well-known/openid-configuration",
+                self.issuer_url
+            ))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .into_diagnostic()
+            .map_err(AuthError::ProviderUnavailable)?
+            .json::<ProviderMetadata>()
+            .await
+            .into_diagnostic()
+            .map_err(AuthError::InvalidProviderResponse)?;
+
+        if metadata.issuer.trim_end_matches('/') != self.issuer_url {
+            return Err(AuthError::Misconfigured(format!(
+                "The discovery document is for {}, not {}",
+                metadata.issuer, self.issuer_url
+            )));
+        }
+
+        Ok(metadata)
+    }
+
+    /// Where to send the user to log in
+    pub async fn authorization_url(
+        &self,
+        redirect_uri: &str,
+        pending: &PendingLogin,
+    ) -> Result<String, AuthError> {
+        let metadata = self.metadata(&reqwest::Client::new()).await?;
+
+        let mut url = reqwest::Url::parse(&metadata.authorization_endpoint)
+            .into_diagnostic()
+            .map_err(AuthError::InvalidProviderResponse)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("scope", &self.scopes)
+            .append_pair("state", &pending.state)
+            .append_pair("nonce", &pending.nonce)
+            .append_pair("code_challenge", &pending.code_challenge())
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(url.to_string())
+    }
+
+    /// Exchanges the code the provider redirected back with for an ID token,
+    /// and checks the token was issued by the provider, for us and for this
+    /// login attempt. The caller has to have checked `state` already
+    pub async fn finish(
+        &self,
+        redirect_uri: &str,
+        pending: &PendingLogin,
+        code: &str,
+    ) -> Result<OidcIdentity, AuthError> {
+        let client = reqwest::Client::new();
+        let metadata = self.metadata(&client).await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("client_id", self.client_id.as_str()),
+            ("code_verifier", pending.code_verifier.as_str()),
+        ];
+        if let Some(client_secret) = &self.client_secret {
+            form.push(("client_secret", client_secret.as_str()));
+        }
+
+        let resp = client
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .into_diagnostic()
+            .map_err(AuthError::ProviderUnavailable)?;
+        // A 4xx means the code was already used, has expired or wasn't issued
+        // to us
+        let status = resp.status();
+        if status.is_client_error() {
+            return Err(AuthError::Rejected(format!(
+                "The token endpoint answered with {status}"
+            )));
+        }
+        let tokens = resp
+            .error_for_status()
+            .into_diagnostic()
+            .map_err(AuthError::ProviderUnavailable)?
+            .json::<TokenResponse>()
+            .await
+            .into_diagnostic()
+            .map_err(AuthError::InvalidProviderResponse)?;
+
+        let header = jsonwebtoken::decode_header(&tokens.id_token)
+            .into_diagnostic()
+            .map_err(AuthError::InvalidProviderResponse)?;
+        let jwks = client
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .into_diagnostic()
+            .map_err(AuthError::ProviderUnavailable)?
+            .json::<JwkSet>()
+            .await
+            .into_diagnostic()
+            .map_err(AuthError::InvalidProviderResponse)?;
+        let jwk = match &header.kid {
+            Some(kid) => jwks.find(kid),
+            None => jwks.keys.first(),
+        }
+        .ok_or_else(|| {
+            AuthError::InvalidProviderResponse(miette!(
+                "No key in the provider's JWKS matches the ID token"
+            ))
+        })?;
+        let key = DecodingKey::from_jwk(jwk)
+            .into_diagnostic()
+            .map_err(AuthError::InvalidProviderResponse)?;
+
+        // The algorithm comes from the token, but a key of the wrong type for
+        // it fails to verify, so it can't be swapped for a weaker one
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_issuer(&[&metadata.issuer]);
+
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(&tokens.id_token, &key, &validation)
+            .into_diagnostic()
+            .map_err(AuthError::InvalidProviderResponse)?
+            .claims;
+
+        // An ID token minted for some other login attempt, so possibly replayed
+        if claims.nonce.as_deref() != Some(pending.nonce.as_str()) {
+            return Err(AuthError::StateMismatch);
+        }
+
+        Ok(OidcIdentity {
+            issuer: claims.iss,
+            subject: claims.sub,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        extract::State,
+        http::StatusCode,
+        response::{IntoResponse, Response},
+        routing::{get, post},
+        Form, Json, Router,
+    };
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    const CLIENT_ID: &str = "up-guardian";
+    const REDIRECT_URI: &str = "http://localhost:3001/login/oidc/callback";
+    const CODE: &str = "the-code";
+    const SIGNING_SECRET: &[u8] = b"a signing secret for the mock provider";
+
+    /// Just enough of an OpenID provider to log in against. Claims in
+    /// `overrides` replace the ones it would normally put in the ID token
+    struct MockProvider {
+        issuer: String,
+        advertised_issuer: String,
+        overrides: Value,
+        /// What the authorization request asked for, as if the user had been
+        /// sent to the provider and logged in there
+        code_challenge: Mutex<Option<String>>,
+        nonce: Mutex<Option<String>>,
+    }
+
+    async fn discovery(State(provider): State<Arc<MockProvider>>) -> Json<Value> {
+        Json(json!({
+            "issuer": provider.advertised_issuer,
+            "authorization_endpoint": format!("{}/authorize", provider.issuer),
+            "token_endpoint": format!("{}/token", provider.issuer),
+            "jwks_uri": format!("{}/jwks", provider.issuer),
+        }))
+    }
+
+    async fn jwks() -> Json<Value> {
+        Json(json!({
+            "keys": [{
+                "kty": "oct",
+                "kid": "test",
+                "alg": "HS256",
+                "k": URL_SAFE_NO_PAD.encode(SIGNING_SECRET),
+            }]
+        }))
+    }
+
+    async fn token(
+        State(provider): State<Arc<MockProvider>>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Response {
+        let challenge = form
+            .get("code_verifier")
+            .map(|verifier| URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())));
+        let expected_challenge = provider.code_challenge.lock().unwrap().clone();
+        if form.get("code").map(String::as_str) != Some(CODE)
+            || form.get("client_id").map(String::as_str) != Some(CLIENT_ID)
+            || challenge.is_none()
+            || challenge != expected_challenge
+        {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "invalid_grant" })),
+            )
+                .into_response();
+        }
+
+        let mut claims = json!({
+            "iss": provider.issuer,
+            "sub": "user-1",
+            "aud": CLIENT_ID,
+            "exp": chrono::Utc::now().timestamp() + 300,
+            "nonce": provider.nonce.lock().unwrap().clone(),
+        });
+        for (key, value) in provider.overrides.as_object().unwrap() {
+            claims[key] = value.clone();
+        }
+
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("test".to_string());
+        let id_token =
+            jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(SIGNING_SECRET))
+                .unwrap();
+
+        Json(json!({ "id_token": id_token, "token_type": "Bearer" })).into_response()
+    }
+
+    async fn start_provider(
+        overrides: Value,
+        advertised_issuer: Option<&str>,
+    ) -> Arc<MockProvider> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let provider = Arc::new(MockProvider {
+            advertised_issuer: advertised_issuer.map_or_else(|| issuer.clone(), str::to_string),
+            issuer,
+            overrides,
+            code_challenge: Mutex::new(None),
+            nonce: Mutex::new(None),
+        });
+
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/jwks", get(jwks))
+            .route("/token", post(token))
+            .with_state(provider.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        provider
+    }
+
+    fn config(provider: &MockProvider) -> OidcConfig {
+        OidcConfig {
+            issuer_url: provider.issuer.clone(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: None,
+            scopes: "openid".to_string(),
+        }
+    }
+
+    /// Starts a login and has the provider remember what it was asked for
+    async fn authorize(provider: &MockProvider, pending: &PendingLogin) -> Result<(), AuthError> {
+        let url = config(provider)
+            .authorization_url(REDIRECT_URI, pending)
+            .await?;
+        let url = reqwest::Url::parse(&url).unwrap();
+        let params = url.query_pairs().into_owned().collect::<HashMap<_, _>>();
+
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["client_id"], CLIENT_ID);
+        assert_eq!(params["state"], pending.state);
+        assert_eq!(params["code_challenge_method"], "S256");
+        *provider.code_challenge.lock().unwrap() = Some(params["code_challenge"].clone());
+        *provider.nonce.lock().unwrap() = Some(params["nonce"].clone());
+
+        Ok(())
+    }
+
+    async fn log_in(overrides: Value) -> Result<OidcIdentity, AuthError> {
+        let provider = start_provider(overrides, None).await;
+        let pending = PendingLogin::generate();
+        authorize(&provider, &pending).await?;
+
+        config(&provider).finish(REDIRECT_URI, &pending, CODE).await
+    }
+
+    #[tokio::test]
+    async fn logs_in_with_discovery_and_pkce() {
+        let identity = log_in(json!({})).await.unwrap();
+
+        assert_eq!(identity.subject, "user-1");
+        assert!(identity.issuer.starts_with("http://127.0.0.1:"));
+    }
+
+    #[tokio::test]
+    async fn rejects_a_code_exchanged_with_another_verifier() {
+        let provider = start_provider(json!({}), None).await;
+        authorize(&provider, &PendingLogin::generate())
+            .await
+            .unwrap();
+
+        let err = config(&provider)
+            .finish(REDIRECT_URI, &PendingLogin::generate(), CODE)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(err, AuthError::Rejected(_)), "{err}");
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn rejects_an_id_token_for_another_login() {
+        let err = log_in(json!({ "nonce": "someone-elses" }))
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(err, AuthError::StateMismatch), "{err}");
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn rejects_an_id_token_for_another_client() {
+        let err = log_in(json!({ "aud": "another-client" }))
+            .await
+            .err()
+            .unwrap();
+
+        assert!(
+            matches!(err, AuthError::InvalidProviderResponse(_)),
+            "{err}"
+        );
+        assert_eq!(err.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn rejects_an_id_token_from_another_issuer() {
+        let err = log_in(json!({ "iss": "https://attacker.example" }))
+            .await
+            .err()
+            .unwrap();
+
+        assert!(
+            matches!(err, AuthError::InvalidProviderResponse(_)),
+            "{err}"
+        );
+        assert_eq!(err.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_discovery_document_for_another_issuer() {
+        let provider = start_provider(json!({}), Some("https://attacker.example")).await;
+
+        let err = authorize(&provider, &PendingLogin::generate())
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(err, AuthError::Misconfigured(_)), "{err}");
+        assert_eq!(err.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
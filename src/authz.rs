@@ -0,0 +1,230 @@
+use std::str::FromStr;
+
+use miette::IntoDiagnostic;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::errors::AppError;
+
+/// What a member can do in an organization. Each role can do everything the
+/// roles before it can, so they're compared by their order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Can see the organization's sites and their checkins
+    Viewer,
+    /// Can also create and change sites, pages, SLOs and reports
+    Editor,
+    /// Can also manage the organization's members and invitations
+    Owner,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Owner, Role::Editor, Role::Viewer];
+
+    /// How the role is stored in the database and sent in forms
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Role::Viewer => "Viewer",
+            Role::Editor => "Editor",
+            Role::Owner => "Owner",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.as_str() == s)
+            .ok_or_else(|| format!("Unknown role {s}"))
+    }
+}
+
+/// The user's role in the organization, or `None` when they aren't a member
+pub async fn organization_role(
+    db: &PgPool,
+    user_id: Uuid,
+    organization_id: Uuid,
+) -> miette::Result<Option<Role>> {
+    let role = sqlx::query_scalar!(
+        r#"
+    SELECT role
+    FROM OrganizationMemberships
+    WHERE organization_id = $1 AND user_id = $2
+  "#,
+        organization_id,
+        user_id
+    )
+    .fetch_optional(db)
+    .await
+    .into_diagnostic()?;
+
+    role.map(|role| role.parse().map_err(|e: String| miette::miette!(e)))
+        .transpose()
+}
+
+/// The user's role on a site, through their membership of the organization it
+/// belongs to. `None` when they can't see the site or it doesn't exist
+pub async fn site_role(db: &PgPool, user_id: Uuid, site_id: Uuid) -> miette::Result<Option<Role>> {
+    let role = sqlx::query_scalar!(
+        r#"
+    SELECT OrganizationMemberships.role
+    FROM Sites
+    JOIN OrganizationMemberships USING (organization_id)
+    WHERE Sites.site_id = $1 AND OrganizationMemberships.user_id = $2
+  "#,
+        site_id,
+        user_id
+    )
+    .fetch_optional(db)
+    .await
+    .into_diagnostic()?;
+
+    role.map(|role| role.parse().map_err(|e: String| miette::miette!(e)))
+        .transpose()
+}
+
+/// Checks a role looked up with [`organization_role`] or [`site_role`] against
+/// the one an action needs. Someone with no role gets a 404 rather than a 403,
+/// so the ids of other organizations' sites can't be probed for
+pub fn require(role: Option<Role>, required: Role) -> Result<Role, AppError> {
+    match role {
+        Some(role) if role >= required => Ok(role),
+        Some(_) => Err(AppError::Forbidden(format!(
+            "You need the {} role to do that.",
+            required.label()
+        ))),
+        None => Err(AppError::NotFound),
+    }
+}
+
+pub async fn require_organization_role(
+    db: &PgPool,
+    user_id: Uuid,
+    organization_id: Uuid,
+    required: Role,
+) -> Result<Role, AppError> {
+    let role = organization_role(db, user_id, organization_id).await?;
+
+    require(role, required)
+}
+
+pub async fn require_site_role(
+    db: &PgPool,
+    user_id: Uuid,
+    site_id: Uuid,
+    required: Role,
+) -> Result<Role, AppError> {
+    let role = site_role(db, user_id, site_id).await?;
+
+    require(role, required)
+}
+
+/// Every organization the user is a member of, for queries that list things
+/// across all of them
+pub async fn organization_ids(db: &PgPool, user_id: Uuid) -> miette::Result<Vec<Uuid>> {
+    sqlx::query_scalar!(
+        r#"
+    SELECT organization_id
+    FROM OrganizationMemberships
+    WHERE user_id = $1
+  "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .into_diagnostic()
+}
+
+/// Every site the user can see, for queries that list things across all of
+/// them. Filter with `Sites.site_id = ANY($1)`
+pub async fn accessible_site_ids(db: &PgPool, user_id: Uuid) -> miette::Result<Vec<Uuid>> {
+    sqlx::query_scalar!(
+        r#"
+    SELECT Sites.site_id
+    FROM Sites
+    JOIN OrganizationMemberships USING (organization_id)
+    WHERE OrganizationMemberships.user_id = $1
+  "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .into_diagnostic()
+}
+
+/// Gives a user who isn't in any organization one of their own, so they have
+/// somewhere to create sites. Called on every login. The user's row is locked
+/// while checking, so two logins at once can't both create one
+pub async fn ensure_personal_organization(db: &PgPool, user_id: Uuid) -> miette::Result<()> {
+    let mut tx = db.begin().await.into_diagnostic()?;
+
+    sqlx::query!(
+        r#"
+    SELECT user_id
+    FROM Users
+    WHERE user_id = $1
+    FOR UPDATE
+  "#,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .into_diagnostic()?;
+
+    let is_member = sqlx::query_scalar!(
+        r#"
+    SELECT EXISTS (
+      SELECT 1 FROM OrganizationMemberships WHERE user_id = $1
+    ) as "exists!"
+  "#,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .into_diagnostic()?;
+
+    if is_member {
+        return Ok(());
+    }
+
+    let organization_id = sqlx::query_scalar!(
+        r#"
+    INSERT INTO Organizations (name)
+    VALUES ('Personal')
+    RETURNING organization_id
+  "#,
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .into_diagnostic()?;
+
+    sqlx::query!(
+        r#"
+    INSERT INTO OrganizationMemberships (organization_id, user_id, role)
+    VALUES ($1, $2, $3)
+  "#,
+        organization_id,
+        user_id,
+        Role::Owner.as_str()
+    )
+    .execute(&mut *tx)
+    .await
+    .into_diagnostic()?;
+
+    tx.commit().await.into_diagnostic()?;
+
+    Ok(())
+}
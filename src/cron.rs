@@ -4,7 +4,14 @@ use cja::cron::{CronRegistry, Worker};
 
 use crate::{
     app_state::AppState,
-    jobs::{create_checkin::BulkEnqueueCheckins, hello::Hello},
+    jobs::{
+        check_slos::CheckSloBurnRates,
+        create_checkin::BulkEnqueueCheckins,
+        hello::Hello,
+        prune_sessions::PruneSessions,
+        rollup_checkins::{PruneCheckins, RollupCheckins},
+        send_reports::SendMonthlyReports,
+    },
 };
 
 fn cron_registry() -> CronRegistry<AppState> {
@@ -12,6 +19,11 @@ fn cron_registry() -> CronRegistry<AppState> {
 
     registry.register_job(Hello, Duration::from_secs(60));
     registry.register_job(BulkEnqueueCheckins, Duration::from_secs(60));
+    registry.register_job(RollupCheckins, Duration::from_secs(5 * 60));
+    registry.register_job(PruneCheckins, Duration::from_secs(60 * 60));
+    registry.register_job(CheckSloBurnRates, Duration::from_secs(5 * 60));
+    registry.register_job(SendMonthlyReports, Duration::from_secs(60 * 60));
+    registry.register_job(PruneSessions, Duration::from_secs(60 * 60));
 
     registry
 }
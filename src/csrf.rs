@@ -0,0 +1,327 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use cja::app_state::AppState as _;
+use maud::html;
+use miette::IntoDiagnostic;
+use serde::Deserialize;
+use tower_cookies::{cookie::SameSite, Cookie, Cookies};
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    errors::{self, AppError},
+    sessions::SESSION_COOKIE,
+    tokens::tokens_match,
+};
+
+/// The hidden form field the token is sent back in
+pub const CSRF_FIELD: &str = "csrf_token";
+
+/// For requests that don't come from a form
+const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Holds the token for visitors who aren't logged in yet
+const PRE_SESSION_COOKIE: &str = "csrf_token";
+
+/// Forms and pages are buffered whole to read or add the token, this is far
+/// more than any of them need
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct CsrfForm {
+    csrf_token: Option<String>,
+}
+
+/// The token belonging to the session in the request's cookie, if it's one
+/// that still exists
+async fn session_token(state: &AppState, cookies: &Cookies) -> miette::Result<Option<String>> {
+    let session_id = cookies
+        .private(state.cookie_key())
+        .get(SESSION_COOKIE)
+        .and_then(|cookie| Uuid::parse_str(cookie.value()).ok());
+    let Some(session_id) = session_id else {
+        return Ok(None);
+    };
+
+    sqlx::query_scalar!(
+        r#"
+    SELECT csrf_token
+    FROM Sessions
+    WHERE session_id = $1
+  "#,
+        session_id
+    )
+    .fetch_optional(state.db())
+    .await
+    .into_diagnostic()
+}
+
+/// The token for a visitor without a session, from a private cookie that's
+/// handed out on their first request. Without it another site could post
+/// its own credentials to the login form and log the visitor into the
+/// attacker's account
+fn pre_session_token(state: &AppState, cookies: &Cookies) -> String {
+    let private = cookies.private(state.cookie_key());
+    if let Some(cookie) = private.get(PRE_SESSION_COOKIE) {
+        return cookie.value().to_string();
+    }
+
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    private.add(
+        Cookie::build((PRE_SESSION_COOKIE, token.clone()))
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .build(),
+    );
+
+    token
+}
+
+/// Rejects state-changing requests that don't carry the CSRF token, and adds
+/// the token to every POST form on the pages it serves. Logged in sessions
+/// use the token stored with the session, everyone else gets one in a cookie
+pub(crate) async fn protect_forms(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(cookies) = request.extensions().get::<Cookies>().cloned() else {
+        return next.run(request).await;
+    };
+    let token = match session_token(&state, &cookies).await {
+        Ok(Some(token)) => token,
+        Ok(None) => pre_session_token(&state, &cookies),
+        Err(err) => {
+            return errors::render_error_page(
+                state,
+                Some(cookies),
+                AppError::from(err).into_response(),
+            )
+            .await
+        }
+    };
+
+    let request = if is_safe(request.method()) {
+        request
+    } else {
+        let (parts, body) = request.into_parts();
+        let Ok(bytes) = to_bytes(body, MAX_BODY_BYTES).await else {
+            return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+        };
+
+        let submitted = parts
+            .headers
+            .get(CSRF_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned)
+            .or_else(|| {
+                serde_html_form::from_bytes::<CsrfForm>(&bytes)
+                    .ok()
+                    .and_then(|form| form.csrf_token)
+            });
+
+        if !submitted.is_some_and(|submitted| tokens_match(&submitted, &token)) {
+            tracing::warn!(
+                method = %parts.method,
+                uri = %parts.uri,
+                "Rejected a request without a valid CSRF token"
+            );
+            let rejection = AppError::Forbidden(
+                "This form has expired. Go back, reload the page and try again.".to_string(),
+            )
+            .into_response();
+            let resp = errors::render_error_page(state, Some(cookies), rejection).await;
+            return stamp_forms(resp, &token).await;
+        }
+
+        Request::from_parts(parts, Body::from(bytes))
+    };
+
+    let resp = next.run(request).await;
+    stamp_forms(resp, &token).await
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+async fn stamp_forms(resp: Response, token: &str) -> Response {
+    let is_html = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/html"));
+    if !is_html {
+        return resp;
+    }
+
+    let (mut parts, body) = resp.into_parts();
+    let bytes = match to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            tracing::error!(?err, "Couldn't read the page to add CSRF tokens to it");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let Ok(page) = std::str::from_utf8(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    let stamped = insert_token_fields(page, token);
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    Response::from_parts(parts, Body::from(stamped))
+}
+
+/// Adds a hidden token field right inside every `<form method="post">`.
+/// maud escapes `>` in attribute values, so the first `>` after `<form` is
+/// always the end of the tag
+fn insert_token_fields(page: &str, token: &str) -> String {
+    let field = html! {
+      input type="hidden" name=(CSRF_FIELD) value=(token);
+    }
+    .into_string();
+
+    // Lowercasing ASCII keeps every byte where it was, so offsets found in
+    // one are good for the other
+    let lowercase = page.to_ascii_lowercase();
+
+    let mut stamped = String::with_capacity(page.len());
+    let mut copied = 0;
+    let mut searched = 0;
+    while let Some(found) = lowercase[searched..].find("<form") {
+        let start = searched + found;
+        let Some(len) = lowercase[start..].find('>') else {
+            break;
+        };
+        let end = start + len + 1;
+
+        if lowercase[start..end].contains(r#"method="post""#) {
+            stamped.push_str(&page[copied..end]);
+            stamped.push_str(&field);
+            copied = end;
+        }
+        searched = end;
+    }
+    stamped.push_str(&page[copied..]);
+
+    stamped
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::{
+        header::{COOKIE, SET_COOKIE},
+        redirect::Policy,
+        StatusCode,
+    };
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::testing::{serve, test_state};
+
+    const FIELD: &str = r#"<input type="hidden" name="csrf_token" value="abc">"#;
+
+    #[test]
+    fn stamps_every_post_form() {
+        let page = r#"<form method="post" action="/a"><button>A</button></form><p>Between</p><form method="post" action="/b"></form>"#;
+
+        assert_eq!(
+            insert_token_fields(page, "abc"),
+            format!(
+                r#"<form method="post" action="/a">{FIELD}<button>A</button></form><p>Between</p><form method="post" action="/b">{FIELD}</form>"#
+            )
+        );
+    }
+
+    #[test]
+    fn leaves_get_forms_alone() {
+        let page = r#"<form method="get" action="/search"><input name="q"></form><form action="/filter"></form>"#;
+
+        assert_eq!(insert_token_fields(page, "abc"), page);
+    }
+
+    #[test]
+    fn stamps_uppercase_forms() {
+        let page = r#"<FORM METHOD="POST" ACTION="/a"></FORM>"#;
+
+        assert_eq!(
+            insert_token_fields(page, "abc"),
+            format!(r#"<FORM METHOD="POST" ACTION="/a">{FIELD}</FORM>"#)
+        );
+    }
+
+    #[test]
+    fn leaves_pages_without_forms_alone() {
+        let page = "<h1>Nothing to post</h1><p>A <formal> tag that isn't closed";
+
+        assert_eq!(insert_token_fields(page, "abc"), page);
+    }
+
+    fn token_field_value(page: &str) -> &str {
+        let prefix = r#"name="csrf_token" value=""#;
+        let start = page.find(prefix).unwrap() + prefix.len();
+        let len = page[start..].find('"').unwrap();
+
+        &page[start..start + len]
+    }
+
+    #[sqlx::test]
+    async fn logins_need_the_token_from_the_login_page(pool: PgPool) {
+        let base_url = serve(test_state(pool)).await;
+        let client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .build()
+            .unwrap();
+        let credentials = [("username", "alice"), ("password", "wrong")];
+
+        // As if posted from another site, which has neither
+        let resp = client
+            .post(format!("{base_url}/login"))
+            .form(&credentials)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let page = resp.text().await.unwrap();
+        assert!(page.contains("This form has expired"));
+
+        let resp = client
+            .get(format!("{base_url}/login"))
+            .send()
+            .await
+            .unwrap();
+        let cookie = resp
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find(|value| value.starts_with(PRE_SESSION_COOKIE))
+            .and_then(|value| value.split(';').next())
+            .unwrap()
+            .to_string();
+        let page = resp.text().await.unwrap();
+        let token = token_field_value(&page).to_string();
+
+        let resp = client
+            .post(format!("{base_url}/login"))
+            .header(COOKIE, &cookie)
+            .form(&[credentials[0], credentials[1], ("csrf_token", &token)])
+            .send()
+            .await
+            .unwrap();
+        // Through to the login itself, which turns down the password
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let page = resp.text().await.unwrap();
+        assert_eq!(token_field_value(&page), token);
+    }
+}
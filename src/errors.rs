@@ -0,0 +1,129 @@
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use cja::server::session::DBSession;
+use maud::html;
+use tower_cookies::Cookies;
+
+use crate::{app_state::AppState, templates::IntoTemplate};
+
+/// What a route can fail with. Each variant becomes a full page through
+/// [`render_error_pages`]
+#[derive(Debug)]
+pub enum AppError {
+    /// The thing doesn't exist, or the user isn't allowed to know it does
+    NotFound,
+    /// The user can see the thing but not do this to it
+    Forbidden(String),
+    /// The request couldn't be read at all, like a malformed query string
+    BadRequest(String),
+    /// The thing existed once but can't be used any more, like an accepted
+    /// invitation
+    Gone(String),
+    /// The request was understood but what it asks for isn't allowed, like a
+    /// form with a bad value in it
+    Invalid(String),
+    /// Anything else. The details go to the logs and Sentry, the user only
+    /// gets told something went wrong
+    Internal(miette::Report),
+}
+
+/// Left on an error response so [`render_error_pages`] knows to swap its plain
+/// text body for a page
+#[derive(Debug, Clone)]
+struct ErrorPage {
+    message: String,
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            AppError::NotFound => (
+                StatusCode::NOT_FOUND,
+                "We couldn't find what you were looking for.".to_string(),
+            ),
+            AppError::Forbidden(message) => (StatusCode::FORBIDDEN, message),
+            AppError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            AppError::Gone(message) => (StatusCode::GONE, message),
+            AppError::Invalid(message) => (StatusCode::UNPROCESSABLE_ENTITY, message),
+            AppError::Internal(err) => {
+                tracing::error!(?err, "Request failed");
+                let source: &(dyn std::error::Error + 'static) = err.as_ref();
+                sentry::capture_error(source);
+
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Something went wrong on our end.".to_string(),
+                )
+            }
+        };
+
+        let mut resp = (status, message.clone()).into_response();
+        resp.extensions_mut().insert(ErrorPage { message });
+        resp
+    }
+}
+
+impl From<miette::Report> for AppError {
+    fn from(err: miette::Report) -> Self {
+        AppError::Internal(err)
+    }
+}
+
+/// Renders [`AppError`] responses inside the layout. Done as a middleware
+/// since the layout needs the app state and session, which `IntoResponse`
+/// doesn't get
+pub(crate) async fn render_error_pages(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let cookies = request.extensions().get::<Cookies>().cloned();
+
+    let resp = next.run(request).await;
+    render_error_page(state, cookies, resp).await
+}
+
+/// Does the rendering for [`render_error_pages`], for middleware further out
+/// that turns requests away itself. Anything that isn't an [`AppError`] is
+/// passed back untouched
+pub(crate) async fn render_error_page(
+    state: AppState,
+    cookies: Option<Cookies>,
+    resp: Response,
+) -> Response {
+    let Some(ErrorPage { message }) = resp.extensions().get::<ErrorPage>().cloned() else {
+        return resp;
+    };
+    let status = resp.status();
+
+    // Only look up the session once there's a page to render
+    let mut parts = Request::new(()).into_parts().0;
+    if let Some(cookies) = cookies {
+        parts.extensions.insert(cookies);
+    }
+    let session = DBSession::from_request_parts(&mut parts, &state).await.ok();
+
+    let page = html! {
+      h1 { (status.as_u16()) " " (status.canonical_reason().unwrap_or("Error")) }
+
+      p { (message) }
+
+      a href="/" { "Back to the dashboard" }
+    }
+    .into_template(state, session)
+    .await;
+
+    match page {
+        Ok(page) => (status, page).into_response(),
+        // The layout loads the sidebar from the database, which might be
+        // what failed in the first place
+        Err(err) => {
+            tracing::error!(?err, "Couldn't render the error page");
+            resp
+        }
+    }
+}
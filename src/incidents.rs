@@ -0,0 +1,124 @@
+use std::ops::Range;
+
+use chrono::{DateTime, Utc};
+use miette::IntoDiagnostic;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A run of consecutive non successful checkins for a single page
+#[derive(Debug, Clone)]
+pub struct Incident {
+    pub site_id: Uuid,
+    pub page_id: Uuid,
+    pub page_name: String,
+    pub started_at: DateTime<Utc>,
+    /// The first successful checkin after the incident, or `None` if the page
+    /// is still down
+    pub ended_at: Option<DateTime<Utc>>,
+    pub failed_checkins: i64,
+}
+
+impl Incident {
+    pub fn duration(&self) -> chrono::Duration {
+        self.ended_at.unwrap_or_else(Utc::now) - self.started_at
+    }
+}
+
+/// Finds the incidents for the pages of a site that started within `range`.
+///
+/// This only looks at raw checkins, so incidents older than the checkin
+/// retention won't be found
+pub async fn incidents_for_range(
+    db: &PgPool,
+    site_id: Uuid,
+    range: Range<DateTime<Utc>>,
+) -> miette::Result<Vec<Incident>> {
+    sqlx::query_as!(
+        Incident,
+        r#"
+    WITH Numbered AS (
+      SELECT
+        Checkins.page_id,
+        Checkins.outcome,
+        Checkins.created_at,
+        row_number() OVER (PARTITION BY Checkins.page_id ORDER BY Checkins.created_at)
+          - row_number() OVER (PARTITION BY Checkins.page_id, Checkins.outcome = 'success' ORDER BY Checkins.created_at) as island
+      FROM Checkins
+      JOIN Pages USING (page_id)
+      WHERE Pages.site_id = $1
+        AND Checkins.created_at >= $2
+        AND Checkins.created_at < $3
+    ), Islands AS (
+      SELECT
+        page_id,
+        min(created_at) as started_at,
+        max(created_at) as last_failed_at,
+        count(*) as failed_checkins
+      FROM Numbered
+      WHERE outcome <> 'success'
+      GROUP BY page_id, island
+    )
+    SELECT
+      Pages.site_id,
+      Islands.page_id as "page_id!",
+      Pages.name as "page_name!",
+      Islands.started_at as "started_at!",
+      (
+        SELECT min(Checkins.created_at)
+        FROM Checkins
+        WHERE Checkins.page_id = Islands.page_id
+          AND Checkins.created_at > Islands.last_failed_at
+          AND Checkins.outcome = 'success'
+      ) as ended_at,
+      Islands.failed_checkins as "failed_checkins!"
+    FROM Islands
+    JOIN Pages USING (page_id)
+    ORDER BY Islands.started_at ASC
+  "#,
+        site_id,
+        range.start,
+        range.end
+    )
+    .fetch_all(db)
+    .await
+    .into_diagnostic()
+}
+
+/// Finds the pages across all of the given sites whose latest checkin wasn't
+/// successful. Each incident starts at the first failure since the page was
+/// last up
+pub async fn open_incidents_for_sites(
+    db: &PgPool,
+    site_ids: &[Uuid],
+) -> miette::Result<Vec<Incident>> {
+    sqlx::query_as!(
+        Incident,
+        r#"
+    SELECT
+      Pages.site_id,
+      Pages.page_id,
+      Pages.name as page_name,
+      min(Checkins.created_at) as "started_at!",
+      NULL::timestamptz as ended_at,
+      count(*) as "failed_checkins!"
+    FROM Pages
+    LEFT JOIN LATERAL (
+      SELECT LastSuccess.created_at
+      FROM Checkins LastSuccess
+      WHERE LastSuccess.page_id = Pages.page_id
+        AND LastSuccess.outcome = 'success'
+      ORDER BY LastSuccess.created_at DESC
+      LIMIT 1
+    ) LastSuccess ON true
+    JOIN Checkins ON Checkins.page_id = Pages.page_id
+      AND (LastSuccess.created_at IS NULL OR Checkins.created_at > LastSuccess.created_at)
+    WHERE Pages.site_id = ANY($1)
+    GROUP BY Pages.site_id, Pages.page_id, Pages.name
+    ORDER BY min(Checkins.created_at) ASC
+  "#,
+        site_ids
+    )
+    .fetch_all(db)
+    .await
+    .into_diagnostic()
+}
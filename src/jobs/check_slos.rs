@@ -0,0 +1,144 @@
+use cja::{app_state::AppState as _, jobs::Job};
+use maud::html;
+use miette::IntoDiagnostic;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    slo::{Slo, SloAlert, SloReport},
+};
+
+/// Opens an alert for every SLO that is burning its error budget too fast, and
+/// resolves the open alerts for any that have recovered. Both are emailed to
+/// the site's report subscribers and the organization members we have an
+/// address for, when SMTP is configured
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CheckSloBurnRates;
+
+#[async_trait::async_trait]
+impl Job<AppState> for CheckSloBurnRates {
+    const NAME: &'static str = "CheckSloBurnRates";
+
+    async fn run(&self, app_state: AppState) -> miette::Result<()> {
+        let db = app_state.db();
+
+        for slo in Slo::all(db).await? {
+            let report = slo.report(db).await?;
+            let open_alert = SloAlert::open_for_slo(db, report.slo.slo_id).await?;
+
+            match (report.recent_burn_rate(), open_alert) {
+                (Some(burn_rate), None) if report.is_burning_fast() => {
+                    tracing::warn!(
+                        slo_id = %report.slo.slo_id,
+                        slo.name = %report.slo.name,
+                        burn_rate,
+                        "SLO is burning its error budget too fast"
+                    );
+
+                    sqlx::query!(
+                        r#"
+                    INSERT INTO SloAlerts (slo_id, burn_rate)
+                    VALUES ($1, $2)
+                  "#,
+                        report.slo.slo_id,
+                        burn_rate
+                    )
+                    .execute(db)
+                    .await
+                    .into_diagnostic()?;
+
+                    let subject = format!("SLO alert: {} is burning too fast", report.slo.name);
+                    let body = html! {
+                      p {
+                        "The " (report.slo.name) " SLO is using its error budget "
+                        (format!("{burn_rate:.1}")) "x faster than it can afford to."
+                      }
+                    };
+                    email_alert(&app_state, &report, &subject, body).await?;
+                }
+                (_, Some(alert)) if !report.is_burning_fast() => {
+                    tracing::info!(
+                        slo_id = %report.slo.slo_id,
+                        slo.name = %report.slo.name,
+                        "SLO burn rate has recovered"
+                    );
+
+                    sqlx::query!(
+                        r#"
+                    UPDATE SloAlerts
+                    SET resolved_at = now()
+                    WHERE slo_alert_id = $1
+                  "#,
+                        alert.slo_alert_id
+                    )
+                    .execute(db)
+                    .await
+                    .into_diagnostic()?;
+
+                    let subject = format!("SLO recovered: {}", report.slo.name);
+                    let body = html! {
+                      p { "The " (report.slo.name) " SLO is no longer burning its error budget too fast." }
+                    };
+                    email_alert(&app_state, &report, &subject, body).await?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends an alert to everyone watching the SLO's site. The alert is already
+/// recorded by now, so a failed send is logged rather than retried
+async fn email_alert(
+    app_state: &AppState,
+    report: &SloReport,
+    subject: &str,
+    body: maud::Markup,
+) -> miette::Result<()> {
+    let Some(mailer) = app_state.mailer.as_ref() else {
+        tracing::debug!("SMTP_URL is not set, skipping SLO alert emails");
+        return Ok(());
+    };
+
+    let recipients = sqlx::query_scalar!(
+        r#"
+    SELECT email as "email!"
+    FROM ReportSubscriptions
+    WHERE site_id = $1
+    UNION
+    SELECT OrganizationMemberships.email as "email!"
+    FROM OrganizationMemberships
+    JOIN Sites USING (organization_id)
+    WHERE Sites.site_id = $1
+      AND OrganizationMemberships.email IS NOT NULL
+  "#,
+        report.slo.site_id
+    )
+    .fetch_all(app_state.db())
+    .await
+    .into_diagnostic()?;
+
+    let html = html! {
+      (body)
+      p {
+        a href=(format!("{}/my/sites/{}", app_state.base_url, report.slo.site_id)) { "View the site" }
+      }
+    }
+    .into_string();
+
+    for email in recipients {
+        let sent = mailer.send_html(&email, subject, html.clone(), None).await;
+
+        if let Err(err) = sent {
+            tracing::error!(
+                slo_id = %report.slo.slo_id,
+                ?err,
+                "Failed to send SLO alert email"
+            );
+        }
+    }
+
+    Ok(())
+}
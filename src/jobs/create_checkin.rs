@@ -1,12 +1,20 @@
+use std::{collections::HashMap, time::Duration};
 
+use chrono::Utc;
 use cja::{app_state::AppState as _, jobs::Job};
+use hyper::{
+    header::{HeaderName, HeaderValue},
+    HeaderMap,
+};
 use miette::IntoDiagnostic;
 use serde::{Deserialize, Serialize};
 
 use tokio::time::Instant;
+use tracing::Instrument as _;
+use tracing_opentelemetry::OpenTelemetrySpanExt as _;
 use uuid::Uuid;
 
-use crate::app_state::AppState;
+use crate::{app_state::AppState, plans::Plan, probe::probe, validation::check_url};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CreateCheckin {
@@ -20,7 +28,7 @@ impl Job<AppState> for CreateCheckin {
     async fn run(&self, app_state: AppState) -> miette::Result<()> {
         let page = sqlx::query!(
             r#"
-        SELECT Pages.path, Sites.domain
+        SELECT Pages.path, Sites.domain, Sites.propagate_trace_context
         FROM Pages
         JOIN Sites ON Sites.site_id = Pages.site_id
         WHERE page_id = $1
@@ -34,34 +42,120 @@ impl Job<AppState> for CreateCheckin {
         let domain = page.domain;
         let path = page.path;
 
-        let url = format!("https://{domain}{path}");
+        let url = check_url(&domain, &path);
+
+        let span = tracing::info_span!(
+            "checkin",
+            otel.name = "checkin",
+            otel.kind = "client",
+            page.id = %self.page_id,
+            url.full = %url,
+            http.response.status_code = tracing::field::Empty,
+            checkin.outcome = tracing::field::Empty,
+            checkin.duration_ms = tracing::field::Empty,
+            checkin.dns_ms = tracing::field::Empty,
+            checkin.connect_ms = tracing::field::Empty,
+            checkin.tls_ms = tracing::field::Empty,
+            checkin.ttfb_ms = tracing::field::Empty,
+            checkin.download_ms = tracing::field::Empty,
+            error.message = tracing::field::Empty,
+        );
+
+        let mut headers = HeaderMap::new();
+        if page.propagate_trace_context {
+            inject_trace_context(&span, &mut headers);
+        }
+
         let now = Instant::now();
-        let resp = reqwest::get(&url).await;
-
-        let (status, outcome) = match resp {
-            Err(_) => (None, "error"),
-            Ok(resp) => (Some(resp.status()), {
-                if resp.status().is_success() {
-                    "success"
-                } else {
-                    "failure"
-                }
-            }),
+        let resp = probe(&url, &headers).instrument(span.clone()).await;
+
+        let (status, outcome, timings) = match resp {
+            Err(err) => {
+                span.record("error.message", tracing::field::display(&err));
+
+                (None, "error", None)
+            }
+            Ok(resp) => (
+                Some(resp.status),
+                {
+                    if resp.status.is_success() {
+                        "success"
+                    } else {
+                        "failure"
+                    }
+                },
+                Some(resp.timings),
+            ),
         };
-        let duration = now.elapsed();
-        let duration: i64 = duration.as_nanos().try_into().unwrap();
+        let elapsed = now.elapsed();
+        let duration: i64 = elapsed.as_nanos().try_into().unwrap();
 
         let status: Option<i32> = status.map(|s| s.as_u16().into());
 
+        span.record("http.response.status_code", status);
+        span.record("checkin.outcome", outcome);
+        span.record("checkin.duration_ms", elapsed.as_secs_f64() * 1000.0);
+
+        metrics::counter!(
+            "upguardian_checkins_total",
+            "page_id" => self.page_id.to_string(),
+            "outcome" => outcome,
+        )
+        .increment(1);
+        metrics::histogram!(
+            "upguardian_checkin_duration_seconds",
+            "page_id" => self.page_id.to_string(),
+            "outcome" => outcome,
+        )
+        .record(elapsed.as_secs_f64());
+
+        if let Some(timings) = timings {
+            for (phase, duration) in [
+                ("dns", timings.dns),
+                ("connect", timings.connect),
+                ("tls", timings.tls),
+                ("ttfb", timings.ttfb),
+                ("download", timings.download),
+            ] {
+                span.record(
+                    format!("checkin.{phase}_ms").as_str(),
+                    duration.as_secs_f64() * 1000.0,
+                );
+                metrics::histogram!(
+                    "upguardian_checkin_phase_duration_seconds",
+                    "page_id" => self.page_id.to_string(),
+                    "phase" => phase,
+                )
+                .record(duration.as_secs_f64());
+            }
+        }
+
+        let nanos = |phase: Duration| -> i64 { phase.as_nanos().try_into().unwrap() };
+
         sqlx::query!(
             r#"
-        INSERT INTO Checkins (page_id, status_code, outcome, duration_nanos)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO Checkins (
+          page_id,
+          status_code,
+          outcome,
+          duration_nanos,
+          dns_nanos,
+          connect_nanos,
+          tls_nanos,
+          ttfb_nanos,
+          download_nanos
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
       "#,
             self.page_id,
             status,
             outcome,
-            duration
+            duration,
+            timings.map(|t| nanos(t.dns)),
+            timings.map(|t| nanos(t.connect)),
+            timings.map(|t| nanos(t.tls)),
+            timings.map(|t| nanos(t.ttfb)),
+            timings.map(|t| nanos(t.download))
         )
         .execute(app_state.db())
         .await
@@ -71,6 +165,31 @@ impl Job<AppState> for CreateCheckin {
     }
 }
 
+/// Adds a W3C `traceparent` header for `span`, so the monitored service can
+/// link its own trace back to the checkin
+fn inject_trace_context(span: &tracing::Span, headers: &mut HeaderMap) {
+    let context = span.context();
+    let mut carrier = HashMap::<String, String>::new();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut carrier)
+    });
+
+    for (name, value) in carrier {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+/// This job runs every minute and checkins take a moment to land, so a page
+/// is due a little before its full interval is up. Otherwise a page on a five
+/// minute interval would usually wait six
+fn schedule_slack() -> chrono::Duration {
+    chrono::Duration::seconds(30)
+}
+
+/// Enqueues a checkin for every page that's due one under its organization's
+/// plan
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct BulkEnqueueCheckins;
 
@@ -81,15 +200,42 @@ impl Job<AppState> for BulkEnqueueCheckins {
     async fn run(&self, app_state: AppState) -> miette::Result<()> {
         let pages = sqlx::query!(
             r#"
-        SELECT page_id
+        SELECT
+          Pages.page_id,
+          EXISTS (
+            SELECT 1
+            FROM OrganizationMemberships
+            JOIN Users USING (user_id)
+            WHERE OrganizationMemberships.organization_id = Sites.organization_id
+              AND OrganizationMemberships.role = 'owner'
+              AND Users.is_active_sponsor
+          ) as "has_sponsoring_owner!",
+          (
+            SELECT max(created_at)
+            FROM Checkins
+            WHERE Checkins.page_id = Pages.page_id
+          ) as last_checked_at
         FROM Pages
+        JOIN Sites ON Sites.site_id = Pages.site_id
       "#
         )
         .fetch_all(app_state.db())
         .await
         .into_diagnostic()?;
 
+        let now = Utc::now();
+
         for page in pages {
+            let interval = Plan::new(&app_state.auth, page.has_sponsoring_owner)
+                .limits()
+                .check_interval;
+            let checked_recently = page
+                .last_checked_at
+                .is_some_and(|last| now < last + interval - schedule_slack());
+            if checked_recently {
+                continue;
+            }
+
             let job = CreateCheckin {
                 page_id: page.page_id,
             };
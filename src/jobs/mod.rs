@@ -1,9 +1,18 @@
+pub(crate) mod check_slos;
 pub(crate) mod create_checkin;
 pub(crate) mod hello;
+pub(crate) mod prune_sessions;
+pub(crate) mod rollup_checkins;
+pub(crate) mod send_reports;
 
 cja::impl_job_registry!(
     crate::AppState,
     hello::Hello,
     create_checkin::CreateCheckin,
-    create_checkin::BulkEnqueueCheckins
+    create_checkin::BulkEnqueueCheckins,
+    rollup_checkins::RollupCheckins,
+    rollup_checkins::PruneCheckins,
+    check_slos::CheckSloBurnRates,
+    send_reports::SendMonthlyReports,
+    prune_sessions::PruneSessions
 );
@@ -0,0 +1,20 @@
+use cja::{app_state::AppState as _, jobs::Job};
+use serde::{Deserialize, Serialize};
+
+use crate::{app_state::AppState, sessions::prune_sessions};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PruneSessions;
+
+#[async_trait::async_trait]
+impl Job<AppState> for PruneSessions {
+    const NAME: &'static str = "PruneSessions";
+
+    async fn run(&self, app_state: AppState) -> miette::Result<()> {
+        let rows = prune_sessions(app_state.db(), app_state.session_timeouts).await?;
+
+        tracing::info!(rows, "Pruned expired sessions");
+
+        Ok(())
+    }
+}
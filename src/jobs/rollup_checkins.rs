@@ -0,0 +1,41 @@
+use cja::{app_state::AppState as _, jobs::Job};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    stats::{prune_checkins, rollup_checkins, Resolution},
+};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RollupCheckins;
+
+#[async_trait::async_trait]
+impl Job<AppState> for RollupCheckins {
+    const NAME: &'static str = "RollupCheckins";
+
+    async fn run(&self, app_state: AppState) -> miette::Result<()> {
+        for resolution in [Resolution::Hour, Resolution::Day] {
+            let rows = rollup_checkins(app_state.db(), resolution).await?;
+
+            tracing::info!(resolution = resolution.as_str(), rows, "Rolled up checkins");
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PruneCheckins;
+
+#[async_trait::async_trait]
+impl Job<AppState> for PruneCheckins {
+    const NAME: &'static str = "PruneCheckins";
+
+    async fn run(&self, app_state: AppState) -> miette::Result<()> {
+        let rows = prune_checkins(app_state.db(), app_state.checkin_retention).await?;
+
+        tracing::info!(rows, "Pruned checkins past retention");
+
+        Ok(())
+    }
+}
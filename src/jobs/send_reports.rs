@@ -0,0 +1,127 @@
+use chrono::Utc;
+use cja::{app_state::AppState as _, jobs::Job};
+use miette::IntoDiagnostic;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    authz::Role,
+    mailer::HtmlAttachment,
+    reports::{previous_month, SiteReport},
+    routes::current_user::sites::Site,
+};
+
+/// Emails last month's report to every subscription that hasn't been sent it
+/// yet. This runs hourly, so reports go out early on the 1st and anything that
+/// failed gets retried on the next run
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SendMonthlyReports;
+
+#[async_trait::async_trait]
+impl Job<AppState> for SendMonthlyReports {
+    const NAME: &'static str = "SendMonthlyReports";
+
+    async fn run(&self, app_state: AppState) -> miette::Result<()> {
+        let Some(mailer) = app_state.mailer.as_ref() else {
+            tracing::debug!("SMTP_URL is not set, skipping monthly reports");
+            return Ok(());
+        };
+
+        let range = previous_month(Utc::now());
+        let period_start = range.start.date_naive();
+
+        let sites = sqlx::query!(
+            r#"
+        SELECT *
+        FROM Sites
+        WHERE EXISTS (
+          SELECT 1
+          FROM ReportSubscriptions
+          LEFT JOIN ReportDeliveries
+            ON ReportDeliveries.report_subscription_id = ReportSubscriptions.report_subscription_id
+            AND ReportDeliveries.period_start = $1
+          WHERE ReportSubscriptions.site_id = Sites.site_id
+            AND ReportDeliveries.report_subscription_id IS NULL
+        )
+      "#,
+            period_start
+        )
+        .fetch_all(app_state.db())
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        // Nobody is logged in here, and reports only need to read the site
+        .map(|site| Site {
+            site_id: site.site_id,
+            organization_id: site.organization_id,
+            name: site.name,
+            domain: site.domain,
+            description: site.description,
+            propagate_trace_context: site.propagate_trace_context,
+            role: Role::Viewer,
+        });
+
+        for site in sites {
+            let subscriptions = sqlx::query!(
+                r#"
+          SELECT ReportSubscriptions.report_subscription_id, ReportSubscriptions.email
+          FROM ReportSubscriptions
+          LEFT JOIN ReportDeliveries
+            ON ReportDeliveries.report_subscription_id = ReportSubscriptions.report_subscription_id
+            AND ReportDeliveries.period_start = $2
+          WHERE ReportSubscriptions.site_id = $1
+            AND ReportDeliveries.report_subscription_id IS NULL
+        "#,
+                site.site_id,
+                period_start
+            )
+            .fetch_all(app_state.db())
+            .await
+            .into_diagnostic()?;
+
+            let report = SiteReport::generate(&app_state, site, range.clone()).await?;
+            let subject = report.title();
+            let summary = report.email_summary();
+            let html = report.standalone_html(&app_state.font_awesome_kit_id);
+
+            for subscription in subscriptions {
+                let attachment = HtmlAttachment {
+                    filename: report.filename(),
+                    html: html.clone(),
+                };
+                let sent = mailer
+                    .send_html(
+                        &subscription.email,
+                        &subject,
+                        summary.clone(),
+                        Some(attachment),
+                    )
+                    .await;
+
+                if let Err(err) = sent {
+                    tracing::error!(
+                        report_subscription_id = %subscription.report_subscription_id,
+                        site_id = %report.site.site_id,
+                        ?err,
+                        "Failed to send monthly report"
+                    );
+                    continue;
+                }
+
+                sqlx::query!(
+                    r#"
+            INSERT INTO ReportDeliveries (report_subscription_id, period_start)
+            VALUES ($1, $2)
+          "#,
+                    subscription.report_subscription_id,
+                    period_start
+                )
+                .execute(app_state.db())
+                .await
+                .into_diagnostic()?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use cja::app_state::AppState as _;
+use miette::IntoDiagnostic;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgListener;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::app_state::AppState;
+
+/// The Postgres channel the `checkin_created` trigger notifies on
+const CHECKIN_CREATED_CHANNEL: &str = "checkin_created";
+
+/// How many events a slow SSE client can fall behind before it starts missing
+/// some
+pub const CHECKIN_EVENTS_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CheckinEvent {
+    pub checkin_id: Uuid,
+    pub page_id: Uuid,
+    pub site_id: Uuid,
+    pub organization_id: Uuid,
+    pub outcome: String,
+    pub status_changed: bool,
+}
+
+pub fn checkin_events() -> broadcast::Sender<CheckinEvent> {
+    broadcast::channel(CHECKIN_EVENTS_CAPACITY).0
+}
+
+/// Listens for new checkins from every instance and re-broadcasts them to the
+/// SSE connections on this one
+pub(crate) async fn listen_for_checkins(app_state: AppState) -> miette::Result<()> {
+    let mut listener = PgListener::connect_with(app_state.db())
+        .await
+        .into_diagnostic()?;
+    listener
+        .listen(CHECKIN_CREATED_CHANNEL)
+        .await
+        .into_diagnostic()?;
+
+    loop {
+        // The listener reconnects and re-subscribes on the next `recv` after
+        // the connection drops, so errors here are only worth a log line
+        let notification = match listener.recv().await {
+            Ok(notification) => notification,
+            Err(err) => {
+                tracing::warn!(?err, "Lost connection while listening for checkins");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        let event = match serde_json::from_str::<CheckinEvent>(notification.payload()) {
+            Ok(event) => event,
+            Err(err) => {
+                tracing::error!(
+                    ?err,
+                    payload = notification.payload(),
+                    "Invalid checkin event"
+                );
+                continue;
+            }
+        };
+
+        // Sending only fails when nobody is subscribed, which is fine
+        let _ = app_state.checkin_events.send(event);
+    }
+}
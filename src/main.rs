@@ -1,5 +1,6 @@
 use std::net::SocketAddr;
 
+use axum::Router;
 use cja::{app_state::AppState as _, jobs::worker::job_worker, tower_cookies::CookieManagerLayer};
 use miette::{Context, IntoDiagnostic, Result};
 use setup::setup_sentry;
@@ -11,11 +12,30 @@ use crate::{jobs::Jobs, routes::routes};
 mod app_state;
 mod setup;
 
+mod audit;
+mod auth;
+mod authz;
 mod cron;
+mod csrf;
+mod errors;
+mod incidents;
 mod jobs;
+mod live;
+mod mailer;
+mod plans;
+mod probe;
+mod prometheus;
+mod reports;
 mod routes;
+mod sessions;
+mod slo;
+mod stats;
 
 mod templates;
+#[cfg(test)]
+mod testing;
+mod tokens;
+mod validation;
 
 mod server_tracing;
 
@@ -32,15 +52,37 @@ fn main() -> Result<()> {
         .block_on(async { _main().await })
 }
 
+/// The routes with everything they need to handle a request, short of the
+/// tracing
+fn app(app_state: AppState) -> Router {
+    routes()
+        .layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            errors::render_error_pages,
+        ))
+        // Outside the error pages so their forms get tokens too
+        .layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            csrf::protect_forms,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            sessions::expire_sessions,
+        ))
+        .with_state(app_state)
+        .layer(CookieManagerLayer::new())
+}
+
 async fn run_axum(app_state: AppState) -> miette::Result<()> {
     let tracer = server_tracing::Tracer;
     let trace_layer = tower_http::trace::TraceLayer::new_for_http()
         .make_span_with(tracer)
         .on_response(tracer);
 
-    let app = routes()
-        .with_state(app_state)
-        .layer(CookieManagerLayer::new())
+    let app = app(app_state)
+        .layer(axum::middleware::from_fn(
+            server_tracing::record_request_labels,
+        ))
         .layer(trace_layer);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3001));
@@ -72,6 +114,7 @@ async fn _main() -> Result<()> {
         tokio::spawn(run_axum(app_state.clone())),
         tokio::spawn(job_worker(app_state.clone(), Jobs)),
         tokio::spawn(cron::run_cron(app_state.clone())),
+        tokio::spawn(live::listen_for_checkins(app_state.clone())),
     ];
     info!("Tasks Spawned");
 
@@ -0,0 +1,391 @@
+use miette::IntoDiagnostic;
+use sqlx::{PgConnection, PgExecutor};
+use uuid::Uuid;
+
+use crate::{auth::AuthProvider, errors::AppError};
+
+/// Where people can sponsor to move their organizations onto the Sponsor plan
+pub const SPONSOR_URL: &str = "https://github.com/sponsors/coreyja";
+
+/// What an organization is allowed to monitor. Sponsorship only comes from
+/// the coreyja.com login, so self-hosted installs using another provider
+/// aren't limited at all
+///
+/// Every page gets the same HTTP check whatever its plan. Premium check types
+/// need a second kind of check to exist first, so they aren't part of this
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Plan {
+    Free,
+    /// At least one of the organization's owners is an active sponsor
+    Sponsor,
+    SelfHosted,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PlanLimits {
+    /// Sites per organization, `None` for no limit
+    pub max_sites: Option<i64>,
+    /// Pages per organization, across all its sites
+    pub max_pages: Option<i64>,
+    /// Organizations a user can own, so the per-organization limits can't be
+    /// dodged by opening another one
+    pub max_owned_organizations: Option<i64>,
+    /// How long the scheduler waits between checkins of the same page
+    pub check_interval: chrono::Duration,
+}
+
+impl Plan {
+    pub fn new(auth: &AuthProvider, has_sponsoring_owner: bool) -> Self {
+        match auth {
+            AuthProvider::Coreyja(_) if has_sponsoring_owner => Plan::Sponsor,
+            AuthProvider::Coreyja(_) => Plan::Free,
+            AuthProvider::Oidc(_) | AuthProvider::Local(_) => Plan::SelfHosted,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Plan::Free => "Free",
+            Plan::Sponsor => "Sponsor",
+            Plan::SelfHosted => "Self-hosted",
+        }
+    }
+
+    pub fn limits(&self) -> PlanLimits {
+        match self {
+            Plan::Free => PlanLimits {
+                max_sites: Some(3),
+                max_pages: Some(10),
+                max_owned_organizations: Some(2),
+                check_interval: chrono::Duration::minutes(5),
+            },
+            Plan::Sponsor => PlanLimits {
+                max_sites: Some(25),
+                max_pages: Some(250),
+                max_owned_organizations: Some(10),
+                check_interval: chrono::Duration::minutes(1),
+            },
+            Plan::SelfHosted => PlanLimits {
+                max_sites: None,
+                max_pages: None,
+                max_owned_organizations: None,
+                check_interval: chrono::Duration::minutes(1),
+            },
+        }
+    }
+
+    /// What to tell someone who's hit one of this plan's limits
+    pub fn upgrade_message(&self) -> Option<String> {
+        match self {
+            Plan::Free => {
+                let sponsor = Plan::Sponsor.limits();
+                Some(format!(
+                    "Sponsoring at {SPONSOR_URL} moves every organization you own onto the Sponsor plan, with up to {} organizations, {} sites and {} pages each, and checks every minute.",
+                    sponsor.max_owned_organizations.unwrap_or_default(),
+                    sponsor.max_sites.unwrap_or_default(),
+                    sponsor.max_pages.unwrap_or_default(),
+                ))
+            }
+            Plan::Sponsor | Plan::SelfHosted => None,
+        }
+    }
+}
+
+/// How much of its plan an organization is using
+#[derive(Debug, Clone, Copy)]
+pub struct Usage {
+    pub plan: Plan,
+    pub sites: i64,
+    pub pages: i64,
+}
+
+impl Usage {
+    pub fn at_site_limit(&self) -> bool {
+        self.plan
+            .limits()
+            .max_sites
+            .is_some_and(|max| self.sites >= max)
+    }
+
+    pub fn at_page_limit(&self) -> bool {
+        self.plan
+            .limits()
+            .max_pages
+            .is_some_and(|max| self.pages >= max)
+    }
+
+    /// "2 of 3 sites", or just "2 sites" when there's no limit
+    pub fn sites_summary(&self) -> String {
+        summary(self.sites, self.plan.limits().max_sites, "sites")
+    }
+
+    pub fn pages_summary(&self) -> String {
+        summary(self.pages, self.plan.limits().max_pages, "pages")
+    }
+
+    /// Refuses to go over the plan's site limit. Call inside the transaction
+    /// that creates the site, after [`lock_usage`]
+    pub fn require_site_room(&self) -> Result<(), AppError> {
+        if self.at_site_limit() {
+            return Err(self.limit_error(self.sites_summary()));
+        }
+
+        Ok(())
+    }
+
+    pub fn require_page_room(&self) -> Result<(), AppError> {
+        if self.at_page_limit() {
+            return Err(self.limit_error(self.pages_summary()));
+        }
+
+        Ok(())
+    }
+
+    fn limit_error(&self, summary: String) -> AppError {
+        let mut message = format!(
+            "This organization is already using {summary} on the {} plan.",
+            self.plan.label()
+        );
+        if let Some(upgrade) = self.plan.upgrade_message() {
+            message.push(' ');
+            message.push_str(&upgrade);
+        }
+
+        AppError::Forbidden(message)
+    }
+}
+
+fn summary(used: i64, max: Option<i64>, noun: &str) -> String {
+    match max {
+        Some(max) => format!("{used} of {max} {noun}"),
+        None => format!("{used} {noun}"),
+    }
+}
+
+pub async fn organization_usage(
+    db: impl PgExecutor<'_>,
+    auth: &AuthProvider,
+    organization_id: Uuid,
+) -> miette::Result<Usage> {
+    let row = sqlx::query!(
+        r#"
+    SELECT
+      EXISTS (
+        SELECT 1
+        FROM OrganizationMemberships
+        JOIN Users USING (user_id)
+        WHERE OrganizationMemberships.organization_id = $1
+          AND OrganizationMemberships.role = 'owner'
+          AND Users.is_active_sponsor
+      ) as "has_sponsoring_owner!",
+      (SELECT count(*) FROM Sites WHERE organization_id = $1) as "sites!",
+      (
+        SELECT count(*)
+        FROM Pages
+        JOIN Sites USING (site_id)
+        WHERE Sites.organization_id = $1
+      ) as "pages!"
+  "#,
+        organization_id
+    )
+    .fetch_one(db)
+    .await
+    .into_diagnostic()?;
+
+    Ok(Usage {
+        plan: Plan::new(auth, row.has_sponsoring_owner),
+        sites: row.sites,
+        pages: row.pages,
+    })
+}
+
+/// Locks the organization's row before counting, so two requests creating
+/// sites or pages at once can't both squeeze under the limit
+pub async fn lock_usage(
+    tx: &mut PgConnection,
+    auth: &AuthProvider,
+    organization_id: Uuid,
+) -> miette::Result<Usage> {
+    sqlx::query!(
+        r#"
+    SELECT organization_id
+    FROM Organizations
+    WHERE organization_id = $1
+    FOR UPDATE
+  "#,
+        organization_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .into_diagnostic()?;
+
+    organization_usage(&mut *tx, auth, organization_id).await
+}
+
+/// How many organizations a user owns, against the plan their own sponsorship
+/// would give them
+#[derive(Debug, Clone, Copy)]
+pub struct OwnerUsage {
+    pub plan: Plan,
+    pub organizations: i64,
+}
+
+impl OwnerUsage {
+    pub fn at_organization_limit(&self) -> bool {
+        self.plan
+            .limits()
+            .max_owned_organizations
+            .is_some_and(|max| self.organizations >= max)
+    }
+
+    pub fn organizations_summary(&self) -> String {
+        summary(
+            self.organizations,
+            self.plan.limits().max_owned_organizations,
+            "organizations",
+        )
+    }
+
+    /// Refuses to go over the plan's organization limit. Call inside the
+    /// transaction that creates the organization, after [`lock_owner_usage`]
+    pub fn require_organization_room(&self) -> Result<(), AppError> {
+        if !self.at_organization_limit() {
+            return Ok(());
+        }
+
+        let mut message = format!(
+            "You already own {} on the {} plan.",
+            self.organizations_summary(),
+            self.plan.label()
+        );
+        if let Some(upgrade) = self.plan.upgrade_message() {
+            message.push(' ');
+            message.push_str(&upgrade);
+        }
+
+        Err(AppError::Forbidden(message))
+    }
+
+    /// The same limit when an owner promotes someone else, worded for the
+    /// owner doing it since the upgrade isn't theirs to make
+    pub fn require_organization_room_for_member(&self) -> Result<(), AppError> {
+        if !self.at_organization_limit() {
+            return Ok(());
+        }
+
+        Err(AppError::Forbidden(format!(
+            "This member already owns {} on the {} plan, so they can't own another one.",
+            self.organizations_summary(),
+            self.plan.label()
+        )))
+    }
+}
+
+pub async fn owner_usage(
+    db: impl PgExecutor<'_>,
+    auth: &AuthProvider,
+    user_id: Uuid,
+) -> miette::Result<OwnerUsage> {
+    let row = sqlx::query!(
+        r#"
+    SELECT
+      Users.is_active_sponsor,
+      (
+        SELECT count(*)
+        FROM OrganizationMemberships
+        WHERE OrganizationMemberships.user_id = Users.user_id
+          AND OrganizationMemberships.role = 'owner'
+      ) as "organizations!"
+    FROM Users
+    WHERE Users.user_id = $1
+  "#,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+    .into_diagnostic()?;
+
+    Ok(OwnerUsage {
+        plan: Plan::new(auth, row.is_active_sponsor),
+        organizations: row.organizations,
+    })
+}
+
+/// Locks the user's row before counting, so two organizations created at once
+/// can't both squeeze under the limit
+pub async fn lock_owner_usage(
+    tx: &mut PgConnection,
+    auth: &AuthProvider,
+    user_id: Uuid,
+) -> miette::Result<OwnerUsage> {
+    sqlx::query!(
+        r#"
+    SELECT user_id
+    FROM Users
+    WHERE user_id = $1
+    FOR UPDATE
+  "#,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .into_diagnostic()?;
+
+    owner_usage(&mut *tx, auth, user_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use cja::app_state::AppState as _;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::{app_state::AppState, auth::coreyja::CoreyjaConfig, testing::create_user};
+
+    #[sqlx::test]
+    async fn free_users_can_only_own_so_many_organizations(pool: PgPool) {
+        let app_state = AppState::for_tests(pool, AuthProvider::Coreyja(CoreyjaConfig::from_env()));
+        let alice = create_user(&app_state, "alice").await;
+        let db = app_state.db();
+
+        let mut tx = db.begin().await.unwrap();
+        let usage = lock_owner_usage(&mut tx, &app_state.auth, alice.user_id)
+            .await
+            .unwrap();
+        assert_eq!(usage.plan, Plan::Free);
+        assert_eq!(usage.organizations_summary(), "1 of 2 organizations");
+        assert!(usage.require_organization_room().is_ok());
+        tx.rollback().await.unwrap();
+
+        create_user(&app_state, "alice-team").await;
+        sqlx::query!(
+            "UPDATE OrganizationMemberships SET user_id = $1 WHERE user_id != $1",
+            alice.user_id
+        )
+        .execute(db)
+        .await
+        .unwrap();
+
+        let usage = owner_usage(db, &app_state.auth, alice.user_id)
+            .await
+            .unwrap();
+        assert!(matches!(
+            usage.require_organization_room(),
+            Err(AppError::Forbidden(_))
+        ));
+
+        sqlx::query!(
+            "UPDATE Users SET is_active_sponsor = true WHERE user_id = $1",
+            alice.user_id
+        )
+        .execute(db)
+        .await
+        .unwrap();
+
+        let usage = owner_usage(db, &app_state.auth, alice.user_id)
+            .await
+            .unwrap();
+        assert_eq!(usage.plan, Plan::Sponsor);
+        assert!(usage.require_organization_room().is_ok());
+    }
+}
@@ -0,0 +1,398 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    ops::AddAssign,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use hyper::{
+    body::HttpBody,
+    header::{HOST, LOCATION, USER_AGENT},
+    HeaderMap, StatusCode,
+};
+use miette::{miette, IntoDiagnostic};
+use reqwest::Url;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    time::Instant,
+};
+use tokio_rustls::{
+    rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName},
+    TlsConnector,
+};
+
+const MAX_REDIRECTS: usize = 10;
+
+/// How much of a response body is downloaded. Past this the download time
+/// says little more about the page
+const MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+
+/// How long to wait for a TCP connection to any one of the host's addresses
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a whole probe can take, redirects included, so a host that
+/// accepts the connection and then never answers can't hold a job worker
+const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether probes can reach loopback, private and link-local addresses. Off
+/// unless `PROBE_ALLOW_PRIVATE_ADDRESSES=true`, so a site's domain can't be
+/// pointed at the server's own network. Self-hosted installs watching
+/// internal services can turn it on
+fn allow_private_addresses() -> bool {
+    static ALLOW: OnceLock<bool> = OnceLock::new();
+
+    *ALLOW.get_or_init(|| std::env::var("PROBE_ALLOW_PRIVATE_ADDRESSES").as_deref() == Ok("true"))
+}
+
+/// Addresses on the public internet, as opposed to this machine or the network
+/// it's on
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match embedded_v4(ip) {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    // 100.64.0.0/10, carrier-grade NAT
+    let shared = a == 100 && (b & 0b1100_0000) == 64;
+    // 192.0.0.0/24, IETF protocol assignments
+    let protocol_assignments = a == 192 && b == 0 && c == 0;
+    // 198.18.0.0/15, benchmarking
+    let benchmarking = a == 198 && (b & 0b1111_1110) == 18;
+    // 240.0.0.0/4, reserved
+    let reserved = a >= 240;
+
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || shared
+        || protocol_assignments
+        || benchmarking
+        || reserved)
+}
+
+/// The IPv4 address inside an IPv6 one that's only a way of reaching it:
+/// mapped `::ffff:a.b.c.d`, compatible `::a.b.c.d`, NAT64 `64:ff9b::a.b.c.d`
+/// and 6to4 `2002:aabb:ccdd::`
+fn embedded_v4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = ip.segments();
+    let octets = ip.octets();
+    let last_four = Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15]);
+
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return Some(ip);
+    }
+    // `::` and `::1` are the IPv6 unspecified and loopback addresses, which
+    // `is_public_v6` handles
+    if segments[..6] == [0; 6] && !ip.is_unspecified() && !ip.is_loopback() {
+        return Some(last_four);
+    }
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        return Some(last_four);
+    }
+    if segments[0] == 0x2002 {
+        return Some(Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5]));
+    }
+
+    None
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    // fc00::/7
+    let unique_local = (first & 0xfe00) == 0xfc00;
+    // fe80::/10
+    let link_local = (first & 0xffc0) == 0xfe80;
+
+    !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || unique_local || link_local)
+}
+
+/// How long each phase of a request took. When redirects are followed the
+/// phases of every hop are summed together
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PhaseTimings {
+    pub dns: Duration,
+    pub connect: Duration,
+    pub tls: Duration,
+    pub ttfb: Duration,
+    pub download: Duration,
+}
+
+impl AddAssign for PhaseTimings {
+    fn add_assign(&mut self, rhs: Self) {
+        self.dns += rhs.dns;
+        self.connect += rhs.connect;
+        self.tls += rhs.tls;
+        self.ttfb += rhs.ttfb;
+        self.download += rhs.download;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProbeResponse {
+    pub status: StatusCode,
+    pub timings: PhaseTimings,
+}
+
+struct Hop {
+    status: StatusCode,
+    location: Option<String>,
+    timings: PhaseTimings,
+}
+
+/// Makes a GET request to `url`, following redirects the same way
+/// `reqwest::get` would, while timing each phase of the request separately.
+/// `headers` are sent along with every request, including redirects
+pub async fn probe(url: &str, headers: &HeaderMap) -> miette::Result<ProbeResponse> {
+    tokio::time::timeout(PROBE_TIMEOUT, follow_redirects(url, headers))
+        .await
+        .map_err(|_| miette!("Timed out after {PROBE_TIMEOUT:?} probing {url}"))?
+}
+
+async fn follow_redirects(url: &str, headers: &HeaderMap) -> miette::Result<ProbeResponse> {
+    let mut url = Url::parse(url).into_diagnostic()?;
+    let mut timings = PhaseTimings::default();
+
+    for _ in 0..=MAX_REDIRECTS {
+        let hop = probe_once(&url, headers).await?;
+        timings += hop.timings;
+
+        match hop.location {
+            Some(location) if hop.status.is_redirection() => {
+                url = url.join(&location).into_diagnostic()?;
+            }
+            _ => {
+                return Ok(ProbeResponse {
+                    status: hop.status,
+                    timings,
+                })
+            }
+        }
+    }
+
+    Err(miette!("Too many redirects while probing {url}"))
+}
+
+async fn probe_once(url: &Url, headers: &HeaderMap) -> miette::Result<Hop> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| miette!("{url} does not have a host"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| miette!("{url} does not have a port"))?;
+
+    let start = Instant::now();
+    let addrs = tokio::net::lookup_host((host, port))
+        .await
+        .into_diagnostic()?
+        .collect::<Vec<_>>();
+    let dns = start.elapsed();
+
+    // Checked after resolving, and only the checked addresses are connected
+    // to, so a name can't resolve somewhere else between the two
+    let addrs = if allow_private_addresses() {
+        addrs
+    } else {
+        let public = addrs
+            .iter()
+            .copied()
+            .filter(|addr| is_public(addr.ip()))
+            .collect::<Vec<_>>();
+        if public.is_empty() && !addrs.is_empty() {
+            return Err(miette!(
+                "{host} only resolves to private addresses, which aren't checked"
+            ));
+        }
+        public
+    };
+
+    let start = Instant::now();
+    let tcp = connect(host, &addrs).await?;
+    let connect = start.elapsed();
+
+    let (tls, hop) = match url.scheme() {
+        "https" => {
+            let start = Instant::now();
+            let server_name = ServerName::try_from(host).into_diagnostic()?;
+            let stream = tls_connector()
+                .connect(server_name, tcp)
+                .await
+                .into_diagnostic()?;
+            let tls = start.elapsed();
+
+            (tls, send_request(stream, url, headers).await?)
+        }
+        "http" => (Duration::ZERO, send_request(tcp, url, headers).await?),
+        scheme => return Err(miette!("Unsupported scheme {scheme} for {url}")),
+    };
+
+    Ok(Hop {
+        timings: PhaseTimings {
+            dns,
+            connect,
+            tls,
+            ..hop.timings
+        },
+        ..hop
+    })
+}
+
+/// Tries each of the host's addresses in the order DNS returned them, the way
+/// reqwest does, so a host with an unreachable IPv6 address still gets checked
+/// over IPv4
+async fn connect(host: &str, addrs: &[SocketAddr]) -> miette::Result<TcpStream> {
+    let mut last_err = miette!("No addresses found for {host}");
+
+    for addr in addrs {
+        match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(tcp)) => return Ok(tcp),
+            Ok(Err(err)) => last_err = miette!("Couldn't connect to {host} at {addr}: {err}"),
+            Err(_) => last_err = miette!("Timed out connecting to {host} at {addr}"),
+        }
+    }
+
+    Err(last_err)
+}
+
+async fn send_request<IO>(io: IO, url: &Url, headers: &HeaderMap) -> miette::Result<Hop>
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::handshake(io).await.into_diagnostic()?;
+    tokio::spawn(connection);
+
+    let host = match url.port() {
+        Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+    let path = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    };
+
+    let mut request = hyper::Request::get(path)
+        .header(HOST, host)
+        .header(USER_AGENT, "UpGuardian")
+        .body(hyper::Body::empty())
+        .into_diagnostic()?;
+    request.headers_mut().extend(headers.clone());
+
+    let start = Instant::now();
+    let response = sender.send_request(request).await.into_diagnostic()?;
+    let ttfb = start.elapsed();
+
+    let status = response.status();
+    let location = response
+        .headers()
+        .get(LOCATION)
+        .and_then(|l| l.to_str().ok())
+        .map(ToOwned::to_owned);
+
+    // Each chunk is dropped as soon as it's counted, and the download stops at
+    // the cap, so a huge or endless body can't run the worker out of memory
+    let start = Instant::now();
+    let mut body = response.into_body();
+    let mut downloaded = 0;
+    while let Some(chunk) = body.data().await {
+        downloaded += chunk.into_diagnostic()?.len();
+        if downloaded >= MAX_BODY_BYTES {
+            break;
+        }
+    }
+    let download = start.elapsed();
+
+    Ok(Hop {
+        status,
+        location,
+        timings: PhaseTimings {
+            ttfb,
+            download,
+            ..Default::default()
+        },
+    })
+}
+
+fn tls_connector() -> TlsConnector {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+
+    let config = CONFIG.get_or_init(|| {
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+
+        let mut config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        // We only speak HTTP/1.1 so we can time the phases ourselves
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Arc::new(config)
+    });
+
+    TlsConnector::from(config.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_addresses_are_checked() {
+        for ip in [
+            "93.184.216.34",
+            "198.20.0.1",
+            "2606:2800:220:1:248:1893:25c8:1946",
+            "64:ff9b::5db8:d822",
+            "2002:5db8:d822::1",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn private_addresses_are_not() {
+        for ip in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.5.4",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "192.0.0.8",
+            "198.18.0.1",
+            "198.19.255.254",
+            "240.0.0.1",
+            "255.255.255.254",
+            "64:ff9b::127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "::127.0.0.1",
+            "::10.0.0.1",
+            "2002:7f00:1::",
+            "2002:a9fe:a9fe::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+}
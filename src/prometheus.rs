@@ -0,0 +1,209 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+use axum::http::{header::AUTHORIZATION, HeaderMap};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use miette::IntoDiagnostic;
+use sqlx::PgPool;
+
+use crate::tokens::tokens_match;
+
+/// Buckets for the latency histograms, in seconds
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+#[derive(Clone)]
+pub struct Metrics {
+    handle: PrometheusHandle,
+    token: Option<String>,
+    /// Job names we've reported a queue depth for, so they can be zeroed once
+    /// the queue for them empties out
+    job_names: Arc<Mutex<HashSet<String>>>,
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics")
+            .field("token", &self.token.as_ref().map(|_| "[redacted]"))
+            .finish_non_exhaustive()
+    }
+}
+
+impl Metrics {
+    /// Installs the global Prometheus recorder. `/metrics` is only served when
+    /// `METRICS_TOKEN` is set, and requires it as a bearer token
+    pub fn install_from_env() -> miette::Result<Self> {
+        let handle = PrometheusBuilder::new()
+            .set_buckets_for_metric(
+                Matcher::Suffix("_duration_seconds".to_string()),
+                LATENCY_BUCKETS,
+            )
+            .into_diagnostic()?
+            .install_recorder()
+            .into_diagnostic()?;
+        let token = std::env::var("METRICS_TOKEN").ok();
+
+        Ok(Self {
+            handle,
+            token,
+            job_names: Arc::default(),
+        })
+    }
+
+    /// Metrics that are never served, for tests. The recorder isn't installed
+    /// since only one can be per process
+    #[cfg(test)]
+    pub fn disabled() -> Self {
+        Self {
+            handle: PrometheusBuilder::new().build_recorder().handle(),
+            token: None,
+            job_names: Arc::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.token.is_some()
+    }
+
+    pub fn is_authorized(&self, headers: &HeaderMap) -> bool {
+        let Some(token) = self.token.as_ref() else {
+            return false;
+        };
+
+        headers
+            .get(AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .is_some_and(|provided| tokens_match(provided, token))
+    }
+
+    /// Refreshes the gauges that are read from the database and renders every
+    /// metric in the Prometheus text format
+    pub async fn render(&self, db: &PgPool) -> miette::Result<String> {
+        self.record_page_metrics(db).await?;
+        self.record_job_metrics(db).await?;
+        self.record_cron_metrics(db).await?;
+
+        Ok(self.handle.render())
+    }
+
+    async fn record_page_metrics(&self, db: &PgPool) -> miette::Result<()> {
+        let latest_checkins = sqlx::query!(
+            r#"
+        SELECT
+          Pages.page_id,
+          Pages.path,
+          Sites.domain,
+          Latest.outcome as "outcome!",
+          Latest.duration_nanos as "duration_nanos?",
+          Latest.status_code as "status_code?"
+        FROM Pages
+        JOIN Sites USING (site_id)
+        JOIN LATERAL (
+          SELECT Checkins.outcome, Checkins.duration_nanos, Checkins.status_code
+          FROM Checkins
+          WHERE Checkins.page_id = Pages.page_id
+          ORDER BY Checkins.created_at DESC
+          LIMIT 1
+        ) Latest ON true
+      "#
+        )
+        .fetch_all(db)
+        .await
+        .into_diagnostic()?;
+
+        for checkin in latest_checkins {
+            let labels = [
+                ("page_id", checkin.page_id.to_string()),
+                ("domain", checkin.domain),
+                ("path", checkin.path),
+            ];
+
+            let up = if checkin.outcome == "success" {
+                1.0
+            } else {
+                0.0
+            };
+            metrics::gauge!("upguardian_page_up", &labels).set(up);
+
+            if let Some(duration_nanos) = checkin.duration_nanos {
+                metrics::gauge!("upguardian_page_last_duration_seconds", &labels)
+                    .set(duration_nanos as f64 / 1_000_000_000.0);
+            }
+            if let Some(status_code) = checkin.status_code {
+                metrics::gauge!("upguardian_page_last_status_code", &labels)
+                    .set(f64::from(status_code));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn record_job_metrics(&self, db: &PgPool) -> miette::Result<()> {
+        let queues = sqlx::query!(
+            r#"
+        SELECT
+          name,
+          count(*) FILTER (WHERE locked_at IS NULL) as "queued!",
+          count(*) FILTER (WHERE locked_at IS NOT NULL) as "running!",
+          extract(epoch FROM now() - min(run_at) FILTER (
+            WHERE locked_at IS NULL AND run_at <= now()
+          ))::float8 as oldest_ready_seconds
+        FROM Jobs
+        GROUP BY name
+      "#
+        )
+        .fetch_all(db)
+        .await
+        .into_diagnostic()?;
+
+        let mut job_names = self.job_names.lock().unwrap();
+        let mut empty_queues = job_names.clone();
+
+        for queue in queues {
+            empty_queues.remove(&queue.name);
+            job_names.insert(queue.name.clone());
+
+            metrics::gauge!("upguardian_jobs_queued", "name" => queue.name.clone())
+                .set(queue.queued as f64);
+            metrics::gauge!("upguardian_jobs_running", "name" => queue.name.clone())
+                .set(queue.running as f64);
+            // How long the oldest job that is ready to run has been waiting for a
+            // worker to pick it up
+            metrics::gauge!("upguardian_job_latency_seconds", "name" => queue.name)
+                .set(queue.oldest_ready_seconds.unwrap_or_default());
+        }
+
+        for name in empty_queues {
+            metrics::gauge!("upguardian_jobs_queued", "name" => name.clone()).set(0.0);
+            metrics::gauge!("upguardian_jobs_running", "name" => name.clone()).set(0.0);
+            metrics::gauge!("upguardian_job_latency_seconds", "name" => name).set(0.0);
+        }
+
+        Ok(())
+    }
+
+    async fn record_cron_metrics(&self, db: &PgPool) -> miette::Result<()> {
+        let crons = sqlx::query!(
+            r#"
+        SELECT
+          name,
+          extract(epoch FROM now() - last_run_at)::float8 as "seconds_since_last_run!"
+        FROM Crons
+      "#
+        )
+        .fetch_all(db)
+        .await
+        .into_diagnostic()?;
+
+        for cron in crons {
+            metrics::gauge!("upguardian_cron_seconds_since_last_run", "name" => cron.name)
+                .set(cron.seconds_since_last_run);
+        }
+
+        Ok(())
+    }
+}
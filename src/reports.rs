@@ -0,0 +1,389 @@
+use std::ops::Range;
+
+use chrono::{DateTime, Datelike, Utc};
+use cja::app_state::AppState as _;
+use maud::{html, PreEscaped, Render, DOCTYPE};
+use miette::IntoDiagnostic;
+
+use crate::{
+    app_state::AppState,
+    incidents::{incidents_for_range, Incident},
+    routes::{
+        current_user::{
+            pages::{BucketedCheckinGraph, GraphSeries, Page, GRAPH_BUCKETS},
+            sites::{format_response_time, single_stat, Site},
+        },
+        TAILWIND_CSS,
+    },
+    stats::{
+        checkin_buckets, stats_for_range, CheckinBucket, CheckinStats, Percentile, StatsScope,
+    },
+};
+
+pub struct PageReport {
+    pub page: Page,
+    pub stats: CheckinStats,
+    pub buckets: Vec<CheckinBucket>,
+}
+
+/// Everything that goes into the uptime report for a site over a date range
+pub struct SiteReport {
+    pub site: Site,
+    pub range: Range<DateTime<Utc>>,
+    pub stats: CheckinStats,
+    pub pages: Vec<PageReport>,
+    pub incidents: Vec<Incident>,
+    /// Incidents come from the raw checkins, which only go back as far as the
+    /// checkin retention
+    pub raw_checkins_start: DateTime<Utc>,
+}
+
+/// The whole calendar month before the one `now` is in
+pub fn previous_month(now: DateTime<Utc>) -> Range<DateTime<Utc>> {
+    let this_month = now.date_naive().with_day(1).unwrap();
+    let last_month = (this_month - chrono::Duration::days(1))
+        .with_day(1)
+        .unwrap();
+
+    let start = last_month.and_hms_opt(0, 0, 0).unwrap().and_utc();
+    let end = this_month.and_hms_opt(0, 0, 0).unwrap().and_utc();
+
+    start..end
+}
+
+impl SiteReport {
+    pub async fn generate(
+        state: &AppState,
+        site: Site,
+        range: Range<DateTime<Utc>>,
+    ) -> miette::Result<Self> {
+        let db = state.db();
+
+        let stats = stats_for_range(
+            db,
+            StatsScope::Site(site.site_id),
+            range.clone(),
+            state.checkin_retention,
+        )
+        .await?;
+        let incidents = incidents_for_range(db, site.site_id, range.clone()).await?;
+
+        let pages = sqlx::query_as!(
+            Page,
+            r#"
+        SELECT *
+        FROM Pages
+        WHERE site_id = $1
+        ORDER BY name ASC
+      "#,
+            site.site_id
+        )
+        .fetch_all(db)
+        .await
+        .into_diagnostic()?;
+
+        let mut page_reports = vec![];
+        for page in pages {
+            let stats = stats_for_range(
+                db,
+                StatsScope::Page(page.page_id),
+                range.clone(),
+                state.checkin_retention,
+            )
+            .await?;
+            let buckets = checkin_buckets(
+                db,
+                StatsScope::Page(page.page_id),
+                range.clone(),
+                GRAPH_BUCKETS,
+                state.checkin_retention,
+            )
+            .await?;
+
+            page_reports.push(PageReport {
+                page,
+                stats,
+                buckets,
+            });
+        }
+
+        Ok(Self {
+            site,
+            range,
+            stats,
+            pages: page_reports,
+            incidents,
+            raw_checkins_start: Utc::now() - state.checkin_retention,
+        })
+    }
+
+    /// The last day covered by the report, since the range end is exclusive
+    fn last_day(&self) -> chrono::NaiveDate {
+        (self.range.end - chrono::Duration::nanoseconds(1)).date_naive()
+    }
+
+    pub fn title(&self) -> String {
+        format!(
+            "{} uptime report, {} to {}",
+            self.site.name,
+            self.range.start.format("%B %-d, %Y"),
+            self.last_day().format("%B %-d, %Y")
+        )
+    }
+
+    pub fn filename(&self) -> String {
+        format!(
+            "{}-uptime-{}-{}.html",
+            self.site.domain,
+            self.range.start.format("%Y-%m-%d"),
+            self.last_day().format("%Y-%m-%d")
+        )
+    }
+
+    /// How long at least one page was down. Incidents on different pages
+    /// that overlap are only counted once
+    pub fn downtime(&self) -> chrono::Duration {
+        total_downtime(&self.incidents, Utc::now())
+    }
+
+    /// A self contained HTML document with the styles inlined, so it can be
+    /// downloaded, emailed or printed to PDF without the app
+    pub fn standalone_html(&self, font_awesome_kit_id: &str) -> String {
+        html! {
+          (DOCTYPE)
+          html class="bg-white" {
+            head {
+              meta charset="utf-8";
+              meta name="viewport" content="width=device-width, initial-scale=1.0";
+              title { (self.title()) }
+              style { (PreEscaped(TAILWIND_CSS)) }
+              link rel="stylesheet" href=(format!("https://kit.fontawesome.com/{}.css", font_awesome_kit_id)) crossorigin="anonymous";
+            }
+
+            body class="mx-auto max-w-5xl p-8" {
+              (self)
+            }
+          }
+        }
+        .into_string()
+    }
+
+    /// The short body of the monthly email, the full report goes along as an
+    /// attachment
+    pub fn email_summary(&self) -> String {
+        html! {
+          p { (self.title()) }
+
+          p {
+            (format!(
+              "Uptime was {:.3}% across {} checkins, with {} incidents.",
+              self.stats.success_percent(),
+              self.stats.count,
+              self.incidents.len()
+            ))
+          }
+
+          p { "The full report is attached." }
+        }
+        .into_string()
+    }
+}
+
+fn total_downtime(incidents: &[Incident], now: DateTime<Utc>) -> chrono::Duration {
+    let mut intervals = incidents
+        .iter()
+        .map(|incident| (incident.started_at, incident.ended_at.unwrap_or(now)))
+        .collect::<Vec<_>>();
+    intervals.sort();
+
+    let mut total = chrono::Duration::zero();
+    let mut current: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+    for (start, end) in intervals {
+        current = match current {
+            Some((current_start, current_end)) if start <= current_end => {
+                Some((current_start, current_end.max(end)))
+            }
+            Some((current_start, current_end)) => {
+                total = total + (current_end - current_start);
+                Some((start, end))
+            }
+            None => Some((start, end)),
+        };
+    }
+    if let Some((start, end)) = current {
+        total = total + (end - start);
+    }
+
+    total
+}
+
+pub fn format_duration(duration: chrono::Duration) -> String {
+    let duration = duration.to_std().unwrap_or_default();
+    let duration = std::time::Duration::from_secs(duration.as_secs());
+
+    humantime::format_duration(duration).to_string()
+}
+
+impl Render for SiteReport {
+    fn render(&self) -> maud::Markup {
+        let uptime = if self.stats.is_empty() {
+            "-".to_string()
+        } else {
+            format!("{:.3}%", self.stats.success_percent())
+        };
+
+        html! {
+          h1."text-2xl font-semibold text-gray-900" { (self.title()) }
+
+          @if let Some(description) = self.site.description.as_ref() {
+            p { (description) }
+          }
+
+          dl."mt-5 grid grid-cols-1 gap-5 sm:grid-cols-2 lg:grid-cols-4" {
+            (single_stat("Uptime", uptime, None, "fa-arrow-up"))
+            (single_stat("Checkins", self.stats.count, None, "fa-list-check"))
+            (single_stat("Incidents", self.incidents.len(), None, "fa-triangle-exclamation"))
+            (single_stat("Downtime", format_duration(self.downtime()), None, "fa-arrow-down"))
+          }
+
+          dl."mt-5 grid grid-cols-1 gap-5 sm:grid-cols-2 lg:grid-cols-4" {
+            (single_stat("Avg. Response Time", format_response_time(self.stats.avg_response_time_ms()), None, "fa-clock"))
+            @for percentile in Percentile::ALL {
+              (single_stat(
+                &format!("{} Response Time", percentile.label()),
+                format_response_time(self.stats.percentile_response_time_ms(percentile)),
+                None,
+                "fa-stopwatch",
+              ))
+            }
+          }
+
+          @if self.range.start < self.raw_checkins_start {
+            p."mt-5 text-sm text-gray-500" {
+              (format!(
+                "Incidents only include checkins since {}",
+                self.raw_checkins_start.format("%B %-d, %Y")
+              ))
+            }
+          }
+
+          h2."mt-8 text-xl font-semibold text-gray-900" { "Incidents" }
+
+          @if self.incidents.is_empty() {
+            p { "No incidents" }
+          } @else {
+            table."min-w-full divide-y divide-gray-300" {
+              thead {
+                tr {
+                  th."text-left" { "Page" }
+                  th."text-left" { "Started" }
+                  th."text-left" { "Resolved" }
+                  th."text-left" { "Duration" }
+                  th."text-left" { "Failed Checkins" }
+                }
+              }
+              tbody."divide-y divide-gray-200" {
+                @for incident in &self.incidents {
+                  tr {
+                    td { (incident.page_name) }
+                    td { (incident.started_at.format("%D %H:%M UTC")) }
+                    td {
+                      @if let Some(ended_at) = incident.ended_at {
+                        (ended_at.format("%D %H:%M UTC"))
+                      } @else {
+                        "Ongoing"
+                      }
+                    }
+                    td { (format_duration(incident.duration())) }
+                    td { (incident.failed_checkins) }
+                  }
+                }
+              }
+            }
+          }
+
+          h2."mt-8 text-xl font-semibold text-gray-900" { "Pages" }
+
+          @for page_report in &self.pages {
+            div."mt-5 break-inside-avoid" {
+              h3."text-base font-semibold leading-6 text-gray-900" {
+                (page_report.page.name) " - " (page_report.page.path)
+              }
+
+              p {
+                @if page_report.stats.is_empty() {
+                  "No checkins"
+                } @else {
+                  (format!(
+                    "{:.3}% uptime, {} p95 response time",
+                    page_report.stats.success_percent(),
+                    format_response_time(page_report.stats.percentile_response_time_ms(Percentile::P95))
+                  ))
+                }
+              }
+
+              (BucketedCheckinGraph {
+                buckets: page_report.buckets.clone(),
+                range: self.range.clone(),
+                series: GraphSeries::P95,
+                tz: chrono_tz::Tz::UTC,
+              })
+            }
+          }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn incident(start_minute: u32, end_minute: Option<u32>) -> Incident {
+        let at = |minute| Utc.with_ymd_and_hms(2024, 3, 1, 12, minute, 0).unwrap();
+
+        Incident {
+            site_id: Uuid::nil(),
+            page_id: Uuid::new_v4(),
+            page_name: "Home".to_string(),
+            started_at: at(start_minute),
+            ended_at: end_minute.map(at),
+            failed_checkins: 1,
+        }
+    }
+
+    #[test]
+    fn overlapping_incidents_are_counted_once() {
+        let incidents = [incident(0, Some(10)), incident(5, Some(15))];
+        let now = Utc::now();
+
+        assert_eq!(
+            total_downtime(&incidents, now),
+            chrono::Duration::minutes(15)
+        );
+    }
+
+    #[test]
+    fn separate_incidents_are_added_up() {
+        let incidents = [incident(30, Some(40)), incident(0, Some(10))];
+        let now = Utc::now();
+
+        assert_eq!(
+            total_downtime(&incidents, now),
+            chrono::Duration::minutes(20)
+        );
+    }
+
+    #[test]
+    fn ongoing_incidents_run_until_now() {
+        let incidents = [incident(0, None), incident(5, Some(10))];
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 0).unwrap();
+
+        assert_eq!(
+            total_downtime(&incidents, now),
+            chrono::Duration::minutes(30)
+        );
+    }
+}
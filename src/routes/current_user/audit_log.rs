@@ -0,0 +1,167 @@
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use cja::{app_state::AppState as _, server::session::DBSession};
+use maud::html;
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    audit::{entries_for_site, AuditAction, AuditLogEntry},
+    errors::AppError,
+    templates::IntoTemplate,
+};
+
+use super::{
+    exports::{export_form, ExportQuery},
+    sites::Site,
+};
+
+/// How many of the most recent entries the audit log page shows. Older ones
+/// are still in the export
+const AUDIT_LOG_LIMIT: i64 = 100;
+
+fn format_value(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => "-".to_string(),
+        Some(Value::String(s)) => s.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+fn render_changes(entry: &AuditLogEntry) -> maud::Markup {
+    html! {
+      ul {
+        @for (field, before, after) in entry.changed_fields() {
+          li {
+            span."font-semibold" { (field) ": " }
+            @match entry.action {
+              AuditAction::Create => { (format_value(after)) }
+              AuditAction::Delete => { (format_value(before)) }
+              AuditAction::Update => { (format_value(before)) " → " (format_value(after)) }
+            }
+          }
+        }
+      }
+    }
+}
+
+pub async fn show(
+    site: Site,
+    session: DBSession,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let entries = entries_for_site(state.db(), site.site_id, None, Some(AUDIT_LOG_LIMIT)).await?;
+
+    Ok(html! {
+      h1 { (site.name) " Audit Log" }
+
+      p { "Every change to this site's configuration, newest first" }
+
+      (export_form(&format!("/my/sites/{}/audit-log/export", site.site_id), "Export Audit Log"))
+
+      @if entries.is_empty() {
+        p { "Nothing has changed yet" }
+      } @else {
+        table."min-w-full divide-y divide-gray-300" {
+          thead {
+            tr {
+              th."text-left" { "When" }
+              th."text-left" { "Who" }
+              th."text-left" { "What" }
+              th."text-left" { "Changes" }
+            }
+          }
+          tbody {
+            @for entry in &entries {
+              tr {
+                td { (entry.created_at.format("%Y-%m-%d %H:%M:%S UTC")) }
+                td { (entry.actor()) }
+                td { (entry.action.label()) " " (entry.entity.label().to_lowercase()) }
+                td { (render_changes(entry)) }
+              }
+            }
+          }
+        }
+      }
+    }
+    .into_template(state, Some(session))
+    .await?
+    .into_response())
+}
+
+/// One entry of the export. The snapshots are nested JSON, which CSV can't
+/// hold, so they're written out as JSON strings
+#[derive(Serialize)]
+struct AuditLogExportRow {
+    created_at: DateTime<Utc>,
+    actor: String,
+    actor_user_id: Option<Uuid>,
+    action: &'static str,
+    entity_type: &'static str,
+    entity_id: Uuid,
+    before: Option<String>,
+    after: Option<String>,
+}
+
+impl From<&AuditLogEntry> for AuditLogExportRow {
+    fn from(entry: &AuditLogEntry) -> Self {
+        Self {
+            created_at: entry.created_at,
+            actor: entry.actor(),
+            actor_user_id: entry.actor_user_id,
+            action: entry.action.as_str(),
+            entity_type: entry.entity.as_str(),
+            entity_id: entry.entity_id,
+            before: entry.before.as_ref().map(Value::to_string),
+            after: entry.after.as_ref().map(Value::to_string),
+        }
+    }
+}
+
+pub async fn export(
+    site: Site,
+    State(state): State<AppState>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, AppError> {
+    let range = query.range()?;
+    let format = query.format;
+
+    // Oldest first, like the checkin exports
+    let mut entries = entries_for_site(state.db(), site.site_id, Some(range), None).await?;
+    entries.reverse();
+
+    let mut body = vec![];
+    for (i, entry) in entries.iter().enumerate() {
+        body.extend(
+            format
+                .encode(&AuditLogExportRow::from(entry), i == 0)
+                .map_err(|err| miette::miette!(err))?,
+        );
+    }
+
+    let filename = format!(
+        "{}-audit-log-{}-{}.{}",
+        site.domain,
+        query.start,
+        query.end,
+        format.extension()
+    );
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
@@ -0,0 +1,219 @@
+use axum::{
+    extract::{RawQuery, State},
+    response::{IntoResponse, Response},
+};
+use cja::{app_state::AppState as _, server::session::DBSession};
+use maud::html;
+use miette::IntoDiagnostic;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    authz,
+    errors::AppError,
+    stats::{checkin_buckets, StatsScope},
+    templates::IntoTemplate,
+};
+
+use super::{
+    pages::{GraphLine, GraphSeries, ResponseTimeGraph, GRAPH_BUCKETS, LINE_COLORS},
+    time_range::TimeRangeQuery,
+};
+
+/// How much history the comparison shows when no range is picked
+fn default_compare_range() -> chrono::Duration {
+    chrono::Duration::hours(24)
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CompareQuery {
+    /// Repeated once for every page to compare, the way checkboxes submit
+    #[serde(default, rename = "page_id")]
+    page_ids: Vec<Uuid>,
+    #[serde(flatten)]
+    range: TimeRangeQuery,
+    #[serde(default)]
+    series: GraphSeries,
+}
+
+impl CompareQuery {
+    /// `axum::extract::Query` can't deserialize repeated keys into a `Vec`,
+    /// so this parses the raw query string instead
+    fn parse(query: Option<String>) -> Result<Self, AppError> {
+        serde_html_form::from_str(query.as_deref().unwrap_or_default())
+            .map_err(|err| AppError::BadRequest(err.to_string()))
+    }
+}
+
+struct ComparablePage {
+    page_id: Uuid,
+    site_id: Uuid,
+    site_name: String,
+    name: String,
+    path: String,
+}
+
+impl ComparablePage {
+    fn label(&self) -> String {
+        format!("{} - {}", self.site_name, self.name)
+    }
+
+    fn href(&self) -> String {
+        format!("/my/sites/{}/pages/{}", self.site_id, self.page_id)
+    }
+}
+
+pub async fn show(
+    session: DBSession,
+    State(state): State<AppState>,
+    RawQuery(query): RawQuery,
+) -> Result<Response, AppError> {
+    let query = CompareQuery::parse(query)?;
+
+    let site_ids = authz::accessible_site_ids(state.db(), session.user_id).await?;
+    let pages = sqlx::query_as!(
+        ComparablePage,
+        r#"
+    SELECT
+      Pages.page_id,
+      Pages.site_id,
+      Sites.name as site_name,
+      Pages.name,
+      Pages.path
+    FROM Pages
+    JOIN Sites USING (site_id)
+    WHERE Sites.site_id = ANY($1)
+    ORDER BY Sites.name ASC, Pages.name ASC
+  "#,
+        &site_ids
+    )
+    .fetch_all(state.db())
+    .await
+    .into_diagnostic()?;
+
+    let graph = comparison_graph(&session, &state, &query).await?;
+
+    Ok(html! {
+      h1 { "Compare Pages" }
+
+      p {
+        "Pick up to " (LINE_COLORS.len()) " pages to overlay their response times"
+      }
+
+      form action="/my/compare/refresh" method="get" data-target=".refresh" data-app="LiveForm" data-sync-url {
+        (query.range.form_fields("Last 24 hours"))
+
+        select name="series" {
+          @for (series, label) in [
+            (GraphSeries::Avg, "Average"),
+            (GraphSeries::P50, "p50"),
+            (GraphSeries::P95, "p95"),
+            (GraphSeries::P99, "p99"),
+          ] {
+            option value=(series.as_str()) selected[query.series == series] { (label) }
+          }
+        }
+
+        fieldset {
+          legend { "Pages" }
+
+          @for page in &pages {
+            label."block" {
+              input type="checkbox" name="page_id" value=(page.page_id) checked[query.page_ids.contains(&page.page_id)];
+              " " (page.label()) " (" (page.path) ")"
+            }
+          }
+        }
+
+        div class="refresh" {
+          (graph)
+        }
+      }
+    }
+    .into_template(state, Some(session))
+    .await?
+    .into_response())
+}
+
+pub async fn refresh(
+    session: DBSession,
+    State(state): State<AppState>,
+    RawQuery(query): RawQuery,
+) -> Result<Response, AppError> {
+    let query = CompareQuery::parse(query)?;
+
+    Ok(comparison_graph(&session, &state, &query)
+        .await?
+        .0
+        .into_response())
+}
+
+async fn comparison_graph(
+    session: &DBSession,
+    state: &AppState,
+    query: &CompareQuery,
+) -> Result<maud::Markup, AppError> {
+    if query.page_ids.is_empty() {
+        return Ok(html! { p { "Pick some pages to compare" } });
+    }
+    if query.page_ids.len() > LINE_COLORS.len() {
+        return Err(AppError::Invalid(format!(
+            "Compare at most {} pages at once",
+            LINE_COLORS.len()
+        )));
+    }
+
+    let range = query.range.range(default_compare_range())?;
+
+    // Only pages the user can see, in the order they were picked
+    let site_ids = authz::accessible_site_ids(state.db(), session.user_id).await?;
+    let pages = sqlx::query_as!(
+        ComparablePage,
+        r#"
+    SELECT
+      Pages.page_id,
+      Pages.site_id,
+      Sites.name as site_name,
+      Pages.name,
+      Pages.path
+    FROM Pages
+    JOIN Sites USING (site_id)
+    WHERE Sites.site_id = ANY($1)
+      AND Pages.page_id = ANY($2)
+    ORDER BY array_position($2, Pages.page_id)
+  "#,
+        &site_ids,
+        &query.page_ids
+    )
+    .fetch_all(state.db())
+    .await
+    .into_diagnostic()?;
+
+    let mut lines = vec![];
+    for page in pages {
+        let buckets = checkin_buckets(
+            state.db(),
+            StatsScope::Page(page.page_id),
+            range.clone(),
+            GRAPH_BUCKETS,
+            state.checkin_retention,
+        )
+        .await?;
+
+        lines.push(GraphLine {
+            label: page.label(),
+            href: Some(page.href()),
+            buckets,
+        });
+    }
+
+    Ok(html! {
+      (ResponseTimeGraph {
+        lines,
+        range,
+        series: query.series,
+        tz: query.range.tz(),
+      })
+    })
+}
@@ -0,0 +1,62 @@
+use std::{convert::Infallible, time::Duration};
+
+use axum::{
+    extract::State,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use cja::{app_state::AppState as _, server::session::DBSession};
+use futures::Stream;
+use tokio::{sync::broadcast::error::RecvError, time::Instant};
+
+use crate::{app_state::AppState, authz, errors::AppError};
+
+/// How stale the stream's memberships can get before they're looked up again,
+/// so someone removed from an organization stops seeing its checkins
+const MEMBERSHIP_REFRESH: Duration = Duration::from_secs(30);
+
+/// Streams a `checkin` event for every new checkin on the sites the current
+/// user can see. Memberships are looked up again before the next event once
+/// they're older than [`MEMBERSHIP_REFRESH`]
+pub async fn stream(
+    session: DBSession,
+    State(state): State<AppState>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    let mut receiver = state.checkin_events.subscribe();
+    let user_id = session.user_id;
+    let db = state.db().clone();
+    let mut organization_ids = authz::organization_ids(&db, user_id).await?;
+    let mut refreshed_at = Instant::now();
+
+    let events = async_stream::stream! {
+        loop {
+            let checkin = match receiver.recv().await {
+                Ok(checkin) => checkin,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+
+            if refreshed_at.elapsed() >= MEMBERSHIP_REFRESH {
+                match authz::organization_ids(&db, user_id).await {
+                    Ok(ids) => {
+                        organization_ids = ids;
+                        refreshed_at = Instant::now();
+                    }
+                    // Better to end the stream than keep sending on stale
+                    // memberships, the browser reconnects on its own
+                    Err(err) => {
+                        tracing::error!(?err, "Couldn't refresh the event stream's memberships");
+                        break;
+                    }
+                }
+            }
+
+            if organization_ids.contains(&checkin.organization_id) {
+                if let Ok(event) = Event::default().event("checkin").json_data(&checkin) {
+                    yield Ok(event);
+                }
+            }
+        }
+    };
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
@@ -0,0 +1,208 @@
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use cja::app_state::AppState as _;
+use futures::{stream::BoxStream, TryStreamExt as _};
+use maud::html;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    errors::AppError,
+    stats::{day_range, StatsScope},
+};
+
+use super::{
+    pages::{Checkin, Page},
+    sites::Site,
+};
+
+pub(crate) type ExportError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub(crate) fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub(crate) fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+
+    /// Encodes a single row as one line of the export. CSV only gets a
+    /// header row before the first row
+    pub(crate) fn encode<T: Serialize>(
+        &self,
+        row: &T,
+        first: bool,
+    ) -> Result<Vec<u8>, ExportError> {
+        match self {
+            ExportFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(first)
+                    .from_writer(vec![]);
+                writer.serialize(row)?;
+
+                Ok(writer.into_inner()?)
+            }
+            ExportFormat::Ndjson => {
+                let mut line = serde_json::to_vec(row)?;
+                line.push(b'\n');
+
+                Ok(line)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    /// Both ends are inclusive
+    pub(crate) start: NaiveDate,
+    pub(crate) end: NaiveDate,
+    #[serde(default)]
+    pub(crate) format: ExportFormat,
+}
+
+impl ExportQuery {
+    /// From the start of the first day to the end of the last
+    pub(crate) fn range(&self) -> Result<std::ops::Range<DateTime<Utc>>, AppError> {
+        if self.start > self.end {
+            return Err(AppError::Invalid("Start must be before end".to_string()));
+        }
+
+        day_range(self.start, self.end)
+            .ok_or_else(|| AppError::Invalid("End is too far in the future".to_string()))
+    }
+}
+
+pub async fn site_checkins(
+    site: Site,
+    State(state): State<AppState>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    let filename = format!("{}-checkins", site.domain);
+
+    export_checkins(state, StatsScope::Site(site.site_id), query, filename)
+}
+
+pub async fn page_checkins(
+    site: Site,
+    page: Page,
+    State(state): State<AppState>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    let filename = format!("{}-{}-checkins", site.domain, page.page_id);
+
+    export_checkins(state, StatsScope::Page(page.page_id), query, filename)
+}
+
+/// Streams the checkins straight from Postgres to the response body, so
+/// exporting a long range never holds more than a row in memory
+fn export_checkins(
+    state: AppState,
+    scope: StatsScope,
+    query: ExportQuery,
+    filename: String,
+) -> Response {
+    let range = match query.range() {
+        Ok(range) => range,
+        Err(err) => return err.into_response(),
+    };
+    let format = query.format;
+
+    let rows = async_stream::try_stream! {
+        let site_ids = scope.site_ids(state.db()).await?;
+
+        let mut checkins = sqlx::query_as!(
+            Checkin,
+            r#"
+        SELECT Checkins.*
+        FROM Checkins
+        JOIN Pages USING (page_id)
+        WHERE ($1::uuid IS NULL OR Checkins.page_id = $1)
+          AND ($2::uuid IS NULL OR Pages.site_id = $2)
+          AND ($3::uuid[] IS NULL OR Pages.site_id = ANY($3))
+          AND Checkins.created_at >= $4
+          AND Checkins.created_at < $5
+        ORDER BY Checkins.created_at ASC
+      "#,
+            scope.page_id(),
+            scope.site_id(),
+            site_ids.as_deref(),
+            range.start,
+            range.end
+        )
+        .fetch(state.db());
+
+        let mut first = true;
+        while let Some(checkin) = checkins.try_next().await? {
+            yield format.encode(&checkin, first)?;
+            first = false;
+        }
+    };
+    let rows: BoxStream<'static, Result<Vec<u8>, ExportError>> = Box::pin(rows);
+
+    let filename = format!(
+        "{filename}-{}-{}.{}",
+        query.start,
+        query.end,
+        format.extension()
+    );
+
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        Body::from_stream(rows),
+    )
+        .into_response()
+}
+
+/// A form to download whatever is behind `action` for a range of days
+pub fn export_form(action: &str, label: &str) -> maud::Markup {
+    let today = chrono::Utc::now().date_naive();
+    let week_ago = today - chrono::Duration::days(7);
+
+    html! {
+      form method="get" action=(action) {
+        label {
+          "From"
+          input type="date" name="start" value=(week_ago) required;
+        }
+
+        label {
+          "To"
+          input type="date" name="end" value=(today) required;
+        }
+
+        select name="format" {
+          option value="csv" { "CSV" }
+          option value="ndjson" { "NDJSON" }
+        }
+
+        button type="submit" { (label) }
+      }
+    }
+}
@@ -1,2 +1,12 @@
+pub mod audit_log;
+pub mod compare;
+pub mod events;
+pub mod exports;
+pub mod organizations;
 pub mod pages;
+pub mod reports;
+pub mod sessions;
 pub mod sites;
+pub mod slos;
+pub mod time_range;
+pub mod uptime_bar;
@@ -0,0 +1,858 @@
+use axum::{
+    extract::{FromRequestParts, Path, State},
+    http::request::Parts,
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+use chrono::{DateTime, Utc};
+use cja::{app_state::AppState as _, server::session::DBSession};
+use maud::html;
+use miette::IntoDiagnostic;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    authz::{self, Role},
+    errors::AppError,
+    plans,
+    templates::IntoTemplate,
+};
+
+/// How long an invitation link can be used for
+fn invitation_expiry() -> chrono::Duration {
+    chrono::Duration::days(7)
+}
+
+/// An organization the current user is a member of, along with their role in it
+pub(crate) struct MemberOrganization {
+    pub organization_id: Uuid,
+    pub name: String,
+    pub role: Role,
+}
+
+pub(crate) async fn organizations_for_user(
+    db: &PgPool,
+    user_id: Uuid,
+) -> miette::Result<Vec<MemberOrganization>> {
+    let rows = sqlx::query!(
+        r#"
+    SELECT Organizations.organization_id, Organizations.name, OrganizationMemberships.role
+    FROM Organizations
+    JOIN OrganizationMemberships USING (organization_id)
+    WHERE OrganizationMemberships.user_id = $1
+    ORDER BY Organizations.name ASC
+  "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .into_diagnostic()?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(MemberOrganization {
+                organization_id: row.organization_id,
+                name: row.name,
+                role: row.role.parse().map_err(|e: String| miette::miette!(e))?,
+            })
+        })
+        .collect()
+}
+
+pub async fn index(
+    session: DBSession,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let organizations = organizations_for_user(state.db(), session.user_id).await?;
+    let usage = plans::owner_usage(state.db(), &state.auth, session.user_id).await?;
+
+    Ok(html! {      h1 { "My Organizations" }
+
+      ul."divide-y divide-gray-100" role="list" {
+        @for organization in organizations {
+          li."flex items-center justify-between gap-x-6 py-5" {
+            a href=(format!("/my/organizations/{}", organization.organization_id)) {
+              (organization.name)
+            }
+
+            p."text-sm text-gray-500" { (organization.role.label()) }
+          }
+        }
+      }
+
+      h2 { "New Organization" }
+
+      p."text-sm text-gray-500" {
+        "You own " (usage.organizations_summary()) " on the " (usage.plan.label()) " plan."
+      }
+
+      @if usage.at_organization_limit() {
+        @if let Some(upgrade_message) = usage.plan.upgrade_message() {
+          p { (upgrade_message) }
+        }
+      } @else {
+        form method="post" action="/my/organizations" {
+          label {
+            "Name"
+            input type="text" name="name" required;
+          }
+
+          button type="submit" { "Create" }
+        }
+      }
+    }
+    .into_template(state, Some(session))
+    .await?
+    .into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub struct OrganizationFormData {
+    name: String,
+}
+
+pub async fn create(
+    session: DBSession,
+    State(state): State<AppState>,
+    Form(form_data): Form<OrganizationFormData>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut tx = state.db().begin().await.into_diagnostic()?;
+
+    plans::lock_owner_usage(&mut tx, &state.auth, session.user_id)
+        .await?
+        .require_organization_room()?;
+
+    let organization_id = sqlx::query_scalar!(
+        r#"
+    INSERT INTO Organizations (name)
+    VALUES ($1)
+    RETURNING organization_id
+  "#,
+        form_data.name.trim()
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .into_diagnostic()?;
+
+    sqlx::query!(
+        r#"
+    INSERT INTO OrganizationMemberships (organization_id, user_id, role)
+    VALUES ($1, $2, $3)
+  "#,
+        organization_id,
+        session.user_id,
+        Role::Owner.as_str()
+    )
+    .execute(&mut *tx)
+    .await
+    .into_diagnostic()?;
+
+    tx.commit().await.into_diagnostic()?;
+
+    Ok(Redirect::to(&format!(
+        "/my/organizations/{organization_id}"
+    )))
+}
+
+pub struct Organization {
+    pub organization_id: Uuid,
+    pub name: String,
+    /// The current user's role in the organization
+    pub role: Role,
+}
+
+impl Organization {
+    pub fn require_role(&self, required: Role) -> Result<(), AppError> {
+        authz::require(Some(self.role), required).map(|_| ())
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct OrganizationParams {
+    organization_id: Uuid,
+}
+
+#[async_trait::async_trait]
+impl FromRequestParts<AppState> for Organization {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let session = DBSession::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let Path(OrganizationParams { organization_id }) =
+            Path::<OrganizationParams>::from_request_parts(parts, state)
+                .await
+                .map_err(|_| AppError::NotFound.into_response())?;
+
+        let role = authz::require_organization_role(
+            state.db(),
+            session.user_id,
+            organization_id,
+            Role::Viewer,
+        )
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+        let name = sqlx::query_scalar!(
+            r#"
+          SELECT name
+          FROM Organizations
+          WHERE organization_id = $1
+        "#,
+            organization_id
+        )
+        .fetch_one(state.db())
+        .await
+        .into_diagnostic()
+        .map_err(|err| AppError::from(err).into_response())?;
+
+        Ok(Organization {
+            organization_id,
+            name,
+            role,
+        })
+    }
+}
+
+struct Member {
+    user_id: Uuid,
+    role: String,
+    email: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+struct PendingInvitation {
+    organization_invitation_id: Uuid,
+    email: String,
+    role: String,
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+fn invitation_url(state: &AppState, token: &str) -> String {
+    format!("{}/invitations/{token}", state.base_url)
+}
+
+pub async fn show(
+    organization: Organization,
+    session: DBSession,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let members = sqlx::query_as!(
+        Member,
+        r#"
+    SELECT user_id, role, email, created_at
+    FROM OrganizationMemberships
+    WHERE organization_id = $1
+    ORDER BY created_at ASC
+  "#,
+        organization.organization_id
+    )
+    .fetch_all(state.db())
+    .await
+    .into_diagnostic()?;
+
+    let invitations = sqlx::query_as!(
+        PendingInvitation,
+        r#"
+    SELECT organization_invitation_id, email, role, token, expires_at
+    FROM OrganizationInvitations
+    WHERE organization_id = $1
+      AND accepted_at IS NULL
+      AND expires_at > now()
+    ORDER BY created_at ASC
+  "#,
+        organization.organization_id
+    )
+    .fetch_all(state.db())
+    .await
+    .into_diagnostic()?;
+
+    let sites = sqlx::query!(
+        r#"
+    SELECT site_id, name
+    FROM Sites
+    WHERE organization_id = $1
+    ORDER BY name ASC
+  "#,
+        organization.organization_id
+    )
+    .fetch_all(state.db())
+    .await
+    .into_diagnostic()?;
+
+    let organization_id = organization.organization_id;
+    let is_owner = organization.role >= Role::Owner;
+
+    Ok(html! {
+      h1 { (organization.name) }
+
+      p { "Your role: " (organization.role.label()) }
+
+      h2 { "Sites" }
+
+      ul {
+        @for site in sites {
+          li {
+            a href=(format!("/my/sites/{}", site.site_id)) { (site.name) }
+          }
+        }
+      }
+
+      h2 { "Members" }
+
+      table."min-w-full divide-y divide-gray-300" {
+        thead {
+          tr {
+            th."text-left" { "Member" }
+            th."text-left" { "Role" }
+            th."text-left" { "Joined" }
+            @if is_owner {
+              th {}
+            }
+          }
+        }
+        tbody {
+          @for member in members {
+            tr {
+              td {
+                (member.email.as_deref().unwrap_or("Unknown email"))
+                @if member.user_id == session.user_id {
+                  " (you)"
+                }
+              }
+              td {
+                @if is_owner {
+                  form method="post" action=(format!("/my/organizations/{organization_id}/members/{}", member.user_id)) {
+                    select name="role" {
+                      @for role in Role::ALL {
+                        option value=(role.as_str()) selected[member.role == role.as_str()] { (role.label()) }
+                      }
+                    }
+
+                    button type="submit" { "Change" }
+                  }
+                } @else {
+                  (member.role)
+                }
+              }
+              td { (member.created_at.format("%Y-%m-%d")) }
+              @if is_owner {
+                td {
+                  form method="post" action=(format!("/my/organizations/{organization_id}/members/{}/delete", member.user_id)) {
+                    button type="submit" { "Remove" }
+                  }
+                }
+              }
+            }
+          }
+        }
+      }
+
+      @if !is_owner {
+        form method="post" action=(format!("/my/organizations/{organization_id}/members/{}/delete", session.user_id)) {
+          button type="submit" { "Leave organization" }
+        }
+      }
+
+      @if is_owner {
+        h2 { "Invitations" }
+
+        @if invitations.is_empty() {
+          p { "No pending invitations" }
+        } @else {
+          ul {
+            @for invitation in invitations {
+              li {
+                (invitation.email) " as " (invitation.role) ", expires " (invitation.expires_at.format("%Y-%m-%d"))
+
+                input type="text" readonly value=(invitation_url(&state, &invitation.token));
+
+                form method="post" action=(format!("/my/organizations/{organization_id}/invitations/{}/delete", invitation.organization_invitation_id)) {
+                  button type="submit" { "Revoke" }
+                }
+              }
+            }
+          }
+        }
+
+        form method="post" action=(format!("/my/organizations/{organization_id}/invitations")) {
+          label {
+            "Email"
+            input type="email" name="email" required;
+          }
+
+          label {
+            "Role"
+            select name="role" {
+              @for role in Role::ALL {
+                option value=(role.as_str()) selected[role == Role::Viewer] { (role.label()) }
+              }
+            }
+          }
+
+          button type="submit" { "Invite" }
+        }
+      }
+    }
+    .into_template(state, Some(session))
+    .await?
+    .into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub struct MemberPath {
+    user_id: Uuid,
+}
+
+#[derive(serde::Deserialize)]
+pub struct MemberFormData {
+    role: Role,
+}
+
+/// Whether the user is the only owner left, who can't be demoted or removed
+/// without leaving the organization unmanageable
+async fn is_last_owner(db: &PgPool, organization_id: Uuid, user_id: Uuid) -> miette::Result<bool> {
+    let other_owners = sqlx::query_scalar!(
+        r#"
+    SELECT count(*) as "count!"
+    FROM OrganizationMemberships
+    WHERE organization_id = $1 AND role = $2 AND user_id <> $3
+  "#,
+        organization_id,
+        Role::Owner.as_str(),
+        user_id
+    )
+    .fetch_one(db)
+    .await
+    .into_diagnostic()?;
+
+    let role = authz::organization_role(db, user_id, organization_id).await?;
+
+    Ok(role == Some(Role::Owner) && other_owners == 0)
+}
+
+fn last_owner_error() -> AppError {
+    AppError::Invalid("Every organization needs at least one owner".to_string())
+}
+
+pub async fn update_member(
+    organization: Organization,
+    State(state): State<AppState>,
+    Path(MemberPath { user_id }): Path<MemberPath>,
+    Form(form_data): Form<MemberFormData>,
+) -> Result<Response, AppError> {
+    organization.require_role(Role::Owner)?;
+
+    if form_data.role != Role::Owner
+        && is_last_owner(state.db(), organization.organization_id, user_id).await?
+    {
+        return Err(last_owner_error());
+    }
+
+    let mut tx = state.db().begin().await.into_diagnostic()?;
+
+    // Promoting someone counts against their own plan, the same as if they
+    // had created the organization themselves
+    let current_role =
+        authz::organization_role(state.db(), user_id, organization.organization_id).await?;
+    if form_data.role == Role::Owner && current_role != Some(Role::Owner) {
+        plans::lock_owner_usage(&mut tx, &state.auth, user_id)
+            .await?
+            .require_organization_room_for_member()?;
+    }
+
+    sqlx::query!(
+        r#"
+    UPDATE OrganizationMemberships
+    SET role = $1
+    WHERE organization_id = $2 AND user_id = $3
+  "#,
+        form_data.role.as_str(),
+        organization.organization_id,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await
+    .into_diagnostic()?;
+
+    tx.commit().await.into_diagnostic()?;
+
+    Ok(Redirect::to(&format!(
+        "/my/organizations/{}",
+        organization.organization_id
+    ))
+    .into_response())
+}
+
+/// Owners can remove anyone, and everyone else can only remove themselves
+pub async fn remove_member(
+    organization: Organization,
+    session: DBSession,
+    State(state): State<AppState>,
+    Path(MemberPath { user_id }): Path<MemberPath>,
+) -> Result<Response, AppError> {
+    if user_id != session.user_id {
+        organization.require_role(Role::Owner)?;
+    }
+
+    if is_last_owner(state.db(), organization.organization_id, user_id).await? {
+        return Err(last_owner_error());
+    }
+
+    sqlx::query!(
+        r#"
+    DELETE FROM OrganizationMemberships
+    WHERE organization_id = $1 AND user_id = $2
+  "#,
+        organization.organization_id,
+        user_id
+    )
+    .execute(state.db())
+    .await
+    .into_diagnostic()?;
+
+    if user_id == session.user_id {
+        Ok(Redirect::to("/my/organizations").into_response())
+    } else {
+        Ok(Redirect::to(&format!(
+            "/my/organizations/{}",
+            organization.organization_id
+        ))
+        .into_response())
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct InvitationFormData {
+    email: String,
+    role: Role,
+}
+
+pub async fn invite(
+    organization: Organization,
+    session: DBSession,
+    State(state): State<AppState>,
+    Form(form_data): Form<InvitationFormData>,
+) -> Result<Response, AppError> {
+    organization.require_role(Role::Owner)?;
+
+    let email = form_data.email.trim();
+    if email.parse::<lettre::Address>().is_err() {
+        return Err(AppError::Invalid("Invalid email address".to_string()));
+    }
+
+    let token = Uuid::new_v4().simple().to_string();
+
+    sqlx::query!(
+        r#"
+    INSERT INTO OrganizationInvitations (organization_id, email, role, token, invited_by_user_id, expires_at)
+    VALUES ($1, $2, $3, $4, $5, $6)
+  "#,
+        organization.organization_id,
+        email,
+        form_data.role.as_str(),
+        token,
+        session.user_id,
+        Utc::now() + invitation_expiry()
+    )
+    .execute(state.db())
+    .await
+    .into_diagnostic()?;
+
+    // The link is also listed on the organization page, so it can be shared
+    // by hand when email isn't set up or doesn't arrive
+    if let Some(mailer) = state.mailer.as_ref() {
+        let url = invitation_url(&state, &token);
+        let html = html! {
+          p {
+            "You've been invited to join " (organization.name) " on UpGuardian as "
+            (form_data.role.as_str()) "."
+          }
+
+          p { a href=(url) { "Accept the invitation" } }
+
+          p { (format!("This link expires in {} days.", invitation_expiry().num_days())) }
+        };
+
+        if let Err(err) = mailer
+            .send_html(
+                email,
+                &format!("Join {} on UpGuardian", organization.name),
+                html.into_string(),
+                None,
+            )
+            .await
+        {
+            tracing::error!(?err, email, "Failed to send invitation");
+        }
+    }
+
+    Ok(Redirect::to(&format!(
+        "/my/organizations/{}",
+        organization.organization_id
+    ))
+    .into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub struct InvitationPath {
+    organization_invitation_id: Uuid,
+}
+
+pub async fn revoke_invitation(
+    organization: Organization,
+    State(state): State<AppState>,
+    Path(InvitationPath {
+        organization_invitation_id,
+    }): Path<InvitationPath>,
+) -> Result<Response, AppError> {
+    organization.require_role(Role::Owner)?;
+
+    sqlx::query!(
+        r#"
+    DELETE FROM OrganizationInvitations
+    WHERE organization_invitation_id = $1 AND organization_id = $2
+  "#,
+        organization_invitation_id,
+        organization.organization_id
+    )
+    .execute(state.db())
+    .await
+    .into_diagnostic()?;
+
+    Ok(Redirect::to(&format!(
+        "/my/organizations/{}",
+        organization.organization_id
+    ))
+    .into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub struct TokenPath {
+    token: String,
+}
+
+struct Invitation {
+    organization_invitation_id: Uuid,
+    organization_id: Uuid,
+    organization_name: String,
+    email: String,
+    role: String,
+}
+
+/// Only invitations that haven't been used or expired can be seen or accepted
+async fn find_invitation(db: &PgPool, token: &str) -> miette::Result<Option<Invitation>> {
+    sqlx::query_as!(
+        Invitation,
+        r#"
+    SELECT
+      OrganizationInvitations.organization_invitation_id,
+      OrganizationInvitations.organization_id,
+      Organizations.name as organization_name,
+      OrganizationInvitations.email,
+      OrganizationInvitations.role
+    FROM OrganizationInvitations
+    JOIN Organizations USING (organization_id)
+    WHERE OrganizationInvitations.token = $1
+      AND OrganizationInvitations.accepted_at IS NULL
+      AND OrganizationInvitations.expires_at > now()
+  "#,
+        token
+    )
+    .fetch_optional(db)
+    .await
+    .into_diagnostic()
+}
+
+fn invalid_invitation() -> AppError {
+    AppError::Gone("This invitation has already been used or has expired".to_string())
+}
+
+pub async fn show_invitation(
+    session: DBSession,
+    State(state): State<AppState>,
+    Path(TokenPath { token }): Path<TokenPath>,
+) -> Result<Response, AppError> {
+    let Some(invitation) = find_invitation(state.db(), &token).await? else {
+        return Err(invalid_invitation());
+    };
+
+    Ok(html! {
+      h1 { "Join " (invitation.organization_name) }
+
+      p {
+        "This invitation was sent to " (invitation.email) " to join as " (invitation.role) "."
+      }
+
+      form method="post" action=(format!("/invitations/{token}/accept")) {
+        button type="submit" { "Accept" }
+      }
+    }
+    .into_template(state, Some(session))
+    .await?
+    .into_response())
+}
+
+pub async fn accept_invitation(
+    session: DBSession,
+    State(state): State<AppState>,
+    Path(TokenPath { token }): Path<TokenPath>,
+) -> Result<Response, AppError> {
+    let Some(invitation) = find_invitation(state.db(), &token).await? else {
+        return Err(invalid_invitation());
+    };
+
+    let mut tx = state.db().begin().await.into_diagnostic()?;
+
+    // Joining as an owner counts against the accepting user's own plan.
+    // Someone who is already a member keeps their role, so it only matters
+    // when they're new here
+    let current_role =
+        authz::organization_role(state.db(), session.user_id, invitation.organization_id).await?;
+    if invitation.role == Role::Owner.as_str() && current_role.is_none() {
+        plans::lock_owner_usage(&mut tx, &state.auth, session.user_id)
+            .await?
+            .require_organization_room()?;
+    }
+
+    // Marking it accepted first means two people racing with the same link
+    // can't both join
+    let claimed = sqlx::query!(
+        r#"
+    UPDATE OrganizationInvitations
+    SET accepted_at = now()
+    WHERE organization_invitation_id = $1 AND accepted_at IS NULL
+  "#,
+        invitation.organization_invitation_id
+    )
+    .execute(&mut *tx)
+    .await
+    .into_diagnostic()?
+    .rows_affected();
+
+    if claimed == 0 {
+        return Err(invalid_invitation());
+    }
+
+    // Someone who is already a member keeps the role they have
+    sqlx::query!(
+        r#"
+    INSERT INTO OrganizationMemberships (organization_id, user_id, role, email)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (organization_id, user_id) DO NOTHING
+  "#,
+        invitation.organization_id,
+        session.user_id,
+        invitation.role,
+        invitation.email
+    )
+    .execute(&mut *tx)
+    .await
+    .into_diagnostic()?;
+
+    tx.commit().await.into_diagnostic()?;
+
+    Ok(Redirect::to(&format!("/my/organizations/{}", invitation.organization_id)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::{
+        auth::{coreyja::CoreyjaConfig, AuthProvider},
+        testing::{create_user, post_as, serve},
+    };
+
+    #[sqlx::test]
+    async fn owners_at_their_limit_cant_be_made_owners_elsewhere(pool: PgPool) {
+        let app_state = AppState::for_tests(pool, AuthProvider::Coreyja(CoreyjaConfig::from_env()));
+        let alice = create_user(&app_state, "alice").await;
+        let bob = create_user(&app_state, "bob").await;
+        let bob_team = create_user(&app_state, "bob-team").await;
+        let db = app_state.db().clone();
+
+        // Bob owns two organizations, the most the Free plan allows
+        sqlx::query!(
+            "UPDATE OrganizationMemberships SET user_id = $1 WHERE user_id = $2",
+            bob.user_id,
+            bob_team.user_id
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let base_url = serve(app_state).await;
+
+        let token = Uuid::new_v4().simple().to_string();
+        sqlx::query!(
+            r#"
+    INSERT INTO OrganizationInvitations (organization_id, email, role, token, invited_by_user_id, expires_at)
+    VALUES ($1, 'bob@example.com', $2, $3, $4, now() + interval '1 day')
+  "#,
+            alice.organization_id,
+            Role::Owner.as_str(),
+            token,
+            alice.user_id
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let status = post_as(
+            &base_url,
+            &bob,
+            &format!("/invitations/{token}/accept"),
+            &[],
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(
+            authz::organization_role(&db, bob.user_id, alice.organization_id)
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(find_invitation(&db, &token).await.unwrap().is_some());
+
+        sqlx::query!(
+            r#"
+    INSERT INTO OrganizationMemberships (organization_id, user_id, role)
+    VALUES ($1, $2, $3)
+  "#,
+            alice.organization_id,
+            bob.user_id,
+            Role::Editor.as_str()
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let status = post_as(
+            &base_url,
+            &alice,
+            &format!(
+                "/my/organizations/{}/members/{}",
+                alice.organization_id, bob.user_id
+            ),
+            &[("role", Role::Owner.as_str())],
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(
+            authz::organization_role(&db, bob.user_id, alice.organization_id)
+                .await
+                .unwrap(),
+            Some(Role::Editor)
+        );
+    }
+}
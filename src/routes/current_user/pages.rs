@@ -1,80 +1,227 @@
-use std::{fmt::Display, time::Duration};
+use std::{fmt::Display, ops::Range, time::Duration};
 
 use axum::{
-    extract::{Path, Query, State},
-    response::{IntoResponse, Redirect},
+    extract::{FromRequestParts, Path, Query, State},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Redirect, Response},
     Form,
 };
 use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use cja::{app_state::AppState as _, server::session::DBSession};
 use maud::{html, Render};
+use miette::IntoDiagnostic;
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::types::PgInterval;
 use uuid::Uuid;
 
 use crate::{
-    app_state::AppState, routes::current_user::sites::single_stat, templates::IntoTemplate,
+    app_state::AppState,
+    audit::{AuditAction, AuditChange, AuditEntity},
+    authz::{self, Role},
+    errors::AppError,
+    incidents::incidents_for_range,
+    plans,
+    routes::current_user::sites::single_stat,
+    stats::{
+        checkin_buckets, stats_for_range, uptime_buckets, CheckinBucket, Percentile, Resolution,
+        StatsScope,
+    },
+    templates::IntoTemplate,
+    validation::{self, field_error, UrlTest},
 };
 
-use super::sites::{
-    avg_response_time_for_checkins, calculate_percentile_change, calculate_response_time_change,
-    chrono_to_pg_interval, success_percent_for_checkins, Site,
+use super::{
+    exports::export_form,
+    sites::{
+        calculate_percentile_change, calculate_response_time_change, format_response_time,
+        percentile_stats, Site,
+    },
+    time_range::TimeRangeQuery,
+    uptime_bar::UptimeBar,
 };
 
 pub async fn new(
     site: Site,
     session: DBSession,
     State(app_state): State<AppState>,
-) -> impl IntoResponse {
-    html! {
+) -> Result<Response, AppError> {
+    site.require_role(Role::Editor)?;
+
+    page_form(app_state, session, &site, PageFormState::default()).await
+}
+
+/// What the new page form is showing, so it can be shown again with the
+/// validation errors or test result after a submit
+#[derive(Default)]
+struct PageFormState {
+    path: String,
+    name: String,
+    path_error: Option<String>,
+    test: Option<UrlTest>,
+}
+
+impl From<PageFormData> for PageFormState {
+    fn from(form_data: PageFormData) -> Self {
+        Self {
+            path: form_data.path,
+            name: form_data.name,
+            path_error: None,
+            test: None,
+        }
+    }
+}
+
+async fn page_form(
+    app_state: AppState,
+    session: DBSession,
+    site: &Site,
+    form: PageFormState,
+) -> Result<Response, AppError> {
+    let usage =
+        plans::organization_usage(app_state.db(), &app_state.auth, site.organization_id).await?;
+
+    let status = if form.path_error.is_some() {
+        StatusCode::UNPROCESSABLE_ENTITY
+    } else {
+        StatusCode::OK
+    };
+
+    let page = html! {
       h1 { "New Page" }
 
+      p."text-sm text-gray-500" {
+        "This organization is using " (usage.pages_summary()) " on the " (usage.plan.label()) " plan."
+      }
+
+      @if usage.at_page_limit() {
+        @if let Some(upgrade_message) = usage.plan.upgrade_message() {
+          p { (upgrade_message) }
+        }
+      }
+
       form method="post" action=(format!("/my/sites/{}/pages", site.site_id)) {
         label {
           "Path"
-          input type="text" name="path" required;
+          input type="text" name="path" value=(form.path) placeholder="/" required;
         }
+        (field_error(form.path_error.as_deref()))
 
         label {
           "Name"
-          input type="text" name="name" required;
+          input type="text" name="name" value=(form.name) required;
+        }
+
+        @if let Some(test) = &form.test {
+          (test)
         }
 
+        button type="submit" name="intent" value="test" { "Test this URL now" }
         button type="submit" { "Create" }
       }
     }
     .into_template(app_state, Some(session))
-    .await
-    .unwrap()
+    .await?;
+
+    Ok((status, page).into_response())
 }
 
 #[derive(serde::Deserialize)]
 pub struct PageFormData {
     path: String,
     name: String,
+    /// Set by the "Test this URL now" button, which checks the page instead
+    /// of saving it
+    #[serde(default)]
+    intent: Option<String>,
 }
 
 pub async fn create(
     site: Site,
+    session: DBSession,
     State(state): State<AppState>,
     Form(form_data): Form<PageFormData>,
-) -> impl IntoResponse {
+) -> Result<Response, AppError> {
+    site.require_role(Role::Editor)?;
+
     let site_id = site.site_id;
 
-    sqlx::query!(
+    let path = match validation::normalize_path(&form_data.path) {
+        Ok(path) => path,
+        Err(error) => {
+            let form = PageFormState {
+                path_error: Some(error),
+                ..PageFormState::from(form_data)
+            };
+            return page_form(state, session, &site, form).await;
+        }
+    };
+
+    if form_data.intent.as_deref() == Some("test") {
+        let test = UrlTest::run(validation::check_url(&site.domain, &path)).await;
+        let form = PageFormState {
+            path,
+            test: Some(test),
+            ..PageFormState::from(form_data)
+        };
+        return page_form(state, session, &site, form).await;
+    }
+
+    let mut tx = state.db().begin().await.into_diagnostic()?;
+
+    plans::lock_usage(&mut tx, &state.auth, site.organization_id)
+        .await?
+        .require_page_room()?;
+
+    let path_taken = sqlx::query_scalar!(
+        r#"
+    SELECT EXISTS (SELECT 1 FROM Pages WHERE site_id = $1 AND path = $2) as "exists!"
+  "#,
+        site_id,
+        path
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .into_diagnostic()?;
+    if path_taken {
+        let form = PageFormState {
+            path,
+            path_error: Some("This site already has a page at that path".to_string()),
+            ..PageFormState::from(form_data)
+        };
+        return page_form(state, session, &site, form).await;
+    }
+
+    let page = sqlx::query!(
         r#"
     INSERT INTO Pages (site_id, path, name)
     VALUES ($1, $2, $3)
+    RETURNING page_id, to_jsonb(Pages) as "after!"
   "#,
         site_id,
-        form_data.path,
+        path,
         form_data.name
     )
-    .execute(state.db())
+    .fetch_one(&mut *tx)
     .await
-    .unwrap();
+    .into_diagnostic()?;
 
-    Redirect::to(&format!("/my/sites/{}", site_id,))
+    AuditChange {
+        organization_id: site.organization_id,
+        site_id,
+        actor_user_id: session.user_id,
+        action: AuditAction::Create,
+        entity: AuditEntity::Page,
+        entity_id: page.page_id,
+        before: None,
+        after: Some(page.after),
+    }
+    .record(&mut *tx)
+    .await?;
+
+    tx.commit().await.into_diagnostic()?;
+
+    Ok(Redirect::to(&format!("/my/sites/{}", site_id,)).into_response())
 }
 
 pub struct Page {
@@ -85,121 +232,175 @@ pub struct Page {
 }
 
 #[derive(serde::Deserialize)]
-pub struct PagePath {
+struct PageParams {
+    site_id: Uuid,
     page_id: Uuid,
 }
 
+#[async_trait::async_trait]
+impl FromRequestParts<AppState> for Page {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let session = DBSession::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let Path(PageParams { site_id, page_id }) =
+            Path::<PageParams>::from_request_parts(parts, state)
+                .await
+                .map_err(|_| AppError::NotFound.into_response())?;
+
+        authz::require_site_role(state.db(), session.user_id, site_id, Role::Viewer)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        // A page from some other site is a 404 too, even one the user can see
+        let page = sqlx::query_as!(
+            Page,
+            r#"
+          SELECT Pages.*
+          FROM Pages
+          WHERE Pages.page_id = $1 AND Pages.site_id = $2
+        "#,
+            page_id,
+            site_id
+        )
+        .fetch_optional(state.db())
+        .await
+        .into_diagnostic()
+        .map_err(|err| AppError::from(err).into_response())?;
+
+        page.ok_or_else(|| AppError::NotFound.into_response())
+    }
+}
+
 pub async fn show(
     site: Site,
+    page: Page,
     State(state): State<AppState>,
-    Path(PagePath { page_id }): Path<PagePath>,
     session: DBSession,
-) -> impl IntoResponse {
-    let page = sqlx::query_as!(
-        Page,
-        r#"
-    SELECT Pages.*
-    FROM Pages
-    JOIN Sites ON Sites.site_id = Pages.site_id
-    WHERE Pages.page_id = $1 AND Pages.site_id = $2 AND Sites.user_id = $3
-  "#,
-        page_id,
-        site.site_id,
-        session.user_id
-    )
-    .fetch_one(state.db())
-    .await
-    .unwrap();
-
-    let all_checkins = sqlx::query_as!(
-        Checkin,
-        r#"
-      SELECT *
-      FROM Checkins
-      WHERE page_id = $1
-      AND created_at >= now() - INTERVAL '12 hours'
-      AND duration_nanos is not null
-      ORDER BY created_at DESC
-    "#,
-        page_id
-    )
-    .fetch_all(state.db())
-    .await
-    .unwrap();
-    let recent_duration = chrono::Duration::hours(6);
-    let split_point = all_checkins
-        .iter()
-        .position(|checkin| checkin.created_at < chrono::Utc::now() - recent_duration)
-        .unwrap_or(all_checkins.len());
-
-    let mut new_checkins = all_checkins;
-    let old_checkins = new_checkins.split_off(split_point);
-    let new_checkins = new_checkins;
+    Query(query): Query<GraphQuery>,
+) -> Result<Response, AppError> {
+    let range = query.range.range(default_page_range())?;
 
-    let avg_response_time = avg_response_time_for_checkins(&new_checkins);
+    let uptime_bar = page_uptime_bar(&site, page.page_id, &state).await?;
+    let graphs = page_graphs(page.page_id, &state, range, query.series, query.range.tz()).await?;
 
-    let response_time_change = calculate_response_time_change(&old_checkins, avg_response_time);
-
-    let avg_response_time = format!("{:.1} ms", avg_response_time);
-
-    let succesful_percent = success_percent_for_checkins(&new_checkins);
-    let successful_percent_change =
-        calculate_percentile_change(old_checkins, &new_checkins, succesful_percent);
-    let succesful_percent = format!("{:.1}%", succesful_percent);
-
-    let mut checkins_for_graph = new_checkins.clone();
-    checkins_for_graph.reverse();
-    let graph = SampledCheckinGraph {
-        checkins: checkins_for_graph,
-        number_of_chunks: 20,
-        range: Some(chrono::Utc::now() - recent_duration..chrono::Utc::now()),
-    };
-
-    html! {
+    Ok(html! {
       h1 { (page.name) }
 
       p { (page.path) }
 
+      a href=(format!("/my/compare?page_id={}", page.page_id)) { "Compare with other pages" }
+
+      h2 { "Last 24 Hours" }
+      (uptime_bar)
+
       h2 { "Checkins" }
 
-      form action=(format!("/my/sites/{}/pages/{}/refresh", site.site_id, page.page_id)) method="get" data-target=".refresh" data-app="LiveForm" {
-        select name="hours" {
-          option value="6" { "6 hours" }
-          option value="12" { "12 hours" }
-          option value="24" { "24 hours" }
-          option value="48" { "48 hours" }
-          option value="168" { "1 week" }
+      form action=(format!("/my/sites/{}/pages/{}/refresh", site.site_id, page.page_id)) method="get" data-target=".refresh" data-app="LiveForm" data-live-page=(page.page_id) data-sync-url {
+        (query.range.form_fields("Last 6 hours"))
+
+        select name="series" {
+          @for (series, label) in [
+            (GraphSeries::Avg, "Average"),
+            (GraphSeries::P50, "p50"),
+            (GraphSeries::P95, "p95"),
+            (GraphSeries::P99, "p99"),
+          ] {
+            option value=(series.as_str()) selected[query.series == series] { (label) }
+          }
         }
 
         div class="refresh" {
-
-            dl."mt-5 grid grid-cols-1 gap-5 sm:grid-cols-2 lg:grid-cols-3" {
-                (
-                single_stat("# of Checkins", new_checkins.len(), None, "fa-file")
-                )
-                (
-                single_stat("Avg. Response Time", avg_response_time, response_time_change, "fa-clock")
-                )
-                (
-                single_stat("Success Rate", succesful_percent, successful_percent_change, "fa-check")
-                )
-            }
-
-            div class="graph" {
-            (graph)
-            }
+          (graphs)
         }
-
       }
+
+      (export_form(&format!("/my/sites/{}/pages/{}/checkins/export", site.site_id, page.page_id), "Export Checkins"))
     }
     .into_template(state, Some(session))
-    .await
-    .unwrap()
+    .await?
+    .into_response())
+}
+
+async fn page_uptime_bar(
+    site: &Site,
+    page_id: Uuid,
+    state: &AppState,
+) -> miette::Result<UptimeBar> {
+    let now = Utc::now();
+    let range = now - chrono::Duration::hours(23)..now;
+
+    let buckets = uptime_buckets(
+        state.db(),
+        StatsScope::Page(page_id),
+        range.clone(),
+        Resolution::Hour,
+    )
+    .await?;
+    let incidents = incidents_for_range(state.db(), site.site_id, range)
+        .await?
+        .into_iter()
+        .filter(|incident| incident.page_id == page_id)
+        .collect();
+
+    Ok(UptimeBar {
+        buckets,
+        resolution: Resolution::Hour,
+        incidents,
+    })
+}
+
+/// How much of the history a page shows when no range is picked
+fn default_page_range() -> chrono::Duration {
+    chrono::Duration::hours(6)
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// How many points the graphs plot, however long the range is
+pub const GRAPH_BUCKETS: i32 = 40;
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct GraphQuery {
-    hours: i32,
+    #[serde(flatten)]
+    range: TimeRangeQuery,
+    #[serde(default)]
+    series: GraphSeries,
+}
+
+/// Which statistic the main line of the graph plots for each chunk
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphSeries {
+    #[default]
+    Avg,
+    P50,
+    P95,
+    P99,
+}
+
+impl GraphSeries {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GraphSeries::Avg => "avg",
+            GraphSeries::P50 => "p50",
+            GraphSeries::P95 => "p95",
+            GraphSeries::P99 => "p99",
+        }
+    }
+
+    fn percentile(&self) -> Option<Percentile> {
+        match self {
+            GraphSeries::Avg => None,
+            GraphSeries::P50 => Some(Percentile::P50),
+            GraphSeries::P95 => Some(Percentile::P95),
+            GraphSeries::P99 => Some(Percentile::P99),
+        }
+    }
 }
 
 pub trait FromHours {
@@ -217,63 +418,93 @@ impl FromHours for PgInterval {
 }
 
 pub async fn refresh(
+    page: Page,
     State(state): State<AppState>,
-    Path(PagePath { page_id }): Path<PagePath>,
-    _session: DBSession,
-    Query(GraphQuery { hours }): Query<GraphQuery>,
-) -> impl IntoResponse {
-    let recent_duration = chrono::Duration::hours(hours.into());
-    let interval = chrono_to_pg_interval(recent_duration * 2);
-
-    let all_checkins = sqlx::query_as!(
-        Checkin,
-        r#"
-  SELECT *
-  FROM Checkins
-  WHERE page_id = $1
-  AND now() - created_at <= $2
-  AND duration_nanos is not null
-  ORDER BY created_at DESC
-"#,
-        page_id,
-        interval
+    Query(query): Query<GraphQuery>,
+) -> Result<Response, AppError> {
+    let range = query.range.range(default_page_range())?;
+
+    Ok(
+        page_graphs(page.page_id, &state, range, query.series, query.range.tz())
+            .await?
+            .0
+            .into_response(),
     )
-    .fetch_all(state.db())
-    .await
-    .unwrap();
-    let split_point = all_checkins
-        .iter()
-        .position(|checkin| checkin.created_at < chrono::Utc::now() - recent_duration)
-        .unwrap_or(all_checkins.len());
+}
+
+async fn page_graphs(
+    page_id: Uuid,
+    state: &AppState,
+    range: Range<DateTime<Utc>>,
+    series: GraphSeries,
+    tz: Tz,
+) -> miette::Result<maud::Markup> {
+    let buckets = checkin_buckets(
+        state.db(),
+        StatsScope::Page(page_id),
+        range.clone(),
+        GRAPH_BUCKETS,
+        state.checkin_retention,
+    )
+    .await?;
+
+    let breakdown = TimingBreakdownGraph {
+        buckets: buckets.clone(),
+        range: range.clone(),
+        tz,
+    };
+    let graph = BucketedCheckinGraph {
+        buckets,
+        range: range.clone(),
+        series,
+        tz,
+    };
 
-    let mut new_checkins = all_checkins;
-    let old_checkins = new_checkins.split_off(split_point);
-    let new_checkins = new_checkins;
+    let stats_overview = page_stats_overview(page_id, state, range).await?;
 
-    let avg_response_time = avg_response_time_for_checkins(&new_checkins);
+    Ok(html! {
+       (stats_overview)
 
-    let response_time_change = calculate_response_time_change(&old_checkins, avg_response_time);
+       div class="graph" {
+        (graph)
+       }
 
-    let avg_response_time = format!("{:.1} ms", avg_response_time);
+       h3 { "Timing Breakdown" }
 
-    let succesful_percent = success_percent_for_checkins(&new_checkins);
-    let successful_percent_change =
-        calculate_percentile_change(old_checkins, &new_checkins, succesful_percent);
-    let succesful_percent = format!("{:.1}%", succesful_percent);
+       div class="breakdown" {
+        (breakdown)
+       }
+    })
+}
+
+/// Stats for `range`, compared to the range of the same length right before it
+async fn page_stats_overview(
+    page_id: Uuid,
+    state: &AppState,
+    range: Range<DateTime<Utc>>,
+) -> miette::Result<maud::Markup> {
+    let scope = StatsScope::Page(page_id);
+    let previous_range = range.start - (range.end - range.start)..range.start;
+    let new_stats = stats_for_range(state.db(), scope, range, state.checkin_retention).await?;
+    let old_stats =
+        stats_for_range(state.db(), scope, previous_range, state.checkin_retention).await?;
 
-    let mut checkins_for_graph = new_checkins.clone();
+    let avg_response_time = new_stats.avg_response_time_ms();
 
-    checkins_for_graph.reverse();
-    let graph = SampledCheckinGraph {
-        checkins: checkins_for_graph,
-        number_of_chunks: 20,
-        range: Some(chrono::Utc::now() - recent_duration..chrono::Utc::now()),
-    };
+    let response_time_change =
+        calculate_response_time_change(old_stats.avg_response_time_ms(), avg_response_time);
+
+    let avg_response_time = format_response_time(avg_response_time);
+
+    let succesful_percent = new_stats.success_percent();
+    let successful_percent_change =
+        calculate_percentile_change(&old_stats, &new_stats, succesful_percent);
+    let succesful_percent = format!("{:.1}%", succesful_percent);
 
-    html! {
+    Ok(html! {
        dl."mt-5 grid grid-cols-1 gap-5 sm:grid-cols-2 lg:grid-cols-3" {
            (
-           single_stat("# of Checkins", new_checkins.len(), None, "fa-file")
+           single_stat("# of Checkins", new_stats.count, None, "fa-file")
            )
            (
            single_stat("Avg. Response Time", avg_response_time, response_time_change, "fa-clock")
@@ -281,13 +512,9 @@ pub async fn refresh(
            (
            single_stat("Success Rate", succesful_percent, successful_percent_change, "fa-check")
            )
+           (percentile_stats(&old_stats, &new_stats))
        }
-
-       div class="graph" {
-        (graph)
-       }
-    }
-    .0
+    })
 }
 
 struct CheckinTable(Vec<Checkin>);
@@ -321,17 +548,11 @@ pub struct Checkin {
     pub status_code: Option<i32>,
     pub duration_nanos: Option<i64>,
     pub created_at: DateTime<Utc>,
-}
-
-struct SimpleCheckinGraph {
-    checkins: Vec<Checkin>,
-    range: Option<std::ops::Range<DateTime<Utc>>>,
-}
-
-struct SampledCheckinGraph {
-    checkins: Vec<Checkin>,
-    number_of_chunks: usize,
-    range: Option<std::ops::Range<DateTime<Utc>>>,
+    pub dns_nanos: Option<i64>,
+    pub connect_nanos: Option<i64>,
+    pub tls_nanos: Option<i64>,
+    pub ttfb_nanos: Option<i64>,
+    pub download_nanos: Option<i64>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -351,6 +572,145 @@ struct XAxisTicks {
     width: usize,
     x_range: std::ops::Range<DateTime<Utc>>,
     number_of_ticks: usize,
+    /// The timezone the tick labels are shown in
+    tz: Tz,
+}
+
+/// The phases of a checkin request, in the order they happen
+#[derive(Debug, Clone, Copy)]
+enum TimingPhase {
+    Dns,
+    Connect,
+    Tls,
+    Ttfb,
+    Download,
+}
+
+impl TimingPhase {
+    const ALL: [TimingPhase; 5] = [
+        TimingPhase::Dns,
+        TimingPhase::Connect,
+        TimingPhase::Tls,
+        TimingPhase::Ttfb,
+        TimingPhase::Download,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            TimingPhase::Dns => "DNS",
+            TimingPhase::Connect => "Connect",
+            TimingPhase::Tls => "TLS",
+            TimingPhase::Ttfb => "TTFB",
+            TimingPhase::Download => "Download",
+        }
+    }
+
+    fn fill_class(&self) -> &'static str {
+        match self {
+            TimingPhase::Dns => "fill-sky-400",
+            TimingPhase::Connect => "fill-indigo-400",
+            TimingPhase::Tls => "fill-violet-400",
+            TimingPhase::Ttfb => "fill-amber-300",
+            TimingPhase::Download => "fill-emerald-400",
+        }
+    }
+
+    fn bg_class(&self) -> &'static str {
+        match self {
+            TimingPhase::Dns => "bg-sky-400",
+            TimingPhase::Connect => "bg-indigo-400",
+            TimingPhase::Tls => "bg-violet-400",
+            TimingPhase::Ttfb => "bg-amber-300",
+            TimingPhase::Download => "bg-emerald-400",
+        }
+    }
+
+    fn avg_nanos(&self, bucket: &CheckinBucket) -> Option<f64> {
+        match self {
+            TimingPhase::Dns => bucket.avg_dns_nanos,
+            TimingPhase::Connect => bucket.avg_connect_nanos,
+            TimingPhase::Tls => bucket.avg_tls_nanos,
+            TimingPhase::Ttfb => bucket.avg_ttfb_nanos,
+            TimingPhase::Download => bucket.avg_download_nanos,
+        }
+    }
+}
+
+/// Stacked area graph of the average time spent in each [`TimingPhase`]
+struct TimingBreakdownGraph {
+    buckets: Vec<CheckinBucket>,
+    range: Range<DateTime<Utc>>,
+    tz: Tz,
+}
+
+/// Graphs the duration of each [`CheckinBucket`] across `range`, as a
+/// [`ResponseTimeGraph`] with a single line
+pub struct BucketedCheckinGraph {
+    pub buckets: Vec<CheckinBucket>,
+    pub range: Range<DateTime<Utc>>,
+    pub series: GraphSeries,
+    pub tz: Tz,
+}
+
+/// One line on a [`ResponseTimeGraph`]
+pub struct GraphLine {
+    pub label: String,
+    pub href: Option<String>,
+    pub buckets: Vec<CheckinBucket>,
+}
+
+/// Overlays the response times of one or more lines on a graph, each in its
+/// own colour from [`LINE_COLORS`]. Several lines get a legend below the
+/// graph, a single one gets the min and max of each bucket drawn dashed
+/// around it instead. The SVG carries the range it covers so it can be zoomed
+/// by dragging across it
+pub struct ResponseTimeGraph {
+    pub lines: Vec<GraphLine>,
+    pub range: Range<DateTime<Utc>>,
+    pub series: GraphSeries,
+    pub tz: Tz,
+}
+
+/// The stroke, point fill and legend classes for each line of a
+/// [`ResponseTimeGraph`], so this is also the most lines it can tell apart
+pub const LINE_COLORS: [(&str, &str, &str); 8] = [
+    ("stroke-blue-500", "fill-blue-500", "bg-blue-500"),
+    ("stroke-orange-500", "fill-orange-500", "bg-orange-500"),
+    ("stroke-emerald-500", "fill-emerald-500", "bg-emerald-500"),
+    ("stroke-rose-500", "fill-rose-500", "bg-rose-500"),
+    ("stroke-violet-500", "fill-violet-500", "bg-violet-500"),
+    ("stroke-cyan-500", "fill-cyan-500", "bg-cyan-500"),
+    ("stroke-yellow-500", "fill-yellow-500", "bg-yellow-500"),
+    ("stroke-gray-700", "fill-gray-700", "bg-gray-700"),
+];
+
+struct SvgArea {
+    top: Vec<(f64, f64)>,
+    bottom: Vec<(f64, f64)>,
+    fill_class: String,
+}
+
+impl Render for SvgArea {
+    fn render(&self) -> maud::Markup {
+        let svg_path = self
+            .top
+            .iter()
+            .chain(self.bottom.iter().rev())
+            .enumerate()
+            .map(|(i, (x, y))| {
+                if i == 0 {
+                    format!("M{x} {y}")
+                } else {
+                    format!("L{x} {y}")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        html! {
+          path d=(format!("{svg_path} Z")) class=(self.fill_class) stroke="none" {}
+        }
+    }
 }
 
 struct SvgPath {
@@ -515,6 +875,7 @@ impl Render for XAxisTicks {
             width,
             x_range,
             number_of_ticks,
+            tz,
         } = self;
 
         let tick_width = *width as f64 / *number_of_ticks as f64;
@@ -525,7 +886,7 @@ impl Render for XAxisTicks {
                 let time = x_range.start.timestamp() as f64
                     + (x / *width as f64) * (x_range.end - x_range.start).num_seconds() as f64;
                 let time = chrono::DateTime::<Utc>::from_timestamp(time as i64, 0).unwrap();
-                let label = time.format("%D %H:%M");
+                let label = time.with_timezone(tz).format("%D %H:%M %Z");
 
                 html! {
                   g {
@@ -562,6 +923,12 @@ impl ToFloat for i64 {
     }
 }
 
+impl ToFloat for f64 {
+    fn to_f64(&self) -> f64 {
+        *self
+    }
+}
+
 impl ToFloat for Duration {
     fn to_f64(&self) -> f64 {
         self.as_nanos() as f64
@@ -601,119 +968,202 @@ where
     (position.to_f64()) / (range_size.to_f64())
 }
 
-impl Render for SimpleCheckinGraph {
+impl Render for BucketedCheckinGraph {
+    fn render(&self) -> maud::Markup {
+        ResponseTimeGraph {
+            lines: vec![GraphLine {
+                label: self.series.as_str().to_string(),
+                href: None,
+                buckets: self.buckets.clone(),
+            }],
+            range: self.range.clone(),
+            series: self.series,
+            tz: self.tz,
+        }
+        .render()
+    }
+}
+
+impl Render for ResponseTimeGraph {
     fn render(&self) -> maud::Markup {
+        let percentile = self.series.percentile();
+        let is_single_line = self.lines.len() == 1;
+        let lines = self
+            .lines
+            .iter()
+            .map(|line| {
+                let values = line
+                    .buckets
+                    .iter()
+                    .filter_map(|bucket| {
+                        Some((bucket.bucket_start, bucket.duration_nanos(percentile)?))
+                    })
+                    .collect::<Vec<_>>();
+
+                (line, values)
+            })
+            .collect::<Vec<_>>();
+
+        // The min and max envelope of a single line is drawn around it, so the
+        // axis has to fit those too
+        let envelope = if is_single_line {
+            self.lines[0]
+                .buckets
+                .iter()
+                .filter_map(|bucket| {
+                    Some((
+                        bucket.bucket_start,
+                        bucket.min_duration_nanos?,
+                        bucket.max_duration_nanos?,
+                    ))
+                })
+                .collect::<Vec<_>>()
+        } else {
+            vec![]
+        };
+
+        let all_values = lines
+            .iter()
+            .flat_map(|(_, values)| values.iter().map(|(_, value)| *value as i64))
+            .chain(envelope.iter().flat_map(|(_, min, max)| [*min, *max]))
+            .collect::<Vec<_>>();
+        if all_values.is_empty() {
+            return html! { "No data found" };
+        }
         let full_height = 100;
         let height_padding = 10;
 
         let width = 200;
         let height = full_height - height_padding * 2;
 
-        let (min_time, max_time) = self
-            .range
-            .as_ref()
-            .map(|r| (r.start, r.end))
-            .unwrap_or_else(|| {
-                (
-                    self.checkins.iter().map(|p| p.created_at).min().unwrap(),
-                    self.checkins.iter().map(|p| p.created_at).max().unwrap(),
-                )
-            });
-
-        let x_range = min_time..max_time;
-
-        let min_duration_nanos = self
-            .checkins
-            .iter()
-            .map(|p| p.duration_nanos.unwrap())
-            .min()
-            .unwrap()
-            / 1_000_000
-            * 1_000_000
-            - 1_000_000;
+        let x_range = self.range.clone();
+
+        let min_duration_nanos =
+            (all_values.iter().min().unwrap() / 1_000_000 * 1_000_000 - 1_000_000).max(0);
         let min_label =
             humantime::format_duration(Duration::from_nanos(min_duration_nanos as u64)).to_string();
 
-        let max_duration_nanos = self
-            .checkins
-            .iter()
-            .map(|p| p.duration_nanos.unwrap())
-            .max()
-            .unwrap()
-            / 1_000_000
-            * 1_000_000
-            + 1_000_000;
-
+        let max_duration_nanos =
+            all_values.iter().max().unwrap() / 1_000_000 * 1_000_000 + 1_000_000;
         let max_label =
             humantime::format_duration(Duration::from_nanos(max_duration_nanos as u64)).to_string();
 
-        let y_range = min_duration_nanos..max_duration_nanos;
+        let y_range = min_duration_nanos as f64..max_duration_nanos as f64;
 
         let calculate_x =
             |time: DateTime<Utc>| calculate_range_percentile(&x_range, time) * width as f64;
 
-        let calculate_y = |duration: i64| {
+        let calculate_y = |duration: f64| {
             height as f64 + height_padding as f64
                 - calculate_range_percentile(&y_range, duration) * height as f64
         };
 
-        let points = self
-            .checkins
+        let envelope_paths = [
+            envelope
+                .iter()
+                .map(|(time, min, _)| (calculate_x(*time), calculate_y(*min as f64)))
+                .collect::<Vec<_>>(),
+            envelope
+                .iter()
+                .map(|(time, _, max)| (calculate_x(*time), calculate_y(*max as f64)))
+                .collect::<Vec<_>>(),
+        ]
+        .into_iter()
+        .filter(|points| !points.is_empty())
+        .map(|points| SvgPath {
+            points,
+            stroke_width: 0.25,
+            path_class: "stroke-black".to_string(),
+            stroke_dashed: true,
+        })
+        .collect::<Vec<_>>();
+
+        let paths = lines
             .iter()
-            .map(|p| GraphPoint {
-                x: calculate_x(p.created_at),
-                y: calculate_y(p.duration_nanos.unwrap()),
-                label: humantime::format_duration(Duration::from_nanos(
-                    p.duration_nanos.unwrap() as u64
-                ))
-                .to_string(),
-            })
+            .zip(LINE_COLORS)
+            .filter(|((_, values), _)| !values.is_empty())
+            .map(
+                |((line, values), (stroke_class, fill_class, _))| SvgPathWithPoints {
+                    points: values
+                        .iter()
+                        .map(|(time, value)| {
+                            let duration =
+                                humantime::format_duration(Duration::from_nanos(*value as u64));
+
+                            GraphPoint {
+                                x: calculate_x(*time),
+                                y: calculate_y(*value),
+                                label: if is_single_line {
+                                    duration.to_string()
+                                } else {
+                                    format!("{}: {duration}", line.label)
+                                },
+                            }
+                        })
+                        .collect(),
+                    stroke_width: 0.5,
+                    path_class: stroke_class.to_string(),
+                    stroke_dashed: false,
+                    point_radius: 2,
+                    label_font_size: 4,
+                    group_class: format!("{fill_class} hover:fill-red-500"),
+                },
+            )
             .collect::<Vec<_>>();
 
         html! {
-          svg class="w-full" viewBox="0 0 200 100" {
+          svg class="w-full" viewBox="0 0 200 100" data-app="ZoomableGraph" data-range-start=(self.range.start.timestamp_millis()) data-range-end=(self.range.end.timestamp_millis()) {
 
-            (XAxisTicks { width, x_range, number_of_ticks: 5 })
+            (XAxisTicks { width, x_range, number_of_ticks: 5, tz: self.tz })
 
             (YAxisLine { width, y_pos: height_padding, label: format!("Max: {max_label}")})
 
             (YAxisLine { width, y_pos: full_height - height_padding, label: format!("Min: {min_label}")})
 
-            (SvgPathWithPoints {
-              points,
-              stroke_width: 0.5,
-              path_class: "stroke-black".to_string(),
-              stroke_dashed: false,
-              point_radius: 2,
-              label_font_size: 4,
-              group_class: "hover:fill-red-500".to_string(),
-            })
+            @for path in envelope_paths {
+              (path)
+            }
+
+            @for path in paths {
+              (path)
+            }
+          }
+
+          @if !is_single_line {
+            ul."flex flex-wrap gap-x-4 text-sm" {
+              @for ((line, values), (_, _, bg_class)) in lines.iter().zip(LINE_COLORS) {
+                li."flex items-center gap-x-1" {
+                  span class=(format!("inline-block h-3 w-3 rounded-sm {bg_class}")) {}
+                  @if let Some(href) = &line.href {
+                    a href=(href) { (line.label) }
+                  } @else {
+                    (line.label)
+                  }
+                  @if values.is_empty() {
+                    " (no data)"
+                  }
+                }
+              }
+            }
           }
         }
     }
 }
 
-fn transpose<X, Y, Z>(vec: Vec<(X, Y, Z)>) -> (Vec<X>, Vec<Y>, Vec<Z>)
-where
-    X: Clone,
-    Y: Clone,
-    Z: Clone,
-{
-    vec.into_iter().fold(
-        (Vec::new(), Vec::new(), Vec::new()),
-        |(mut v1, mut v2, mut v3), (e1, e2, e3)| {
-            v1.push(e1);
-            v2.push(e2);
-            v3.push(e3);
-            (v1, v2, v3)
-        },
-    )
-}
-
-impl Render for SampledCheckinGraph {
+impl Render for TimingBreakdownGraph {
     fn render(&self) -> maud::Markup {
-        if self.checkins.is_empty() {
-            return html! { "No data found" };
+        let buckets = self
+            .buckets
+            .iter()
+            .filter(|bucket| {
+                TimingPhase::ALL
+                    .iter()
+                    .all(|phase| phase.avg_nanos(bucket).is_some())
+            })
+            .collect::<Vec<_>>();
+
+        if buckets.is_empty() {
+            return html! { "No timing data found" };
         }
         let full_height = 100;
         let height_padding = 10;
@@ -721,121 +1171,84 @@ impl Render for SampledCheckinGraph {
         let width = 200;
         let height = full_height - height_padding * 2;
 
-        let checkin_min = self.checkins.iter().map(|p| p.created_at).min().unwrap();
-        let checkin_max = self.checkins.iter().map(|p| p.created_at).max().unwrap();
+        let x_range = self.range.clone();
 
-        let (min_time, max_time) = self
-            .range
-            .as_ref()
-            .map(|r| (checkin_min.min(r.start), checkin_max.max(r.end)))
-            .unwrap_or_else(|| (checkin_min, checkin_max));
+        // For each bucket, the x position and the running total of the average
+        // duration of each phase, so the last entry is the top of the stack
+        let stacks: Vec<(f64, Vec<f64>)> = buckets
+            .iter()
+            .map(|bucket| {
+                let x = calculate_range_percentile(&x_range, bucket.bucket_start) * width as f64;
 
-        let x_range = min_time..max_time;
+                let stack = TimingPhase::ALL
+                    .iter()
+                    .scan(0.0, |total, phase| {
+                        *total += phase.avg_nanos(bucket).unwrap_or_default();
+                        Some(*total)
+                    })
+                    .collect();
 
-        let min_duration_nanos = self
-            .checkins
-            .iter()
-            .map(|p| p.duration_nanos.unwrap())
-            .min()
-            .unwrap()
-            / 1_000_000
-            * 1_000_000
-            - 1_000_000;
-        let min_label =
-            humantime::format_duration(Duration::from_nanos(min_duration_nanos as u64)).to_string();
+                (x, stack)
+            })
+            .collect();
 
-        let max_duration_nanos = self
-            .checkins
+        let max_duration_nanos = stacks
             .iter()
-            .map(|p| p.duration_nanos.unwrap())
+            .filter_map(|(_, stack)| stack.last().map(|total| *total as i64))
             .max()
             .unwrap()
             / 1_000_000
             * 1_000_000
             + 1_000_000;
-
         let max_label =
             humantime::format_duration(Duration::from_nanos(max_duration_nanos as u64)).to_string();
 
-        let y_range = min_duration_nanos..max_duration_nanos;
+        let y_range = 0.0..max_duration_nanos as f64;
 
-        let calculate_x =
-            |time: DateTime<Utc>| calculate_range_percentile(&x_range, time) * width as f64;
-
-        let calculate_y = |duration: i64| {
+        let calculate_y = |duration: f64| {
             height as f64 + height_padding as f64
                 - calculate_range_percentile(&y_range, duration) * height as f64
         };
 
-        let total_count = self.checkins.len();
-
-        let chunk_size = (total_count / self.number_of_chunks).max(1);
-        let chunks = self.checkins.chunks(chunk_size);
-
-        let min_avg_max: Vec<((f64, f64), GraphPoint, (f64, f64))> = chunks
-            .map(|chunk| {
-                let min = chunk
+        let areas = TimingPhase::ALL
+            .iter()
+            .enumerate()
+            .map(|(i, phase)| SvgArea {
+                top: stacks
                     .iter()
-                    .map(|p| p.duration_nanos.unwrap())
-                    .min()
-                    .unwrap();
-                let max = chunk
+                    .map(|(x, stack)| (*x, calculate_y(stack[i])))
+                    .collect(),
+                bottom: stacks
                     .iter()
-                    .map(|p| p.duration_nanos.unwrap())
-                    .max()
-                    .unwrap();
-                let avg = chunk.iter().map(|p| p.duration_nanos.unwrap()).sum::<i64>()
-                    / chunk.len() as i64;
-
-                let x = calculate_x(chunk[chunk.len() / 2].created_at);
-
-                (
-                    (x, calculate_y(min)),
-                    GraphPoint {
-                        x,
-                        y: calculate_y(avg),
-                        label: humantime::format_duration(Duration::from_nanos(avg as u64))
-                            .to_string(),
-                    },
-                    (x, calculate_y(max)),
-                )
+                    .map(|(x, stack)| (*x, calculate_y(if i == 0 { 0.0 } else { stack[i - 1] })))
+                    .collect(),
+                fill_class: phase.fill_class().to_string(),
             })
-            .collect();
+            .collect::<Vec<_>>();
 
-        let (min, avg, max) = transpose(min_avg_max);
+        let tz = self.tz;
 
         html! {
-          svg class="w-full" viewBox="0 0 200 100" {
-
-            (XAxisTicks { width, x_range, number_of_ticks: 5 })
+          svg class="w-full" viewBox="0 0 200 100" data-app="ZoomableGraph" data-range-start=(self.range.start.timestamp_millis()) data-range-end=(self.range.end.timestamp_millis()) {
 
-            (YAxisLine { width, y_pos: height_padding, label: format!("Max: {max_label}")})
+            @for area in areas {
+              (area)
+            }
 
-            (YAxisLine { width, y_pos: full_height - height_padding, label: format!("Min: {min_label}")})
+            (XAxisTicks { width, x_range, number_of_ticks: 5, tz })
 
-            (SvgPath {
-              points: min,
-              stroke_width: 0.25,
-              path_class: "stroke-black".to_string(),
-              stroke_dashed: true,
-            })
+            (YAxisLine { width, y_pos: height_padding, label: format!("Max: {max_label}")})
 
-            (SvgPath {
-              points: max,
-              stroke_width: 0.25,
-              path_class: "stroke-black".to_string(),
-              stroke_dashed: true,
-            })
+            (YAxisLine { width, y_pos: full_height - height_padding, label: "0".to_string()})
+          }
 
-            (SvgPathWithPoints {
-              points: avg,
-              stroke_width: 0.5,
-              path_class: "stroke-blue-500".to_string(),
-              stroke_dashed: false,
-              point_radius: 2,
-              label_font_size: 4,
-              group_class: "hover:fill-red-500".to_string(),
-            })
+          ul."flex gap-x-4 text-sm" {
+            @for phase in TimingPhase::ALL {
+              li."flex items-center gap-x-1" {
+                span class=(format!("inline-block h-3 w-3 rounded-sm {}", phase.bg_class())) {}
+                (phase.label())
+              }
+            }
           }
         }
     }
@@ -0,0 +1,266 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use cja::{app_state::AppState as _, server::session::DBSession};
+use maud::html;
+use miette::IntoDiagnostic;
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    audit::{AuditAction, AuditChange, AuditEntity},
+    authz::Role,
+    errors::AppError,
+    reports::{previous_month, SiteReport},
+    stats::day_range,
+    templates::IntoTemplate,
+};
+
+use super::sites::{empty_string_is_none, Site};
+
+/// Longest range a report can be generated for
+const MAX_REPORT_DAYS: i64 = 366;
+
+#[derive(serde::Deserialize)]
+pub struct ReportQuery {
+    #[serde(default, deserialize_with = "empty_string_is_none")]
+    start: Option<NaiveDate>,
+    /// Inclusive, so a report from the 1st to the 31st covers the whole month
+    #[serde(default, deserialize_with = "empty_string_is_none")]
+    end: Option<NaiveDate>,
+}
+
+impl ReportQuery {
+    /// Defaults to last month when either end of the range is missing
+    fn range(&self) -> Result<std::ops::Range<DateTime<Utc>>, AppError> {
+        let (Some(start), Some(end)) = (self.start, self.end) else {
+            return Ok(previous_month(Utc::now()));
+        };
+
+        if start > end {
+            return Err(AppError::Invalid("Start must be before end".to_string()));
+        }
+        if (end - start).num_days() >= MAX_REPORT_DAYS {
+            return Err(AppError::Invalid(format!(
+                "Reports can cover at most {MAX_REPORT_DAYS} days"
+            )));
+        }
+
+        day_range(start, end)
+            .ok_or_else(|| AppError::Invalid("End is too far in the future".to_string()))
+    }
+}
+
+pub async fn show(
+    site: Site,
+    session: DBSession,
+    State(state): State<AppState>,
+    Query(query): Query<ReportQuery>,
+) -> Result<Response, AppError> {
+    let range = query.range()?;
+
+    let subscriptions = sqlx::query!(
+        r#"
+      SELECT report_subscription_id, email
+      FROM ReportSubscriptions
+      WHERE site_id = $1
+      ORDER BY created_at ASC
+    "#,
+        site.site_id
+    )
+    .fetch_all(state.db())
+    .await
+    .into_diagnostic()?;
+
+    let site_id = site.site_id;
+    let can_edit = site.role >= Role::Editor;
+    let report = SiteReport::generate(&state, site, range).await?;
+
+    let start = report.range.start.format("%Y-%m-%d").to_string();
+    let end = (report.range.end - chrono::Duration::days(1))
+        .format("%Y-%m-%d")
+        .to_string();
+
+    Ok(html! {
+      form method="get" action=(format!("/my/sites/{site_id}/reports")) {
+        label {
+          "From"
+          input type="date" name="start" value=(start) required;
+        }
+
+        label {
+          "To"
+          input type="date" name="end" value=(end) required;
+        }
+
+        button type="submit" { "Generate" }
+      }
+
+      a href=(format!("/my/sites/{site_id}/reports/download?start={start}&end={end}")) { "Download as HTML" }
+
+      (report)
+
+      h2 { "Monthly Emails" }
+
+      p { "These addresses get last month's report emailed to them on the 1st of every month" }
+
+      ul {
+        @for subscription in subscriptions {
+          li {
+            (subscription.email)
+
+            @if can_edit {
+              form method="post" action=(format!("/my/sites/{site_id}/reports/subscriptions/{}/delete", subscription.report_subscription_id)) {
+                button type="submit" { "Remove" }
+              }
+            }
+          }
+        }
+      }
+
+      @if can_edit {
+        form method="post" action=(format!("/my/sites/{site_id}/reports/subscriptions")) {
+          label {
+            "Email"
+            input type="email" name="email" required;
+          }
+
+          button type="submit" { "Add" }
+        }
+      }
+    }
+    .into_template(state, Some(session))
+    .await?
+    .into_response())
+}
+
+pub async fn download(
+    site: Site,
+    State(state): State<AppState>,
+    Query(query): Query<ReportQuery>,
+) -> Result<Response, AppError> {
+    let range = query.range()?;
+
+    let report = SiteReport::generate(&state, site, range).await?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", report.filename()),
+            ),
+        ],
+        report.standalone_html(&state.font_awesome_kit_id),
+    )
+        .into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub struct SubscriptionFormData {
+    email: String,
+}
+
+pub async fn subscribe(
+    site: Site,
+    session: DBSession,
+    State(state): State<AppState>,
+    Form(form_data): Form<SubscriptionFormData>,
+) -> Result<Response, AppError> {
+    site.require_role(Role::Editor)?;
+
+    let email = form_data.email.trim();
+    if email.parse::<lettre::Address>().is_err() {
+        return Err(AppError::Invalid("Invalid email address".to_string()));
+    }
+
+    let mut tx = state.db().begin().await.into_diagnostic()?;
+
+    let subscription = sqlx::query!(
+        r#"
+    INSERT INTO ReportSubscriptions (site_id, email)
+    VALUES ($1, $2)
+    ON CONFLICT (site_id, email) DO NOTHING
+    RETURNING report_subscription_id, to_jsonb(ReportSubscriptions) as "after!"
+  "#,
+        site.site_id,
+        email
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .into_diagnostic()?;
+
+    // Nothing comes back when the address was already subscribed
+    if let Some(subscription) = subscription {
+        AuditChange {
+            organization_id: site.organization_id,
+            site_id: site.site_id,
+            actor_user_id: session.user_id,
+            action: AuditAction::Create,
+            entity: AuditEntity::ReportSubscription,
+            entity_id: subscription.report_subscription_id,
+            before: None,
+            after: Some(subscription.after),
+        }
+        .record(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await.into_diagnostic()?;
+
+    Ok(Redirect::to(&format!("/my/sites/{}/reports", site.site_id)).into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub struct SubscriptionPath {
+    report_subscription_id: Uuid,
+}
+
+pub async fn unsubscribe(
+    site: Site,
+    session: DBSession,
+    State(state): State<AppState>,
+    Path(SubscriptionPath {
+        report_subscription_id,
+    }): Path<SubscriptionPath>,
+) -> Result<Response, AppError> {
+    site.require_role(Role::Editor)?;
+
+    let mut tx = state.db().begin().await.into_diagnostic()?;
+
+    let before = sqlx::query_scalar!(
+        r#"
+    DELETE FROM ReportSubscriptions
+    WHERE report_subscription_id = $1 AND site_id = $2
+    RETURNING to_jsonb(ReportSubscriptions) as "before!"
+  "#,
+        report_subscription_id,
+        site.site_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .into_diagnostic()?;
+
+    if let Some(before) = before {
+        AuditChange {
+            organization_id: site.organization_id,
+            site_id: site.site_id,
+            actor_user_id: session.user_id,
+            action: AuditAction::Delete,
+            entity: AuditEntity::ReportSubscription,
+            entity_id: report_subscription_id,
+            before: Some(before),
+            after: None,
+        }
+        .record(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await.into_diagnostic()?;
+
+    Ok(Redirect::to(&format!("/my/sites/{}/reports", site.site_id)).into_response())
+}
@@ -0,0 +1,147 @@
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Redirect, Response},
+};
+use chrono::{DateTime, Utc};
+use cja::{app_state::AppState as _, server::session::DBSession};
+use maud::html;
+use miette::IntoDiagnostic;
+use uuid::Uuid;
+
+use crate::{app_state::AppState, errors::AppError, templates::IntoTemplate};
+
+struct UserSession {
+    session_id: Uuid,
+    user_agent: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+pub async fn index(
+    session: DBSession,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let now = Utc::now();
+    let timeouts = state.session_timeouts;
+
+    // Expired sessions stick around until they're pruned, but can't be used
+    let sessions = sqlx::query_as!(
+        UserSession,
+        r#"
+    SELECT session_id, user_agent, created_at, updated_at
+    FROM Sessions
+    WHERE user_id = $1
+    ORDER BY updated_at DESC
+  "#,
+        session.user_id
+    )
+    .fetch_all(state.db())
+    .await
+    .into_diagnostic()?
+    .into_iter()
+    .filter(|s| timeouts.expires_at(s.created_at, s.updated_at) > now)
+    .collect::<Vec<_>>();
+
+    let has_other_sessions = sessions.iter().any(|s| s.session_id != session.session_id);
+
+    Ok(html! {
+      h1 { "Sessions" }
+
+      p { "Everywhere you're logged in. Log out any you don't recognize." }
+
+      table."min-w-full divide-y divide-gray-300" {
+        thead {
+          tr {
+            th."text-left" { "Device" }
+            th."text-left" { "Logged in" }
+            th."text-left" { "Last active" }
+            th."text-left" { "Expires" }
+            th {}
+          }
+        }
+        tbody {
+          @for s in &sessions {
+            tr {
+              td { (s.user_agent.as_deref().unwrap_or("Unknown device")) }
+              td { (s.created_at.format("%Y-%m-%d %H:%M UTC")) }
+              td { (s.updated_at.format("%Y-%m-%d %H:%M UTC")) }
+              td { (timeouts.expires_at(s.created_at, s.updated_at).format("%Y-%m-%d %H:%M UTC")) }
+              td {
+                @if s.session_id == session.session_id {
+                  "This device"
+                } @else {
+                  form method="post" action=(format!("/my/sessions/{}/delete", s.session_id)) {
+                    button type="submit" { "Log out" }
+                  }
+                }
+              }
+            }
+          }
+        }
+      }
+
+      @if has_other_sessions {
+        form method="post" action="/my/sessions/delete-others" {
+          button type="submit" { "Log out everywhere else" }
+        }
+      }
+
+      form method="post" action="/logout" {
+        button type="submit" { "Log out of this device" }
+      }
+    }
+    .into_template(state, Some(session))
+    .await?
+    .into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub struct SessionPath {
+    session_id: Uuid,
+}
+
+pub async fn revoke(
+    session: DBSession,
+    State(state): State<AppState>,
+    Path(SessionPath { session_id }): Path<SessionPath>,
+) -> Result<Response, AppError> {
+    // The current session logs out through /logout, so the cookie goes too
+    let revoked = sqlx::query!(
+        r#"
+    DELETE FROM Sessions
+    WHERE session_id = $1 AND user_id = $2 AND session_id <> $3
+  "#,
+        session_id,
+        session.user_id,
+        session.session_id
+    )
+    .execute(state.db())
+    .await
+    .into_diagnostic()?
+    .rows_affected();
+
+    if revoked == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(Redirect::to("/my/sessions").into_response())
+}
+
+pub async fn revoke_others(
+    session: DBSession,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    sqlx::query!(
+        r#"
+    DELETE FROM Sessions
+    WHERE user_id = $1 AND session_id <> $2
+  "#,
+        session.user_id,
+        session.session_id
+    )
+    .execute(state.db())
+    .await
+    .into_diagnostic()?;
+
+    Ok(Redirect::to("/my/sessions").into_response())
+}
@@ -1,30 +1,131 @@
-use std::fmt::Display;
+use std::{fmt::Display, ops::Range};
 
 use axum::{
     extract::{FromRequestParts, Path, Query, State},
-    http::request::Parts,
-    response::{IntoResponse, Redirect},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Redirect, Response},
     Form,
 };
 
-use chrono::Duration;
-use cja::{
-    app_state::AppState as _,
-    server::session::{DBSession, SessionRedirect},
-};
+use chrono::{DateTime, Utc};
+use cja::{app_state::AppState as _, server::session::DBSession};
 use maud::{html, Render};
-use serde::Deserialize as _;
-use sqlx::postgres::types::PgInterval;
+use miette::IntoDiagnostic;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
 use uuid::Uuid;
 
-use crate::{app_state::AppState, routes::current_user::pages::Checkin, templates::IntoTemplate};
-use crate::{routes::current_user::pages::Page, templates::Template};
+use crate::routes::current_user::{
+    exports::export_form, organizations::organizations_for_user, pages::Page, slos::slo_overview,
+    time_range::TimeRangeQuery, uptime_bar::UptimeBar,
+};
+use crate::{
+    app_state::AppState,
+    audit::{AuditAction, AuditChange, AuditEntity},
+    authz::{self, Role},
+    errors::AppError,
+    incidents::incidents_for_range,
+    plans,
+    stats::{stats_for_range, uptime_buckets, CheckinStats, Percentile, Resolution, StatsScope},
+    templates::IntoTemplate,
+    validation::{self, field_error, UrlTest},
+};
 
-struct SiteTableRow {
+pub(crate) struct SiteTableRow {
     site_id: Uuid,
     name: String,
     domain: String,
     description: Option<String>,
+    /// Pages that have been checked at least once
+    checked_pages: i64,
+    /// Pages whose most recent checkin wasn't successful
+    failing_pages: i64,
+    last_checked_at: Option<DateTime<Utc>>,
+}
+
+/// The health of a site, based on the latest checkin of each of its pages.
+/// Ordered from most to least urgent, which is how the status sort works
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SiteHealth {
+    Down,
+    Degraded,
+    Up,
+    Unknown,
+}
+
+impl SiteHealth {
+    pub const ALL: [SiteHealth; 4] = [
+        SiteHealth::Up,
+        SiteHealth::Degraded,
+        SiteHealth::Down,
+        SiteHealth::Unknown,
+    ];
+
+    pub fn from_page_counts(checked_pages: i64, failing_pages: i64) -> Self {
+        if checked_pages == 0 {
+            SiteHealth::Unknown
+        } else if failing_pages == 0 {
+            SiteHealth::Up
+        } else if failing_pages == checked_pages {
+            SiteHealth::Down
+        } else {
+            SiteHealth::Degraded
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SiteHealth::Down => "down",
+            SiteHealth::Degraded => "degraded",
+            SiteHealth::Up => "up",
+            SiteHealth::Unknown => "unknown",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SiteHealth::Down => "Down",
+            SiteHealth::Degraded => "Degraded",
+            SiteHealth::Up => "Up",
+            SiteHealth::Unknown => "Not Checked",
+        }
+    }
+
+    pub fn badge_class(&self) -> &'static str {
+        match self {
+            SiteHealth::Down => "text-red-700 bg-red-50 ring-red-600/20",
+            SiteHealth::Degraded => "text-yellow-800 bg-yellow-50 ring-yellow-600/20",
+            SiteHealth::Up => "text-green-700 bg-green-50 ring-green-600/20",
+            SiteHealth::Unknown => "text-gray-600 bg-gray-50 ring-gray-500/10",
+        }
+    }
+}
+
+impl std::str::FromStr for SiteHealth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SiteHealth::ALL
+            .into_iter()
+            .find(|health| health.as_str() == s)
+            .ok_or_else(|| format!("Unknown status {s}"))
+    }
+}
+
+impl Render for SiteHealth {
+    fn render(&self) -> maud::Markup {
+        html! {
+            p class=(format!("rounded-md whitespace-nowrap mt-0.5 px-1.5 py-0.5 text-xs font-medium ring-1 ring-inset {}", self.badge_class())) {
+                (self.label())
+            }
+        }
+    }
+}
+
+impl SiteTableRow {
+    pub(crate) fn health(&self) -> SiteHealth {
+        SiteHealth::from_page_counts(self.checked_pages, self.failing_pages)
+    }
 }
 
 impl Render for SiteTableRow {
@@ -38,10 +139,7 @@ impl Render for SiteTableRow {
                         (self.name)
                     }
                 }
-                // TODO: Check the last checkin for the 'status'
-                p."rounded-md whitespace-nowrap mt-0.5 px-1.5 py-0.5 text-xs font-medium ring-1 ring-inset text-green-700 bg-green-50 ring-green-600/20" {
-                    "Complete"
-                }
+                (self.health())
             }
             @if let Some(description) = self.description.as_ref() {
               div."mt-1 flex items-center gap-x-2 text-xs leading-5 text-gray-500" {
@@ -52,10 +150,13 @@ impl Render for SiteTableRow {
             }
             div."mt-1 flex items-center gap-x-2 text-xs leading-5 text-gray-500" {
                 p."whitespace-nowrap" {
-                    "Last Checked at "
-                    // TODO: Grab the last checked at
-                    time datetime="2023-03-17T00:00Z" {
-                        "March 17, 2023"
+                    @if let Some(last_checked_at) = self.last_checked_at {
+                        "Last Checked at "
+                        time datetime=(last_checked_at.to_rfc3339()) {
+                            (last_checked_at.format("%B %-d, %Y %H:%M UTC"))
+                        }
+                    } @else {
+                        "Never Checked"
                     }
                 }
                 svg."h-0.5 w-0.5 fill-current" viewBox="0 0 2 2" {
@@ -108,19 +209,76 @@ impl Render for SiteTableRow {
     }
 }
 
-pub async fn index(session: DBSession, State(state): State<AppState>) -> impl IntoResponse {
-    let sites = sqlx::query_as!(
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SiteSort {
+    #[default]
+    Name,
+    Status,
+    LastChecked,
+}
+
+#[derive(serde::Deserialize)]
+pub struct IndexQuery {
+    #[serde(default, deserialize_with = "empty_string_is_none")]
+    status: Option<SiteHealth>,
+    #[serde(default)]
+    sort: SiteSort,
+}
+
+/// Loads every site the user can see along with the latest checkin of each of
+/// its pages
+pub(crate) async fn site_table_rows(
+    db: &PgPool,
+    user_id: Uuid,
+) -> miette::Result<Vec<SiteTableRow>> {
+    let site_ids = authz::accessible_site_ids(db, user_id).await?;
+
+    sqlx::query_as!(
         SiteTableRow,
         r#"
-    SELECT site_id, name, domain, description
+    SELECT
+      Sites.site_id,
+      Sites.name,
+      Sites.domain,
+      Sites.description,
+      count(Latest.outcome) as "checked_pages!",
+      count(Latest.outcome) FILTER (WHERE Latest.outcome <> 'success') as "failing_pages!",
+      max(Latest.created_at) as last_checked_at
     FROM Sites
-    WHERE user_id = $1
+    LEFT JOIN Pages ON Pages.site_id = Sites.site_id
+    LEFT JOIN LATERAL (
+      SELECT Checkins.outcome, Checkins.created_at
+      FROM Checkins
+      WHERE Checkins.page_id = Pages.page_id
+      ORDER BY Checkins.created_at DESC
+      LIMIT 1
+    ) Latest ON true
+    WHERE Sites.site_id = ANY($1)
+    GROUP BY Sites.site_id
   "#,
-        session.user_id
+        &site_ids
     )
-    .fetch_all(state.db())
+    .fetch_all(db)
     .await
-    .unwrap();
+    .into_diagnostic()
+}
+
+pub async fn index(
+    session: DBSession,
+    State(state): State<AppState>,
+    Query(query): Query<IndexQuery>,
+) -> Result<Response, AppError> {
+    let mut sites = site_table_rows(state.db(), session.user_id).await?;
+
+    if let Some(status) = query.status {
+        sites.retain(|site| site.health() == status);
+    }
+    match query.sort {
+        SiteSort::Name => sites.sort_by_key(|site| site.name.to_lowercase()),
+        SiteSort::Status => sites.sort_by_key(|site| site.health()),
+        SiteSort::LastChecked => sites.sort_by_key(|site| std::cmp::Reverse(site.last_checked_at)),
+    }
 
     let table = html! {
         ul."divide-y divide-gray-100" role="list" {
@@ -132,63 +290,171 @@ pub async fn index(session: DBSession, State(state): State<AppState>) -> impl In
         }
     };
 
-    html! {
+    Ok(html! {
       h1 { "My Sites" }
 
       a href="/my/sites/new" { "Create a new site" }
 
+      a href="/my/compare" { "Compare pages" }
+
+      form method="get" action="/my/sites" {
+        select name="status" {
+          option value="" selected[query.status.is_none()] { "All statuses" }
+          @for health in SiteHealth::ALL {
+            option value=(health.as_str()) selected[query.status == Some(health)] { (health.label()) }
+          }
+        }
+
+        select name="sort" {
+          option value="name" selected[query.sort == SiteSort::Name] { "Sort by name" }
+          option value="status" selected[query.sort == SiteSort::Status] { "Sort by status" }
+          option value="last_checked" selected[query.sort == SiteSort::LastChecked] { "Sort by last checked" }
+        }
+
+        button type="submit" { "Apply" }
+      }
+
       (table)
     }
     .into_template(state, Some(session))
-    .await
-    .unwrap()
+    .await?
+    .into_response())
 }
 
-pub async fn new(session: DBSession, State(state): State<AppState>) -> impl IntoResponse {
-    html! {
+pub async fn new(session: DBSession, State(state): State<AppState>) -> Result<Response, AppError> {
+    site_form(state, session, SiteFormState::default()).await
+}
+
+/// What the new site form is showing, so it can be shown again with the
+/// validation errors or test result after a submit
+#[derive(Default)]
+struct SiteFormState {
+    organization_id: Option<Uuid>,
+    name: String,
+    domain: String,
+    description: Option<String>,
+    domain_error: Option<String>,
+    test: Option<UrlTest>,
+}
+
+async fn site_form(
+    state: AppState,
+    session: DBSession,
+    form: SiteFormState,
+) -> Result<Response, AppError> {
+    let organizations = organizations_for_user(state.db(), session.user_id)
+        .await?
+        .into_iter()
+        .filter(|organization| organization.role >= Role::Editor)
+        .collect::<Vec<_>>();
+
+    let mut usages = Vec::with_capacity(organizations.len());
+    for organization in &organizations {
+        let usage =
+            plans::organization_usage(state.db(), &state.auth, organization.organization_id)
+                .await?;
+        usages.push(usage);
+    }
+    let upgrade_message = usages
+        .iter()
+        .filter(|usage| usage.at_site_limit())
+        .find_map(|usage| usage.plan.upgrade_message());
+
+    let status = if form.domain_error.is_some() {
+        StatusCode::UNPROCESSABLE_ENTITY
+    } else {
+        StatusCode::OK
+    };
+
+    let page = html! {
       h1 { "New Site" }
 
+      @if let Some(upgrade_message) = upgrade_message {
+        p { "Some of your organizations are out of sites on their plan. " (upgrade_message) }
+      }
+
       form method="post" action="/my/sites" {
+        label {
+          "Organization"
+          select name="organization_id" required {
+            @for (organization, usage) in organizations.iter().zip(&usages) {
+              option
+                value=(organization.organization_id)
+                selected[form.organization_id == Some(organization.organization_id)]
+                disabled[usage.at_site_limit()] {
+                (organization.name) " (" (usage.sites_summary()) ", " (usage.plan.label()) " plan)"
+              }
+            }
+          }
+        }
+
         label {
           "Name"
-          input type="text" name="name" required;
+          input type="text" name="name" value=(form.name) required;
         }
 
         label {
           "Domain"
-          input type="text" name="domain" required;
+          input type="text" name="domain" value=(form.domain) placeholder="example.com" required;
         }
+        (field_error(form.domain_error.as_deref()))
 
         label {
           "Description"
-          textarea name="description" {}
+          textarea name="description" { (form.description.as_deref().unwrap_or_default()) }
         }
 
+        @if let Some(test) = &form.test {
+          (test)
+        }
+
+        button type="submit" name="intent" value="test" { "Test this URL now" }
         input type="submit" value="Create";
       }
     }
     .into_template(state, Some(session))
-    .await
-    .unwrap()
+    .await?;
+
+    Ok((status, page).into_response())
 }
 
 #[derive(serde::Deserialize)]
 pub struct CreateSiteFormData {
+    organization_id: Uuid,
     name: String,
     domain: String,
     #[serde(deserialize_with = "empty_string_is_none")]
     description: Option<String>,
+    /// Set by the "Test this URL now" button, which checks the domain instead
+    /// of saving the site
+    #[serde(default)]
+    intent: Option<String>,
+}
+
+impl From<CreateSiteFormData> for SiteFormState {
+    fn from(form_data: CreateSiteFormData) -> Self {
+        Self {
+            organization_id: Some(form_data.organization_id),
+            name: form_data.name,
+            domain: form_data.domain,
+            description: form_data.description,
+            domain_error: None,
+            test: None,
+        }
+    }
 }
 
-fn empty_string_is_none<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+pub fn empty_string_is_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
 where
     D: serde::Deserializer<'de>,
+    T: std::str::FromStr,
+    T::Err: Display,
 {
     let s = String::deserialize(deserializer)?;
     if s.is_empty() {
         Ok(None)
     } else {
-        Ok(Some(s))
+        s.parse().map(Some).map_err(serde::de::Error::custom)
     }
 }
 
@@ -196,32 +462,93 @@ pub async fn create(
     session: DBSession,
     State(app_state): State<AppState>,
     Form(form_data): Form<CreateSiteFormData>,
-) -> impl IntoResponse {
-    let new_site_id = sqlx::query!(
+) -> Result<Response, AppError> {
+    authz::require_organization_role(
+        app_state.db(),
+        session.user_id,
+        form_data.organization_id,
+        Role::Editor,
+    )
+    .await?;
+
+    let domain = match validation::normalize_domain(&form_data.domain) {
+        Ok(domain) => domain,
+        Err(error) => {
+            let form = SiteFormState {
+                domain_error: Some(error),
+                ..SiteFormState::from(form_data)
+            };
+            return site_form(app_state, session, form).await;
+        }
+    };
+
+    if form_data.intent.as_deref() == Some("test") {
+        let test = UrlTest::run(validation::check_url(&domain, "/")).await;
+        let form = SiteFormState {
+            domain,
+            test: Some(test),
+            ..SiteFormState::from(form_data)
+        };
+        return site_form(app_state, session, form).await;
+    }
+
+    let mut tx = app_state.db().begin().await.into_diagnostic()?;
+
+    plans::lock_usage(&mut tx, &app_state.auth, form_data.organization_id)
+        .await?
+        .require_site_room()?;
+
+    let new_site = sqlx::query!(
         r#"
-      INSERT INTO Sites (name, domain, description, user_id)
+      INSERT INTO Sites (name, domain, description, organization_id)
       VALUES ($1, $2, $3, $4)
-      RETURNING site_id
+      RETURNING site_id, to_jsonb(Sites) as "after!"
     "#,
         form_data.name,
-        form_data.domain,
+        domain,
         form_data.description,
-        session.user_id
+        form_data.organization_id
     )
-    .fetch_one(app_state.db())
+    .fetch_one(&mut *tx)
     .await
-    .unwrap()
-    .site_id;
+    .into_diagnostic()?;
+    let new_site_id = new_site.site_id;
+
+    AuditChange {
+        organization_id: form_data.organization_id,
+        site_id: new_site_id,
+        actor_user_id: session.user_id,
+        action: AuditAction::Create,
+        entity: AuditEntity::Site,
+        entity_id: new_site_id,
+        before: None,
+        after: Some(new_site.after),
+    }
+    .record(&mut *tx)
+    .await?;
 
-    Redirect::to(&format!("/my/sites/{new_site_id}"))
+    tx.commit().await.into_diagnostic()?;
+
+    Ok(Redirect::to(&format!("/my/sites/{new_site_id}")).into_response())
 }
 
 pub struct Site {
     pub site_id: Uuid,
-    pub user_id: Uuid,
+    pub organization_id: Uuid,
     pub name: String,
     pub domain: String,
     pub description: Option<String>,
+    pub propagate_trace_context: bool,
+    /// The current user's role in the site's organization
+    pub role: Role,
+}
+
+impl Site {
+    /// For handlers that change the site, since anyone in the organization can
+    /// load it
+    pub fn require_role(&self, required: Role) -> Result<(), AppError> {
+        authz::require(Some(self.role), required).map(|_| ())
+    }
 }
 
 #[derive(serde::Deserialize)]
@@ -231,59 +558,81 @@ struct SiteParams {
 
 #[async_trait::async_trait]
 impl FromRequestParts<AppState> for Site {
-    type Rejection = SessionRedirect;
+    type Rejection = Response;
 
     async fn from_request_parts(
         parts: &mut Parts,
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
-        let session = DBSession::from_request_parts(parts, state).await?;
+        let session = DBSession::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
 
         let Path(SiteParams { site_id }) =
             axum::extract::Path::<SiteParams>::from_request_parts(parts, state)
                 .await
-                .unwrap();
+                .map_err(|_| AppError::NotFound.into_response())?;
+
+        let role = authz::require_site_role(state.db(), session.user_id, site_id, Role::Viewer)
+            .await
+            .map_err(IntoResponse::into_response)?;
 
-        let site = sqlx::query_as!(
-            Site,
+        let site = sqlx::query!(
             r#"
           SELECT *
           FROM Sites
-          WHERE site_id = $1 AND user_id = $2
+          WHERE site_id = $1
         "#,
-            site_id,
-            session.user_id
+            site_id
         )
-        .fetch_optional(state.db())
+        .fetch_one(state.db())
         .await
-        .unwrap();
-
-        let site = match site {
-            Some(site) => site,
-            None => panic!("TODO: 404"),
-        };
-
-        Ok(site)
+        .into_diagnostic()
+        .map_err(|err| AppError::from(err).into_response())?;
+
+        Ok(Site {
+            site_id: site.site_id,
+            organization_id: site.organization_id,
+            name: site.name,
+            domain: site.domain,
+            description: site.description,
+            propagate_trace_context: site.propagate_trace_context,
+            role,
+        })
     }
 }
 
-pub async fn show(site: Site, State(state): State<AppState>, session: DBSession) -> Template {
+/// How much of the history a site shows when no range is picked
+fn default_site_range() -> chrono::Duration {
+    chrono::Duration::days(30)
+}
+
+pub async fn show(
+    site: Site,
+    State(state): State<AppState>,
+    session: DBSession,
+    Query(query): Query<TimeRangeQuery>,
+) -> Result<Response, AppError> {
     let pages = sqlx::query_as!(
         Page,
         r#"
       SELECT Pages.*
       FROM Pages
-      JOIN Sites ON Sites.site_id = Pages.site_id
-      WHERE Pages.site_id = $1 AND Sites.user_id = $2
+      WHERE Pages.site_id = $1
     "#,
-        site.site_id,
-        session.user_id
+        site.site_id
     )
     .fetch_all(state.db())
     .await
-    .unwrap();
+    .into_diagnostic()?;
 
-    html! {
+    let range = query.range(default_site_range())?;
+
+    let uptime_bar = site_uptime_bar(&site, &state).await?;
+    let stats_overview = site_stats_overview(&site, &pages, &state, range).await?;
+    let slo_overview = slo_overview(&site, &state).await?;
+
+    Ok(html! {
       h1 { (site.name) }
 
       @if let Some(description) = site.description.as_ref() {
@@ -292,23 +641,41 @@ pub async fn show(site: Site, State(state): State<AppState>, session: DBSession)
 
       a href=(format!("https://{}", site.domain)) rel="noopener" { "Visit Site" }
 
-      form action=(format!("/my/sites/{}/refresh", site.site_id)) method="get" data-target=".refresh" data-app="LiveForm" {
-        select name="days" {
-          option value="3" { "3 days" }
-          option value="7" { "7 days" }
-          option value="14" { "14 days" }
-          option value="30" selected { "30 days" }
-          option value="90" { "90 days" }
-        }
+      a href=(format!("/my/sites/{}/reports", site.site_id)) { "Uptime Reports" }
+
+      a href=(format!("/my/sites/{}/audit-log", site.site_id)) { "Audit Log" }
+
+      h2 { "Last 90 Days" }
+      (uptime_bar)
+
+      form action=(format!("/my/sites/{}/refresh", site.site_id)) method="get" data-target=".refresh" data-app="LiveForm" data-live-site=(site.site_id) data-sync-url {
+        (query.form_fields("Last 30 days"))
 
         div class="refresh" {
-          (site_stats_overview(&site, &pages, &state, chrono::Duration::days(31)).await)
+          (stats_overview)
+        }
+      }
+
+      (slo_overview)
+
+      (export_form(&format!("/my/sites/{}/checkins/export", site.site_id), "Export Checkins"))
+
+      @if site.role >= Role::Editor {
+        form method="post" action=(format!("/my/sites/{}/trace-context", site.site_id)) {
+          label {
+            input type="checkbox" name="propagate_trace_context" value="true" checked[site.propagate_trace_context];
+            "Send a W3C traceparent header with checkins, so they show up in your own traces"
+          }
+
+          button type="submit" { "Save" }
         }
       }
 
       h2 { "Pages" }
 
-      a href=(format!("/my/sites/{}/pages/new", site.site_id)) { "Create a new page" }
+      @if site.role >= Role::Editor {
+        a href=(format!("/my/sites/{}/pages/new", site.site_id)) { "Create a new page" }
+      }
 
       ul {
         @for page in pages {
@@ -321,42 +688,121 @@ pub async fn show(site: Site, State(state): State<AppState>, session: DBSession)
       }
     }
     .into_template(state, Some(session))
-    .await
-    .unwrap()
+    .await?
+    .into_response())
+}
+
+/// How many days of history the uptime bar on the site page covers
+const UPTIME_BAR_DAYS: i64 = 90;
+
+async fn site_uptime_bar(site: &Site, state: &AppState) -> miette::Result<UptimeBar> {
+    let now = Utc::now();
+    let range = now - chrono::Duration::days(UPTIME_BAR_DAYS - 1)..now;
+
+    let buckets = uptime_buckets(
+        state.db(),
+        StatsScope::Site(site.site_id),
+        range.clone(),
+        Resolution::Day,
+    )
+    .await?;
+    let incidents = incidents_for_range(state.db(), site.site_id, range).await?;
+
+    Ok(UptimeBar {
+        buckets,
+        resolution: Resolution::Day,
+        incidents,
+    })
 }
 
 #[derive(serde::Deserialize)]
-pub struct RefreshQuery {
-    days: i32,
+pub struct TraceContextFormData {
+    #[serde(default)]
+    propagate_trace_context: bool,
+}
+
+pub async fn update_trace_context(
+    site: Site,
+    session: DBSession,
+    State(state): State<AppState>,
+    Form(form_data): Form<TraceContextFormData>,
+) -> Result<Response, AppError> {
+    site.require_role(Role::Editor)?;
+
+    let mut tx = state.db().begin().await.into_diagnostic()?;
+
+    let before = sqlx::query_scalar!(
+        r#"
+    SELECT to_jsonb(Sites) as "before!"
+    FROM Sites
+    WHERE site_id = $1
+    FOR UPDATE
+  "#,
+        site.site_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .into_diagnostic()?;
+
+    let after = sqlx::query_scalar!(
+        r#"
+    UPDATE Sites
+    SET propagate_trace_context = $1
+    WHERE site_id = $2
+    RETURNING to_jsonb(Sites) as "after!"
+  "#,
+        form_data.propagate_trace_context,
+        site.site_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .into_diagnostic()?;
+
+    // Saving the form without changing anything isn't worth an entry
+    if before != after {
+        AuditChange {
+            organization_id: site.organization_id,
+            site_id: site.site_id,
+            actor_user_id: session.user_id,
+            action: AuditAction::Update,
+            entity: AuditEntity::Site,
+            entity_id: site.site_id,
+            before: Some(before),
+            after: Some(after),
+        }
+        .record(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await.into_diagnostic()?;
+
+    Ok(Redirect::to(&format!("/my/sites/{}", site.site_id)).into_response())
 }
 
 pub async fn refresh(
-    Query(RefreshQuery { days }): Query<RefreshQuery>,
+    Query(query): Query<TimeRangeQuery>,
     site: Site,
     State(state): State<AppState>,
-    session: DBSession,
-) -> impl IntoResponse {
+) -> Result<Response, AppError> {
     let pages = sqlx::query_as!(
         Page,
         r#"
       SELECT Pages.*
       FROM Pages
-      JOIN Sites ON Sites.site_id = Pages.site_id
-      WHERE Pages.site_id = $1 AND Sites.user_id = $2
+      WHERE Pages.site_id = $1
     "#,
-        site.site_id,
-        session.user_id
+        site.site_id
     )
     .fetch_all(state.db())
     .await
-    .unwrap();
+    .into_diagnostic()?;
 
-    let duration = chrono::Duration::days(days as i64);
+    let range = query.range(default_site_range())?;
 
-    html! {
-      (site_stats_overview(&site, &pages, &state, duration).await)
-    }
-    .0
+    Ok(site_stats_overview(&site, &pages, &state, range)
+        .await?
+        .0
+        .into_response())
 }
 
 pub struct Change {
@@ -465,57 +911,33 @@ impl Render for Change {
     }
 }
 
-pub fn chrono_to_pg_interval(duration: Duration) -> PgInterval {
-    let std = duration.to_std().unwrap();
-    std.try_into().unwrap()
-}
-
 async fn site_stats_overview(
     site: &Site,
     pages: &[Page],
     state: &AppState,
-    recent_duration: chrono::Duration,
-) -> maud::Markup {
+    range: Range<DateTime<Utc>>,
+) -> miette::Result<maud::Markup> {
     let pages_tracked = pages.len();
 
-    let all_interval = chrono_to_pg_interval(recent_duration * 2);
-    let all_checkins = sqlx::query_as!(
-        Checkin,
-        r#"
-    SELECT Checkins.*
-    FROM Checkins
-    JOIN Pages using (page_id)
-    WHERE Pages.site_id = $1 AND
-          now() - Checkins.created_at < $2
-    ORDER BY Checkins.created_at DESC
-  "#,
-        site.site_id,
-        all_interval
-    )
-    .fetch_all(state.db())
-    .await
-    .unwrap();
-    let split_point = all_checkins
-        .iter()
-        .position(|checkin| checkin.created_at < chrono::Utc::now() - recent_duration)
-        .unwrap_or(all_checkins.len());
-
-    let mut new_checkins = all_checkins;
-    let old_checkins = new_checkins.split_off(split_point);
-    let new_checkins = new_checkins;
+    let scope = StatsScope::Site(site.site_id);
+    let previous_range = range.start - (range.end - range.start)..range.start;
+    let new_stats = stats_for_range(state.db(), scope, range, state.checkin_retention).await?;
+    let old_stats =
+        stats_for_range(state.db(), scope, previous_range, state.checkin_retention).await?;
 
-    let avg_response_time = avg_response_time_for_checkins(&new_checkins);
+    let avg_response_time = new_stats.avg_response_time_ms();
 
-    let response_time_change = calculate_response_time_change(&old_checkins, avg_response_time);
+    let response_time_change =
+        calculate_response_time_change(old_stats.avg_response_time_ms(), avg_response_time);
 
-    let avg_response_time = format!("{:.1} ms", avg_response_time);
+    let avg_response_time = format_response_time(avg_response_time);
 
-    let succesful_percent = success_percent_for_checkins(&new_checkins);
+    let succesful_percent = new_stats.success_percent();
     let successful_percent_change =
-        calculate_percentile_change(old_checkins, &new_checkins, succesful_percent);
+        calculate_percentile_change(&old_stats, &new_stats, succesful_percent);
     let succesful_percent = format!("{:.1}%", succesful_percent);
 
-    html! {
+    Ok(html! {
         dl."mt-5 grid grid-cols-1 gap-5 sm:grid-cols-2 lg:grid-cols-3" {
             (
                 single_stat("Pages Tracked", pages_tracked, None, "fa-file")
@@ -526,19 +948,46 @@ async fn site_stats_overview(
             (
                 single_stat("Success Rate", succesful_percent, successful_percent_change, "fa-check")
             )
+            (percentile_stats(&old_stats, &new_stats))
+        }
+    })
+}
+
+pub fn percentile_stats(old_stats: &CheckinStats, new_stats: &CheckinStats) -> maud::Markup {
+    html! {
+        @for percentile in Percentile::ALL {
+            @let response_time = new_stats.percentile_response_time_ms(percentile);
+            @let change = calculate_response_time_change(
+                old_stats.percentile_response_time_ms(percentile),
+                response_time,
+            );
+            (
+                single_stat(
+                    &format!("{} Response Time", percentile.label()),
+                    format_response_time(response_time),
+                    change,
+                    "fa-stopwatch",
+                )
+            )
         }
     }
 }
 
+pub fn format_response_time(response_time: Option<f64>) -> String {
+    response_time
+        .map(|response_time| format!("{:.1} ms", response_time))
+        .unwrap_or_else(|| "-".to_string())
+}
+
 pub fn calculate_percentile_change(
-    old_checkins: Vec<Checkin>,
-    new_checkins: &Vec<Checkin>,
+    old_stats: &CheckinStats,
+    new_stats: &CheckinStats,
     succesful_percent: f64,
 ) -> Option<Change> {
-    let successful_percent_change = if old_checkins.is_empty() || new_checkins.is_empty() {
+    let successful_percent_change = if old_stats.is_empty() || new_stats.is_empty() {
         None
     } else {
-        let old_succesful_percent = success_percent_for_checkins(&old_checkins);
+        let old_succesful_percent = old_stats.success_percent();
 
         let succesful_percent_change = old_succesful_percent - succesful_percent;
         if succesful_percent_change.abs() < 0.00001 {
@@ -562,15 +1011,13 @@ pub fn calculate_percentile_change(
 }
 
 pub fn calculate_response_time_change(
-    old_checkins: &Vec<Checkin>,
-    avg_response_time: f64,
+    old_response_time: Option<f64>,
+    new_response_time: Option<f64>,
 ) -> Option<Change> {
-    let response_time_change = if old_checkins.is_empty() {
-        None
-    } else {
-        let old_response_time = avg_response_time_for_checkins(old_checkins);
-
-        let response_time_change = old_response_time - avg_response_time;
+    let response_time_change = if let (Some(old_response_time), Some(new_response_time)) =
+        (old_response_time, new_response_time)
+    {
+        let response_time_change = old_response_time - new_response_time;
         let response_time_change = response_time_change / old_response_time * 100.0;
 
         let response_time_abs_change = response_time_change.abs();
@@ -592,25 +1039,8 @@ pub fn calculate_response_time_change(
 
             Some(response_time_change)
         }
+    } else {
+        None
     };
     response_time_change
 }
-
-pub fn success_percent_for_checkins(checkins: &[Checkin]) -> f64 {
-    let count_successful = checkins
-        .iter()
-        .filter(|checkin| checkin.outcome == "success")
-        .count();
-
-    count_successful as f64 / checkins.len() as f64 * 100.0
-}
-
-pub fn avg_response_time_for_checkins(checkins: &[Checkin]) -> f64 {
-    let total_nanos = checkins
-        .iter()
-        .filter_map(|checkin| checkin.duration_nanos)
-        .sum::<i64>();
-    let total_millis = total_nanos as f64 / 1_000_000.0;
-
-    total_millis / checkins.len() as f64
-}
@@ -0,0 +1,238 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+use cja::{app_state::AppState as _, server::session::DBSession};
+use maud::html;
+use miette::IntoDiagnostic;
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    audit::{AuditAction, AuditChange, AuditEntity},
+    authz::Role,
+    errors::AppError,
+    slo::{Slo, SloAlert, FAST_BURN_RATE},
+    templates::IntoTemplate,
+};
+
+use super::{
+    pages::Page,
+    sites::{empty_string_is_none, single_stat, Site},
+};
+
+pub async fn new(
+    site: Site,
+    session: DBSession,
+    State(app_state): State<AppState>,
+) -> Result<Response, AppError> {
+    site.require_role(Role::Editor)?;
+
+    let pages = sqlx::query_as!(
+        Page,
+        r#"
+      SELECT Pages.*
+      FROM Pages
+      WHERE Pages.site_id = $1
+    "#,
+        site.site_id
+    )
+    .fetch_all(app_state.db())
+    .await
+    .into_diagnostic()?;
+
+    Ok(html! {
+      h1 { "New SLO" }
+
+      form method="post" action=(format!("/my/sites/{}/slos", site.site_id)) {
+        label {
+          "Name"
+          input type="text" name="name" required;
+        }
+
+        label {
+          "Page"
+          select name="page_id" {
+            option value="" { "Whole site" }
+            @for page in pages {
+              option value=(page.page_id) { (page.name) " - " (page.path) }
+            }
+          }
+        }
+
+        label {
+          "Target %"
+          input type="number" name="target_percent" min="0" max="100" step="0.001" value="99.9" required;
+        }
+
+        label {
+          "Window (days)"
+          input type="number" name="window_days" min="1" max=(app_state.checkin_retention.num_days()) value="30" required;
+        }
+
+        label {
+          "Latency threshold (ms)"
+          input type="number" name="latency_threshold_ms" min="1";
+        }
+
+        button type="submit" { "Create" }
+      }
+    }
+    .into_template(app_state, Some(session))
+    .await?
+    .into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub struct SloFormData {
+    name: String,
+    #[serde(deserialize_with = "empty_string_is_none")]
+    page_id: Option<Uuid>,
+    target_percent: f64,
+    window_days: i32,
+    #[serde(deserialize_with = "empty_string_is_none")]
+    latency_threshold_ms: Option<i32>,
+}
+
+pub async fn create(
+    site: Site,
+    session: DBSession,
+    State(state): State<AppState>,
+    Form(form_data): Form<SloFormData>,
+) -> Result<Response, AppError> {
+    site.require_role(Role::Editor)?;
+
+    if !(form_data.target_percent > 0.0 && form_data.target_percent < 100.0) {
+        return Err(AppError::Invalid(
+            "Target must be between 0 and 100%".to_string(),
+        ));
+    }
+
+    // SLOs are calculated from the raw checkins, so the window can't be longer
+    // than we keep those around for
+    let max_window_days = state.checkin_retention.num_days();
+    if form_data.window_days < 1 || i64::from(form_data.window_days) > max_window_days {
+        return Err(AppError::Invalid(format!(
+            "Window must be between 1 and {max_window_days} days"
+        )));
+    }
+
+    if let Some(page_id) = form_data.page_id {
+        let page_in_site = sqlx::query!(
+            r#"
+        SELECT page_id
+        FROM Pages
+        WHERE page_id = $1 AND site_id = $2
+      "#,
+            page_id,
+            site.site_id
+        )
+        .fetch_optional(state.db())
+        .await
+        .into_diagnostic()?
+        .is_some();
+
+        if !page_in_site {
+            return Err(AppError::Invalid("Unknown page".to_string()));
+        }
+    }
+
+    let mut tx = state.db().begin().await.into_diagnostic()?;
+
+    let slo = sqlx::query!(
+        r#"
+    INSERT INTO Slos (site_id, page_id, name, target_percent, window_days, latency_threshold_ms)
+    VALUES ($1, $2, $3, $4, $5, $6)
+    RETURNING slo_id, to_jsonb(Slos) as "after!"
+  "#,
+        site.site_id,
+        form_data.page_id,
+        form_data.name,
+        form_data.target_percent,
+        form_data.window_days,
+        form_data.latency_threshold_ms
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .into_diagnostic()?;
+
+    AuditChange {
+        organization_id: site.organization_id,
+        site_id: site.site_id,
+        actor_user_id: session.user_id,
+        action: AuditAction::Create,
+        entity: AuditEntity::Slo,
+        entity_id: slo.slo_id,
+        before: None,
+        after: Some(slo.after),
+    }
+    .record(&mut *tx)
+    .await?;
+
+    tx.commit().await.into_diagnostic()?;
+
+    Ok(Redirect::to(&format!("/my/sites/{}", site.site_id)).into_response())
+}
+
+pub async fn slo_overview(site: &Site, state: &AppState) -> miette::Result<maud::Markup> {
+    let slos = Slo::for_site(state.db(), site.site_id).await?;
+
+    let mut reports = vec![];
+    for slo in slos {
+        let report = slo.report(state.db()).await?;
+        let open_alert = SloAlert::open_for_slo(state.db(), report.slo.slo_id).await?;
+
+        reports.push((report, open_alert));
+    }
+
+    let format_percent = |percent: Option<f64>| {
+        percent
+            .map(|percent| format!("{:.3}%", percent))
+            .unwrap_or_else(|| "-".to_string())
+    };
+    let format_burn_rate = |burn_rate: Option<f64>| {
+        burn_rate
+            .map(|burn_rate| format!("{:.2}x", burn_rate))
+            .unwrap_or_else(|| "-".to_string())
+    };
+
+    Ok(html! {
+      h2 { "Service Level Objectives" }
+
+      @if site.role >= Role::Editor {
+        a href=(format!("/my/sites/{}/slos/new", site.site_id)) { "Create a new SLO" }
+      }
+
+      @for (report, open_alert) in reports {
+        div."mt-5" {
+          h3."text-base font-semibold leading-6 text-gray-900" {
+            (report.slo.name)
+            " - "
+            (format!("{}% over {} days", report.slo.target_percent, report.slo.window_days))
+            @if let Some(latency_threshold_ms) = report.slo.latency_threshold_ms {
+              (format!(" within {latency_threshold_ms} ms"))
+            }
+          }
+
+          @if let Some(alert) = open_alert {
+            p."rounded-md mt-2 px-2 py-1 text-sm font-medium ring-1 ring-inset text-red-700 bg-red-50 ring-red-600/20" {
+              (format!(
+                "Burning error budget at {:.1}x since {} (alert threshold {}x)",
+                alert.burn_rate,
+                alert.fired_at.format("%D %H:%M"),
+                FAST_BURN_RATE
+              ))
+            }
+          }
+
+          dl."mt-5 grid grid-cols-1 gap-5 sm:grid-cols-2 lg:grid-cols-4" {
+            (single_stat("Attained", format_percent(report.attained_percent()), None, "fa-bullseye"))
+            (single_stat("Error Budget Left", format_percent(report.budget_remaining().map(|b| b * 100.0)), None, "fa-piggy-bank"))
+            (single_stat("Burn Rate", format_burn_rate(report.burn_rate()), None, "fa-fire"))
+            (single_stat("Burn Rate (1h)", format_burn_rate(report.recent_burn_rate()), None, "fa-fire-flame-curved"))
+          }
+        }
+      }
+    })
+}
@@ -0,0 +1,103 @@
+use std::{fmt::Display, ops::Range, str::FromStr};
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use maud::html;
+use serde::Deserialize;
+
+use super::sites::empty_string_is_none;
+use crate::errors::AppError;
+
+/// The format of a `datetime-local` input
+const LOCAL_FORMAT: &str = "%Y-%m-%dT%H:%M";
+
+/// A date and time without a timezone, as entered in a `datetime-local` input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalDateTime(pub NaiveDateTime);
+
+impl FromStr for LocalDateTime {
+    type Err = chrono::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Browsers only include the seconds when they aren't zero
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+            .or_else(|_| NaiveDateTime::parse_from_str(s, LOCAL_FORMAT))
+            .map(LocalDateTime)
+    }
+}
+
+impl Display for LocalDateTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.format(LOCAL_FORMAT))
+    }
+}
+
+/// The range a page or site is viewed over. Both ends are local to `tz`, and
+/// when they're left out the range ends now and covers the default length for
+/// the view, so the graphs keep moving as new checkins come in
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct TimeRangeQuery {
+    #[serde(default, deserialize_with = "empty_string_is_none")]
+    start: Option<LocalDateTime>,
+    #[serde(default, deserialize_with = "empty_string_is_none")]
+    end: Option<LocalDateTime>,
+    /// An IANA timezone name, filled in from the browser. Defaults to UTC
+    #[serde(default, deserialize_with = "empty_string_is_none")]
+    tz: Option<Tz>,
+}
+
+impl TimeRangeQuery {
+    pub fn tz(&self) -> Tz {
+        self.tz.unwrap_or(Tz::UTC)
+    }
+
+    pub fn range(
+        &self,
+        default_length: chrono::Duration,
+    ) -> Result<Range<DateTime<Utc>>, AppError> {
+        let end = match self.end {
+            Some(end) => self.to_utc(end)?,
+            None => Utc::now(),
+        };
+        let start = match self.start {
+            Some(start) => self.to_utc(start)?,
+            None => end - default_length,
+        };
+
+        if start >= end {
+            return Err(AppError::Invalid("Start must be before end".to_string()));
+        }
+
+        Ok(start..end)
+    }
+
+    fn to_utc(&self, local: LocalDateTime) -> Result<DateTime<Utc>, AppError> {
+        // Times that are skipped by a DST change don't exist in `tz`, and
+        // times that happen twice resolve to the first of the two
+        self.tz()
+            .from_local_datetime(&local.0)
+            .earliest()
+            .map(|time| time.with_timezone(&Utc))
+            .ok_or_else(|| AppError::Invalid(format!("{local} doesn't exist in {}", self.tz())))
+    }
+
+    /// The inputs for picking the range, to go in a `LiveForm`. The graphs in
+    /// the form update these when they're zoomed
+    pub fn form_fields(&self, default_label: &str) -> maud::Markup {
+        html! {
+          label {
+            "From"
+            input type="datetime-local" name="start" value=[self.start];
+          }
+
+          label {
+            "To"
+            input type="datetime-local" name="end" value=[self.end];
+          }
+
+          input type="hidden" name="tz" value=[self.tz.map(|tz| tz.name())] data-browser-timezone;
+
+          a href="?" { (default_label) }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use chrono::Utc;
+use maud::{html, Render};
+
+use crate::{
+    incidents::Incident,
+    stats::{Resolution, UptimeBucket},
+};
+
+/// The classic status page bar, with one bar per hour or day coloured by how
+/// many of that bucket's checkins succeeded. Hovering a bar shows its counts
+/// and how many incidents overlapped it
+pub struct UptimeBar {
+    pub buckets: Vec<UptimeBucket>,
+    pub resolution: Resolution,
+    /// Incidents to count against each bucket they overlap
+    pub incidents: Vec<Incident>,
+}
+
+impl UptimeBar {
+    fn bucket_label(&self, bucket: &UptimeBucket) -> String {
+        match self.resolution {
+            Resolution::Hour => bucket.bucket_start.format("%b %-d %H:00 UTC").to_string(),
+            Resolution::Day => bucket.bucket_start.format("%b %-d, %Y").to_string(),
+        }
+    }
+
+    fn incident_count(&self, bucket: &UptimeBucket) -> usize {
+        let bucket_end = bucket.bucket_start + self.resolution.duration();
+
+        self.incidents
+            .iter()
+            .filter(|incident| {
+                incident.started_at < bucket_end
+                    && incident.ended_at.unwrap_or_else(Utc::now) > bucket.bucket_start
+            })
+            .count()
+    }
+
+    fn tooltip(&self, bucket: &UptimeBucket) -> String {
+        let label = self.bucket_label(bucket);
+
+        let Some(percent) = bucket.success_percent() else {
+            return format!("{label}: No checkins");
+        };
+
+        let incidents = match self.incident_count(bucket) {
+            0 => "No incidents".to_string(),
+            1 => "1 incident".to_string(),
+            count => format!("{count} incidents"),
+        };
+
+        format!(
+            "{label}: {percent:.2}% up ({} of {} checkins succeeded). {incidents}",
+            bucket.success_count, bucket.count
+        )
+    }
+
+    fn overall_percent(&self) -> Option<f64> {
+        let count = self.buckets.iter().map(|b| b.count).sum::<i64>();
+        let success_count = self.buckets.iter().map(|b| b.success_count).sum::<i64>();
+
+        (count > 0).then(|| success_count as f64 / count as f64 * 100.0)
+    }
+}
+
+fn fill_class(success_percent: Option<f64>) -> &'static str {
+    match success_percent {
+        None => "fill-gray-200",
+        Some(percent) if percent >= 99.9 => "fill-green-500",
+        Some(percent) if percent >= 99.0 => "fill-lime-400",
+        Some(percent) if percent >= 95.0 => "fill-yellow-400",
+        Some(_) => "fill-red-500",
+    }
+}
+
+impl Render for UptimeBar {
+    fn render(&self) -> maud::Markup {
+        if self.buckets.is_empty() {
+            return html! { "No data found" };
+        }
+
+        let width = 200.0;
+        let height = 20.0;
+
+        let slot_width = width / self.buckets.len() as f64;
+        let bar_width = slot_width * 0.8;
+
+        let first = self.buckets.first().unwrap();
+        let last = self.buckets.last().unwrap();
+
+        html! {
+          div {
+            svg class="w-full" viewBox=(format!("0 0 {width} {height}")) preserveAspectRatio="none" {
+              @for (i, bucket) in self.buckets.iter().enumerate() {
+                rect
+                  x=(i as f64 * slot_width + (slot_width - bar_width) / 2.0)
+                  y="0"
+                  width=(bar_width)
+                  height=(height)
+                  rx="0.5"
+                  class=(format!("{} hover:opacity-75", fill_class(bucket.success_percent())))
+                {
+                  title { (self.tooltip(bucket)) }
+                }
+              }
+            }
+
+            div."flex justify-between text-xs text-gray-500" {
+              span { (self.bucket_label(first)) }
+              span {
+                @if let Some(percent) = self.overall_percent() {
+                  (format!("{percent:.2}% uptime"))
+                } @else {
+                  "No checkins"
+                }
+              }
+              span { (self.bucket_label(last)) }
+            }
+          }
+        }
+    }
+}
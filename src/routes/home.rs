@@ -1,45 +1,303 @@
-use axum::extract::State;
-use cja::server::session::DBSession;
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use cja::{app_state::AppState as _, server::session::DBSession};
+use maud::html;
+use miette::IntoDiagnostic;
+use sqlx::PgPool;
+use uuid::Uuid;
 
 use crate::{
     app_state::AppState,
-    templates::{IntoTemplate, Template},
+    authz,
+    errors::AppError,
+    incidents::open_incidents_for_sites,
+    reports::format_duration,
+    routes::current_user::{
+        pages::{BucketedCheckinGraph, GraphSeries},
+        sites::{format_response_time, single_stat, site_table_rows},
+    },
+    stats::{checkin_buckets, stats_for_range, StatsScope},
+    templates::IntoTemplate,
 };
 
-pub async fn show(session: Option<DBSession>, State(state): State<AppState>) -> Template {
-    maud::html! {
-        html {
-            head {
-                script src="https://unpkg.com/htmx.org@1.9.10" {}
-                link rel="stylesheet" href="/styles/tailwind.css" {}
+/// How many pages to list in the slowest pages table
+const SLOWEST_PAGES_LIMIT: i64 = 5;
+/// How many of the most recent up/down transitions to list
+const STATE_CHANGES_LIMIT: i64 = 10;
+
+pub async fn show(
+    session: Option<DBSession>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let content = match session.as_ref() {
+        Some(session) => dashboard(session, &state).await?,
+        None => html! {
+            h1 { "Hello, world!" }
+
+            p { "You are not logged in!" }
+            p { "Click " a href="/login" { "here" } " to login!" }
 
-                title { "UpGuardian - Uptime Monitoring by coreyja" }
+            p { "This is a Uptime Tracker built by "
+                a href="https://coreyja.com" { "coreyja!" }
             }
+        },
+    };
+
+    Ok(content.into_template(state, session).await?.into_response())
+}
+
+async fn dashboard(session: &DBSession, state: &AppState) -> miette::Result<maud::Markup> {
+    let db = state.db();
+    let now = Utc::now();
+    let scope = StatsScope::User(session.user_id);
+
+    let mut uptimes = vec![];
+    for (label, duration) in [
+        ("Uptime (24h)", chrono::Duration::hours(24)),
+        ("Uptime (7d)", chrono::Duration::days(7)),
+        ("Uptime (30d)", chrono::Duration::days(30)),
+    ] {
+        let stats =
+            stats_for_range(db, scope, now - duration..now, state.checkin_retention).await?;
+        let uptime = if stats.is_empty() {
+            "-".to_string()
+        } else {
+            format!("{:.2}%", stats.success_percent())
+        };
+
+        uptimes.push((label, uptime));
+    }
 
-            body {
-                @if let Some(session) = session.clone() {
-                    h1 { "Hello, " (session.user_id) "!" }
-                    h3 { "Session Id: " (session.session_id) }
+    let mut sites = site_table_rows(db, session.user_id).await?;
+    sites.sort_by_key(|site| site.health());
 
-                    form method="POST" action="/logout" {
-                        button type="submit" { "Logout" }
-                    }
+    let site_ids = authz::accessible_site_ids(db, session.user_id).await?;
+    let incidents = open_incidents_for_sites(db, &site_ids).await?;
+    let slowest_pages = slowest_pages(db, &site_ids, now - chrono::Duration::hours(24)).await?;
+    let state_changes = recent_state_changes(db, &site_ids).await?;
 
-                    a href="/my/sites" { "My Sites" }
-                } @else {
-                    h1 { "Hello, world!" }
+    let recent_duration = chrono::Duration::hours(24);
+    let buckets = checkin_buckets(
+        db,
+        scope,
+        now - recent_duration..now,
+        24,
+        state.checkin_retention,
+    )
+    .await?;
+    let graph = BucketedCheckinGraph {
+        buckets,
+        range: now - recent_duration..now,
+        series: GraphSeries::P95,
+        tz: chrono_tz::Tz::UTC,
+    };
 
-                    p { "You are not logged in!" }
-                    p { "Click " a href="/login" { "here" } " to login!" }
+    Ok(html! {
+      h1 { "Dashboard" }
+
+      dl."mt-5 grid grid-cols-1 gap-5 sm:grid-cols-2 lg:grid-cols-3" {
+        @for (label, uptime) in uptimes {
+          (single_stat(label, uptime, None, "fa-heart-pulse"))
+        }
+      }
+
+      h2."mt-8 text-xl font-semibold text-gray-900" { "P95 Response Time (24h)" }
+      (graph)
+
+      h2."mt-8 text-xl font-semibold text-gray-900" { "Sites" }
+      @if sites.is_empty() {
+        p { "You aren't tracking any sites yet. " a href="/my/sites/new" { "Create one" } }
+      } @else {
+        ul."divide-y divide-gray-100" role="list" {
+          @for site in sites {
+            li."flex items-center justify-between gap-x-6 py-5" {
+              (site)
+            }
+          }
+        }
+      }
+
+      h2."mt-8 text-xl font-semibold text-gray-900" { "Open Incidents" }
+      @if incidents.is_empty() {
+        p { "Everything is up" }
+      } @else {
+        table."min-w-full divide-y divide-gray-300" {
+          thead {
+            tr {
+              th."text-left" { "Page" }
+              th."text-left" { "Started" }
+              th."text-left" { "Duration" }
+              th."text-left" { "Failed Checkins" }
+            }
+          }
+          tbody."divide-y divide-gray-200" {
+            @for incident in &incidents {
+              tr {
+                td {
+                  a href=(format!("/my/sites/{}/pages/{}", incident.site_id, incident.page_id)) {
+                    (incident.page_name)
+                  }
                 }
+                td { (incident.started_at.format("%D %H:%M UTC")) }
+                td { (format_duration(incident.duration())) }
+                td { (incident.failed_checkins) }
+              }
+            }
+          }
+        }
+      }
 
-                p { "This is a Uptime Tracker built by "
-                    a href="https://coreyja.com" { "coreyja!" }
+      h2."mt-8 text-xl font-semibold text-gray-900" { "Slowest Pages (24h)" }
+      @if slowest_pages.is_empty() {
+        p { "No checkins in the last 24 hours" }
+      } @else {
+        table."min-w-full divide-y divide-gray-300" {
+          thead {
+            tr {
+              th."text-left" { "Page" }
+              th."text-left" { "Site" }
+              th."text-left" { "Avg. Response Time" }
+              th."text-left" { "P95 Response Time" }
+            }
+          }
+          tbody."divide-y divide-gray-200" {
+            @for page in &slowest_pages {
+              tr {
+                td {
+                  a href=(format!("/my/sites/{}/pages/{}", page.site_id, page.page_id)) {
+                    (page.page_name) " - " (page.path)
+                  }
                 }
+                td { (page.site_name) }
+                td { (format_response_time(page.avg_duration_nanos.map(|nanos| nanos / 1_000_000.0))) }
+                td { (format_response_time(page.p95_duration_nanos.map(|nanos| nanos as f64 / 1_000_000.0))) }
+              }
             }
+          }
         }
-    }
-    .into_template(state, session)
+      }
+
+      h2."mt-8 text-xl font-semibold text-gray-900" { "Recent Status Changes" }
+      @if state_changes.is_empty() {
+        p { "No status changes in the last week" }
+      } @else {
+        ul."divide-y divide-gray-100" role="list" {
+          @for change in &state_changes {
+            li."py-2" {
+              time datetime=(change.created_at.to_rfc3339()) {
+                (change.created_at.format("%D %H:%M UTC"))
+              }
+              " "
+              a href=(format!("/my/sites/{}/pages/{}", change.site_id, change.page_id)) {
+                (change.page_name)
+              }
+              @if change.outcome == "success" {
+                span."text-green-700" { " recovered" }
+              } @else {
+                span."text-red-700" { " went down (" (change.outcome) ")" }
+              }
+            }
+          }
+        }
+      }
+
+      form method="POST" action="/logout" {
+        button type="submit" { "Logout" }
+      }
+    })
+}
+
+struct SlowPage {
+    site_id: Uuid,
+    site_name: String,
+    page_id: Uuid,
+    page_name: String,
+    path: String,
+    avg_duration_nanos: Option<f64>,
+    p95_duration_nanos: Option<i64>,
+}
+
+async fn slowest_pages(
+    db: &PgPool,
+    site_ids: &[Uuid],
+    since: DateTime<Utc>,
+) -> miette::Result<Vec<SlowPage>> {
+    sqlx::query_as!(
+        SlowPage,
+        r#"
+    SELECT
+      Sites.site_id,
+      Sites.name as site_name,
+      Pages.page_id,
+      Pages.name as page_name,
+      Pages.path,
+      avg(Checkins.duration_nanos)::float8 as avg_duration_nanos,
+      (percentile_cont(0.95) WITHIN GROUP (ORDER BY Checkins.duration_nanos))::int8 as p95_duration_nanos
+    FROM Checkins
+    JOIN Pages USING (page_id)
+    JOIN Sites USING (site_id)
+    WHERE Sites.site_id = ANY($1)
+      AND Checkins.created_at >= $2
+      AND Checkins.duration_nanos IS NOT NULL
+    GROUP BY Sites.site_id, Pages.page_id
+    ORDER BY avg(Checkins.duration_nanos) DESC
+    LIMIT $3
+  "#,
+        site_ids,
+        since,
+        SLOWEST_PAGES_LIMIT
+    )
+    .fetch_all(db)
+    .await
+    .into_diagnostic()
+}
+
+/// A checkin whose outcome flipped between success and not success compared
+/// to the checkin before it
+struct StateChange {
+    site_id: Uuid,
+    page_id: Uuid,
+    page_name: String,
+    outcome: String,
+    created_at: DateTime<Utc>,
+}
+
+async fn recent_state_changes(db: &PgPool, site_ids: &[Uuid]) -> miette::Result<Vec<StateChange>> {
+    sqlx::query_as!(
+        StateChange,
+        r#"
+    WITH Ordered AS (
+      SELECT
+        Pages.site_id,
+        Pages.page_id,
+        Pages.name as page_name,
+        Checkins.outcome,
+        Checkins.created_at,
+        lag(Checkins.outcome) OVER (PARTITION BY Checkins.page_id ORDER BY Checkins.created_at) as previous_outcome
+      FROM Checkins
+      JOIN Pages USING (page_id)
+      WHERE Pages.site_id = ANY($1)
+        AND Checkins.created_at >= now() - interval '7 days'
+    )
+    SELECT
+      site_id as "site_id!",
+      page_id as "page_id!",
+      page_name as "page_name!",
+      outcome as "outcome!",
+      created_at as "created_at!"
+    FROM Ordered
+    WHERE previous_outcome IS NOT NULL
+      AND (outcome = 'success') <> (previous_outcome = 'success')
+    ORDER BY created_at DESC
+    LIMIT $2
+  "#,
+        site_ids,
+        STATE_CHANGES_LIMIT
+    )
+    .fetch_all(db)
     .await
-    .unwrap()
+    .into_diagnostic()
 }
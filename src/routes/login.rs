@@ -1,97 +1,513 @@
 use axum::{
     extract::{Query, State},
-    response::{IntoResponse, Redirect},
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    Form,
 };
 use cja::{app_state::AppState as _, server::session::DBSession};
-use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use maud::html;
 use miette::IntoDiagnostic;
-use serde::{Deserialize, Serialize};
-use serde_json::json;
-use tower_cookies::Cookie;
+use serde::Deserialize;
+use tower_cookies::{
+    cookie::{time, SameSite},
+    Cookie, Cookies,
+};
+use uuid::Uuid;
 
-use crate::app_state::AppState;
+use crate::{
+    app_state::AppState,
+    auth::{
+        coreyja::CoreyjaConfig,
+        local::{self, MIN_PASSWORD_LENGTH},
+        oidc::{OidcConfig, PendingLogin},
+        AuthError, AuthProvider,
+    },
+    authz,
+    errors::AppError,
+    sessions::SESSION_COOKIE,
+    templates::IntoTemplate,
+};
 
-#[derive(Debug, Deserialize)]
-pub struct LoginCallback {
-    state: String,
+/// Set when a coreyja.com login starts. This is only a best-effort check that
+/// the callback comes back to a browser that started a login here. The IdP
+/// picks the `state` itself and doesn't send back anything of ours, so the
+/// callback can't be tied to one particular attempt, and this doesn't stop a
+/// login CSRF from a page that first sends the browser to `/login`
+const COREYJA_LOGIN_COOKIE: &str = "coreyja_login";
+
+/// Holds the `PendingLogin` while the user is off at the OIDC provider
+const OIDC_LOGIN_COOKIE: &str = "oidc_login";
+
+fn oidc_redirect_uri(app_state: &AppState) -> String {
+    format!("{}/login/oidc/callback", app_state.base_url)
+}
+
+/// A private cookie that lives for one login attempt
+fn login_cookie(name: &'static str, value: String) -> Cookie<'static> {
+    Cookie::build((name, value))
+        .path("/login")
+        .http_only(true)
+        // The provider redirects back with a top level GET, which Lax still
+        // sends the cookie with
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::minutes(10))
+        .build()
+}
+
+/// Reads a login attempt's cookie and removes it, so each one is only good
+/// for a single callback
+fn take_login_cookie(
+    name: &'static str,
+    app_state: &AppState,
+    cookies: &Cookies,
+) -> Option<String> {
+    let private = cookies.private(app_state.cookie_key());
+    let value = private.get(name).map(|cookie| cookie.value().to_string());
+    private.remove(Cookie::build(name).path("/login").build());
+
+    value
+}
+
+/// Starts a session for a user who just proved who they are, however they
+/// did it
+async fn log_in(
+    user_id: Uuid,
+    app_state: &AppState,
+    cookies: &Cookies,
+) -> Result<Response, AuthError> {
+    authz::ensure_personal_organization(app_state.db(), user_id).await?;
+
+    DBSession::create(user_id, app_state, cookies).await?;
+
+    Ok(Redirect::to("/").into_response())
 }
 
-pub async fn show(session: Option<DBSession>) -> axum::response::Response {
-    if session.is_none() {
-        let idp_url =
-            std::env::var("COREYJA_IDP_URL").unwrap_or_else(|_| "https://coreyja.com".into());
-        let login_url = format!("{}/login/upguardian", idp_url);
-        Redirect::temporary(&login_url).into_response()
-    } else {
-        Redirect::temporary("/").into_response()
+pub async fn show(
+    session: Option<DBSession>,
+    cookies: Cookies,
+    State(app_state): State<AppState>,
+) -> Response {
+    if session.is_some() {
+        return Redirect::temporary("/").into_response();
+    }
+
+    match &app_state.auth {
+        AuthProvider::Coreyja(config) => {
+            cookies
+                .private(app_state.cookie_key())
+                .add(login_cookie(COREYJA_LOGIN_COOKIE, "pending".into()));
+
+            Redirect::temporary(&config.login_url()).into_response()
+        }
+        AuthProvider::Oidc(config) => {
+            let pending = PendingLogin::generate();
+            let url = match config
+                .authorization_url(&oidc_redirect_uri(&app_state), &pending)
+                .await
+            {
+                Ok(url) => url,
+                Err(err) => return err.into_page(&app_state).await,
+            };
+
+            cookies.private(app_state.cookie_key()).add(login_cookie(
+                OIDC_LOGIN_COOKIE,
+                serde_json::to_string(&pending).unwrap(),
+            ));
+
+            Redirect::temporary(&url).into_response()
+        }
+        AuthProvider::Local(config) => local_login_form(&app_state, config.allow_signups, None)
+            .await
+            .into_response(),
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct LoginCallback {
+    state: Option<String>,
+}
+
 pub async fn callback(
-    cookies: tower_cookies::Cookies,
+    cookies: Cookies,
     Query(query): Query<LoginCallback>,
     State(app_state): State<AppState>,
-) -> impl IntoResponse {
-    let idp_url = std::env::var("COREYJA_IDP_URL").unwrap_or_else(|_| "https://coreyja.com".into());
-    let client = reqwest::Client::new();
+) -> Response {
+    let AuthProvider::Coreyja(config) = &app_state.auth else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
 
-    #[derive(Debug, Serialize, Deserialize)]
-    struct Claim {
-        sub: String,
-        exp: usize,
+    match finish_coreyja_login(config, query, &app_state, &cookies).await {
+        Ok(resp) => resp,
+        Err(err) => err.into_page(&app_state).await,
     }
+}
 
-    let key = std::env::var("AUTH_PRIVATE_KEY").unwrap();
-    let token = jsonwebtoken::encode(
-        &Header::new(Algorithm::RS256),
-        &Claim {
-            sub: query.state,
-            exp: (chrono::Utc::now() + chrono::Duration::minutes(1)).timestamp() as usize,
-        },
-        &EncodingKey::from_rsa_pem(key.as_bytes()).unwrap(),
+async fn finish_coreyja_login(
+    config: &CoreyjaConfig,
+    query: LoginCallback,
+    app_state: &AppState,
+    cookies: &Cookies,
+) -> Result<Response, AuthError> {
+    if take_login_cookie(COREYJA_LOGIN_COOKIE, app_state, cookies).is_none() {
+        return Err(AuthError::StateMismatch);
+    }
+
+    let state = query
+        .state
+        .filter(|state| !state.is_empty())
+        .ok_or(AuthError::InvalidCallback("missing state"))?;
+
+    let identity = config.claim(state).await?;
+
+    let user_id = sqlx::query_scalar!(
+        "INSERT INTO Users (user_id, coreyja_user_id, is_active_sponsor) VALUES ($1, $2, $3) ON CONFLICT (coreyja_user_id) DO UPDATE SET is_active_sponsor = excluded.is_active_sponsor RETURNING user_id",
+        Uuid::new_v4(),
+        identity.user_id,
+        identity.is_active_sponsor
     )
-    .unwrap();
+    .fetch_one(app_state.db())
+    .await
+    .into_diagnostic()?;
 
-    let claim_url = format!("{}/login/upguardian", idp_url);
+    log_in(user_id, app_state, cookies).await
+}
 
-    let resp = client
-        .post(claim_url)
-        .json(&json!({ "jwt": token }))
-        .send()
-        .await
-        .into_diagnostic()
-        .unwrap();
+#[derive(Debug, Deserialize)]
+pub struct OidcCallback {
+    state: Option<String>,
+    code: Option<String>,
+    /// Set instead of `code` when the provider turned the login down
+    error: Option<String>,
+}
+
+pub async fn oidc_callback(
+    cookies: Cookies,
+    Query(query): Query<OidcCallback>,
+    State(app_state): State<AppState>,
+) -> Response {
+    let AuthProvider::Oidc(config) = &app_state.auth else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
 
-    #[derive(Debug, Serialize, Deserialize)]
-    struct ClaimResponse {
-        user_id: String,
-        is_active_sponsor: bool,
+    match finish_oidc_login(config, query, &app_state, &cookies).await {
+        Ok(resp) => resp,
+        Err(err) => err.into_page(&app_state).await,
     }
-    let json = resp.json::<ClaimResponse>().await.unwrap();
+}
 
-    let user = sqlx::query!(
-        "INSERT INTO Users (user_id, coreyja_user_id, is_active_sponsor) VALUES ($1, $2, $3) ON CONFLICT (coreyja_user_id) DO UPDATE SET is_active_sponsor = excluded.is_active_sponsor RETURNING *",
-        uuid::Uuid::new_v4(),
-        uuid::Uuid::parse_str(&json.user_id).unwrap(),
-        json.is_active_sponsor
+async fn finish_oidc_login(
+    config: &OidcConfig,
+    query: OidcCallback,
+    app_state: &AppState,
+    cookies: &Cookies,
+) -> Result<Response, AuthError> {
+    let pending = take_login_cookie(OIDC_LOGIN_COOKIE, app_state, cookies)
+        .and_then(|value| serde_json::from_str::<PendingLogin>(&value).ok())
+        .filter(|pending| query.state.as_deref() == Some(pending.state.as_str()))
+        .ok_or(AuthError::StateMismatch)?;
+
+    if let Some(error) = query.error {
+        return Err(AuthError::Rejected(error));
+    }
+    let code = query
+        .code
+        .ok_or(AuthError::InvalidCallback("missing authorization code"))?;
+
+    let identity = config
+        .finish(&oidc_redirect_uri(app_state), &pending, &code)
+        .await?;
+
+    let user_id = sqlx::query_scalar!(
+        r#"
+    INSERT INTO Users (user_id, oidc_issuer, oidc_subject)
+    VALUES ($1, $2, $3)
+    ON CONFLICT (oidc_issuer, oidc_subject) DO UPDATE SET oidc_subject = excluded.oidc_subject
+    RETURNING user_id
+  "#,
+        Uuid::new_v4(),
+        identity.issuer,
+        identity.subject
     )
     .fetch_one(app_state.db())
     .await
-    .unwrap();
+    .into_diagnostic()?;
 
-    DBSession::create(user.user_id, &app_state, &cookies)
-        .await
-        .unwrap();
+    log_in(user_id, app_state, cookies).await
+}
+
+async fn local_login_form(
+    app_state: &AppState,
+    allow_signups: bool,
+    error: Option<&str>,
+) -> impl IntoResponse {
+    html! {
+      h1 { "Log In" }
+
+      @if let Some(error) = error {
+        p."text-red-700" { (error) }
+      }
+
+      form method="post" action="/login" {
+        label {
+          "Username"
+          input type="text" name="username" autocomplete="username" required;
+        }
 
-    Redirect::temporary("/").into_response()
+        label {
+          "Password"
+          input type="password" name="password" autocomplete="current-password" required;
+        }
+
+        button type="submit" { "Log In" }
+      }
+
+      @if allow_signups {
+        p { "Don't have an account? " a href="/signup" { "Sign up" } }
+      }
+    }
+    .into_template(app_state.clone(), None)
+    .await
+    .unwrap()
+}
+
+#[derive(Deserialize)]
+pub struct LocalLoginFormData {
+    username: String,
+    password: String,
+}
+
+pub async fn local_login(
+    cookies: Cookies,
+    State(app_state): State<AppState>,
+    Form(form_data): Form<LocalLoginFormData>,
+) -> Response {
+    let AuthProvider::Local(config) = &app_state.auth else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let user = sqlx::query!(
+        r#"
+    SELECT user_id, password_hash as "password_hash!"
+    FROM Users
+    WHERE lower(username) = lower($1) AND password_hash IS NOT NULL
+  "#,
+        form_data.username.trim()
+    )
+    .fetch_optional(app_state.db())
+    .await
+    .into_diagnostic();
+    let user = match user {
+        Ok(user) => user,
+        Err(err) => return AuthError::from(err).into_page(&app_state).await,
+    };
+
+    let valid = local::verify_password(
+        form_data.password,
+        user.as_ref().map(|user| user.password_hash.clone()),
+    )
+    .await;
+
+    match user {
+        Some(user) if valid => match log_in(user.user_id, &app_state, &cookies).await {
+            Ok(resp) => resp,
+            Err(err) => err.into_page(&app_state).await,
+        },
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            local_login_form(
+                &app_state,
+                config.allow_signups,
+                Some("Wrong username or password"),
+            )
+            .await,
+        )
+            .into_response(),
+    }
+}
+
+async fn signup_form(app_state: &AppState, error: Option<&str>) -> impl IntoResponse {
+    html! {
+      h1 { "Sign Up" }
+
+      @if let Some(error) = error {
+        p."text-red-700" { (error) }
+      }
+
+      form method="post" action="/signup" {
+        label {
+          "Username"
+          input type="text" name="username" autocomplete="username" required;
+        }
+
+        label {
+          "Password"
+          input type="password" name="password" autocomplete="new-password" minlength=(MIN_PASSWORD_LENGTH) required;
+        }
+
+        button type="submit" { "Sign Up" }
+      }
+
+      p { "Already have an account? " a href="/login" { "Log in" } }
+    }
+    .into_template(app_state.clone(), None)
+    .await
+    .unwrap()
+}
+
+fn signups_allowed(app_state: &AppState) -> bool {
+    matches!(&app_state.auth, AuthProvider::Local(config) if config.allow_signups)
+}
+
+pub async fn show_signup(State(app_state): State<AppState>) -> Response {
+    if !signups_allowed(&app_state) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    signup_form(&app_state, None).await.into_response()
+}
+
+pub async fn signup(
+    cookies: Cookies,
+    State(app_state): State<AppState>,
+    Form(form_data): Form<LocalLoginFormData>,
+) -> Response {
+    if !signups_allowed(&app_state) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let username = form_data.username.trim();
+    if username.is_empty() {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            signup_form(&app_state, Some("Pick a username")).await,
+        )
+            .into_response();
+    }
+    if form_data.password.chars().count() < MIN_PASSWORD_LENGTH {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            signup_form(
+                &app_state,
+                Some(&format!(
+                    "Passwords need at least {MIN_PASSWORD_LENGTH} characters"
+                )),
+            )
+            .await,
+        )
+            .into_response();
+    }
+
+    match create_local_user(username, &form_data.password, &app_state, &cookies).await {
+        Ok(resp) => resp,
+        Err(err) => err.into_page(&app_state).await,
+    }
+}
+
+async fn create_local_user(
+    username: &str,
+    password: &str,
+    app_state: &AppState,
+    cookies: &Cookies,
+) -> Result<Response, AuthError> {
+    let password_hash = local::hash_password(password.to_string()).await?;
+
+    let user_id = sqlx::query_scalar!(
+        r#"
+    INSERT INTO Users (user_id, username, password_hash)
+    VALUES ($1, $2, $3)
+    ON CONFLICT (lower(username)) DO NOTHING
+    RETURNING user_id
+  "#,
+        Uuid::new_v4(),
+        username,
+        password_hash
+    )
+    .fetch_optional(app_state.db())
+    .await
+    .into_diagnostic()?;
+
+    let Some(user_id) = user_id else {
+        return Ok((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            signup_form(app_state, Some("That username is taken")).await,
+        )
+            .into_response());
+    };
+
+    log_in(user_id, app_state, cookies).await
 }
 
 pub async fn logout(
+    session: Option<DBSession>,
     cookies: tower_cookies::Cookies,
     State(app_state): State<AppState>,
-) -> impl IntoResponse {
+) -> Result<Response, AppError> {
+    // Otherwise anyone holding a copy of the cookie could keep using it
+    if let Some(session) = session {
+        sqlx::query!(
+            "DELETE FROM Sessions WHERE session_id = $1",
+            session.session_id
+        )
+        .execute(app_state.db())
+        .await
+        .into_diagnostic()?;
+    }
+
     let private = cookies.private(app_state.cookie_key());
-    private.remove(Cookie::new("session_id", ""));
+    private.remove(Cookie::new(SESSION_COOKIE, ""));
+
+    Ok(Redirect::to("/").into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+
+    fn coreyja_state(pool: PgPool) -> (AppState, CoreyjaConfig) {
+        let config = CoreyjaConfig::from_env();
+        let app_state = AppState::for_tests(pool, AuthProvider::Coreyja(config.clone()));
+
+        (app_state, config)
+    }
 
-    Redirect::to("/").into_response()
+    #[sqlx::test]
+    async fn coreyja_callbacks_need_a_login_started_in_this_browser(pool: PgPool) {
+        let (app_state, config) = coreyja_state(pool);
+        let query = LoginCallback {
+            state: Some("the-state".to_string()),
+        };
+
+        let err = finish_coreyja_login(&config, query, &app_state, &Cookies::default())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, AuthError::StateMismatch), "{err}");
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn coreyja_login_cookies_are_only_good_once(pool: PgPool) {
+        let (app_state, config) = coreyja_state(pool);
+        let cookies = Cookies::default();
+        cookies
+            .private(app_state.cookie_key())
+            .add(login_cookie(COREYJA_LOGIN_COOKIE, "pending".into()));
+
+        let err =
+            finish_coreyja_login(&config, LoginCallback { state: None }, &app_state, &cookies)
+                .await
+                .unwrap_err();
+        assert!(matches!(err, AuthError::InvalidCallback(_)), "{err}");
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+
+        let query = LoginCallback {
+            state: Some("the-state".to_string()),
+        };
+        let err = finish_coreyja_login(&config, query, &app_state, &cookies)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AuthError::StateMismatch), "{err}");
+    }
 }
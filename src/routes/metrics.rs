@@ -0,0 +1,26 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use cja::app_state::AppState as _;
+
+use crate::{app_state::AppState, errors::AppError};
+
+pub async fn show(State(state): State<AppState>, headers: HeaderMap) -> Result<Response, AppError> {
+    if !state.metrics.is_enabled() {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    if !state.metrics.is_authorized(&headers) {
+        return Ok((
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response());
+    }
+
+    let body = state.metrics.render(state.db()).await?;
+
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response())
+}
@@ -7,11 +7,12 @@ use axum::{
 use include_dir::Dir;
 use miette::IntoDiagnostic as _;
 
-use crate::app_state::AppState;
+use crate::{app_state::AppState, errors::AppError};
 
-mod current_user;
+pub(crate) mod current_user;
 mod home;
 mod login;
+mod metrics;
 
 const STATIC_ASSETS: Dir<'_> = include_dir::include_dir!("$CARGO_MANIFEST_DIR/public");
 
@@ -45,9 +46,56 @@ pub fn routes() -> Router<AppState> {
         .route("/", get(home::show))
         .route("/public/*path", get(static_assets))
         .route("/styles/tailwind.css", get(tailwind_css))
-        .route("/login", get(login::show))
+        .route("/login", get(login::show).post(login::local_login))
         .route("/login/callback", get(login::callback))
+        .route("/login/oidc/callback", get(login::oidc_callback))
+        .route("/signup", get(login::show_signup).post(login::signup))
         .route("/logout", post(login::logout))
+        .route("/metrics", get(metrics::show))
+        .route("/my/events", get(current_user::events::stream))
+        .route(
+            "/my/organizations",
+            get(current_user::organizations::index).post(current_user::organizations::create),
+        )
+        .route(
+            "/my/organizations/:organization_id",
+            get(current_user::organizations::show),
+        )
+        .route(
+            "/my/organizations/:organization_id/members/:user_id",
+            post(current_user::organizations::update_member),
+        )
+        .route(
+            "/my/organizations/:organization_id/members/:user_id/delete",
+            post(current_user::organizations::remove_member),
+        )
+        .route(
+            "/my/organizations/:organization_id/invitations",
+            post(current_user::organizations::invite),
+        )
+        .route(
+            "/my/organizations/:organization_id/invitations/:organization_invitation_id/delete",
+            post(current_user::organizations::revoke_invitation),
+        )
+        .route(
+            "/invitations/:token",
+            get(current_user::organizations::show_invitation),
+        )
+        .route(
+            "/invitations/:token/accept",
+            post(current_user::organizations::accept_invitation),
+        )
+        .route("/my/sessions", get(current_user::sessions::index))
+        .route(
+            "/my/sessions/delete-others",
+            post(current_user::sessions::revoke_others),
+        )
+        .route(
+            "/my/sessions/:session_id/delete",
+            post(current_user::sessions::revoke),
+        )
+        .route("/my/compare", get(current_user::compare::show))
+        .route("/my/compare/refresh", get(current_user::compare::refresh))
         .route(
             "/my/sites",
             get(current_user::sites::index).post(current_user::sites::create),
@@ -55,6 +103,10 @@ pub fn routes() -> Router<AppState> {
         .route("/my/sites/new", get(current_user::sites::new))
         .route("/my/sites/:site_id", get(current_user::sites::show))
         .route("/my/sites/:site_id/refresh", get(current_user::sites::refresh))
+        .route(
+            "/my/sites/:site_id/trace-context",
+            post(current_user::sites::update_trace_context),
+        )
         .route(
             "/my/sites/:site_id/pages/new",
             get(current_user::pages::new),
@@ -71,8 +123,159 @@ pub fn routes() -> Router<AppState> {
             "/my/sites/:site_id/pages/:page_id/refresh",
             get(current_user::pages::refresh),
         )
+        .route(
+            "/my/sites/:site_id/audit-log",
+            get(current_user::audit_log::show),
+        )
+        .route(
+            "/my/sites/:site_id/audit-log/export",
+            get(current_user::audit_log::export),
+        )
+        .route(
+            "/my/sites/:site_id/checkins/export",
+            get(current_user::exports::site_checkins),
+        )
+        .route(
+            "/my/sites/:site_id/pages/:page_id/checkins/export",
+            get(current_user::exports::page_checkins),
+        )
+        .route("/my/sites/:site_id/slos/new", get(current_user::slos::new))
+        .route("/my/sites/:site_id/slos", post(current_user::slos::create))
+        .route(
+            "/my/sites/:site_id/reports",
+            get(current_user::reports::show),
+        )
+        .route(
+            "/my/sites/:site_id/reports/download",
+            get(current_user::reports::download),
+        )
+        .route(
+            "/my/sites/:site_id/reports/subscriptions",
+            post(current_user::reports::subscribe),
+        )
+        .route(
+            "/my/sites/:site_id/reports/subscriptions/:report_subscription_id/delete",
+            post(current_user::reports::unsubscribe),
+        )
+        .fallback(|| async { AppError::NotFound })
 }
 
+pub(crate) const TAILWIND_CSS: &str =
+    include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/target/tailwind.css"));
+
 async fn tailwind_css() -> &'static str {
-    include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/target/tailwind.css"))
+    TAILWIND_CSS
+}
+
+#[cfg(test)]
+mod tests {
+    use cja::app_state::AppState as _;
+    use reqwest::StatusCode;
+    use sqlx::PgPool;
+
+    use crate::testing::{create_user, get_as, get_body_as, serve, test_state, TestUser};
+
+    /// Every route that shows a site's checkins or history. `{site}` and
+    /// `{page}` are filled in with the IDs being asked for
+    const SITE_ROUTES: &[&str] = &[
+        "/my/sites/{site}",
+        "/my/sites/{site}/refresh",
+        "/my/sites/{site}/reports",
+        "/my/sites/{site}/reports/download",
+        "/my/sites/{site}/audit-log",
+        "/my/sites/{site}/audit-log/export?start=2024-03-01&end=2024-03-31",
+        "/my/sites/{site}/checkins/export?start=2024-03-01&end=2024-03-31",
+        "/my/sites/{site}/pages/{page}",
+        "/my/sites/{site}/pages/{page}/refresh",
+        "/my/sites/{site}/pages/{page}/checkins/export?start=2024-03-01&end=2024-03-31",
+    ];
+
+    fn fill(route: &str, site: &TestUser, page: &TestUser) -> String {
+        route
+            .replace("{site}", &site.site_id.to_string())
+            .replace("{page}", &page.page_id.to_string())
+    }
+
+    #[sqlx::test]
+    async fn other_users_sites_are_not_found(pool: PgPool) {
+        let app_state = test_state(pool);
+        let alice = create_user(&app_state, "alice").await;
+        let bob = create_user(&app_state, "bob").await;
+        let base_url = serve(app_state).await;
+
+        for route in SITE_ROUTES {
+            let own = fill(route, &alice, &alice);
+            assert_eq!(
+                get_as(&base_url, &alice, &own).await,
+                StatusCode::OK,
+                "{own}"
+            );
+
+            let others = fill(route, &bob, &bob);
+            assert_eq!(
+                get_as(&base_url, &alice, &others).await,
+                StatusCode::NOT_FOUND,
+                "{others}"
+            );
+
+            // Someone else's page can't be reached through your own site either
+            if route.contains("{page}") {
+                let mixed = fill(route, &alice, &bob);
+                assert_eq!(
+                    get_as(&base_url, &alice, &mixed).await,
+                    StatusCode::NOT_FOUND,
+                    "{mixed}"
+                );
+            }
+        }
+    }
+
+    #[sqlx::test]
+    async fn comparisons_leave_out_other_users_pages(pool: PgPool) {
+        let app_state = test_state(pool);
+        let alice = create_user(&app_state, "alice").await;
+        let bob = create_user(&app_state, "bob").await;
+
+        // The legend linking to each page only shows once there's more than
+        // one line with data
+        let alice_about_page_id = sqlx::query_scalar!(
+            r#"
+    INSERT INTO Pages (site_id, name, path)
+    VALUES ($1, 'About', '/about')
+    RETURNING page_id
+  "#,
+            alice.site_id
+        )
+        .fetch_one(app_state.db())
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+    INSERT INTO Checkins (page_id, outcome, status_code, duration_nanos, created_at)
+    SELECT page_id, 'success', 200, 50000000, now() - interval '1 hour'
+    FROM Pages
+  "#
+        )
+        .execute(app_state.db())
+        .await
+        .unwrap();
+
+        let base_url = serve(app_state).await;
+
+        let query = format!(
+            "page_id={}&page_id={alice_about_page_id}&page_id={}",
+            alice.page_id, bob.page_id
+        );
+        for path in [
+            format!("/my/compare?{query}"),
+            format!("/my/compare/refresh?{query}"),
+        ] {
+            let (status, body) = get_body_as(&base_url, &alice, &path).await;
+
+            assert_eq!(status, StatusCode::OK, "{path}");
+            assert!(body.contains(&alice.page_id.to_string()), "{path}");
+            assert!(body.contains(&alice_about_page_id.to_string()), "{path}");
+            assert!(!body.contains(&bob.page_id.to_string()), "{path}");
+        }
+    }
 }
@@ -1,4 +1,9 @@
-use axum::http::Request;
+use axum::{
+    extract::MatchedPath,
+    http::{Method, Request},
+    middleware::Next,
+    response::Response,
+};
 use tower_http::trace::{MakeSpan, OnResponse};
 use tracing::Level;
 
@@ -48,6 +53,17 @@ impl<Body> OnResponse<Body> for Tracer {
         span: &tracing::Span,
     ) {
         let status_code = response.status().as_u16();
+
+        if let Some(labels) = response.extensions().get::<RequestLabels>() {
+            metrics::histogram!(
+                "upguardian_http_request_duration_seconds",
+                "method" => labels.method.to_string(),
+                "route" => labels.route.clone(),
+                "status" => status_code.to_string(),
+            )
+            .record(latency.as_secs_f64());
+        }
+
         tracing::event!(
             Level::INFO,
             status = status_code,
@@ -66,9 +82,30 @@ impl<Body> OnResponse<Body> for Tracer {
     }
 }
 
+/// The parts of the request we label the latency metrics with. `OnResponse`
+/// only gets the response, so [`record_request_labels`] copies these over to
+/// its extensions
+#[derive(Debug, Clone)]
+struct RequestLabels {
+    method: Method,
+    route: String,
+}
+
+pub(crate) async fn record_request_labels(request: axum::extract::Request, next: Next) -> Response {
+    let labels = RequestLabels {
+        method: request.method().clone(),
+        route: http_route(&request).to_string(),
+    };
+
+    let mut response = next.run(request).await;
+    response.extensions_mut().insert(labels);
+
+    response
+}
+
 #[inline]
 fn http_route<B>(req: &Request<B>) -> &str {
     req.extensions()
-        .get::<axum::extract::MatchedPath>()
+        .get::<MatchedPath>()
         .map_or_else(|| "", |mp| mp.as_str())
 }
@@ -0,0 +1,170 @@
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use cja::app_state::AppState as _;
+use miette::{Context, IntoDiagnostic};
+use sqlx::PgPool;
+use tower_cookies::{Cookie, Cookies};
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    errors::{self, AppError},
+};
+
+/// The private cookie cja keeps the session id in
+pub const SESSION_COOKIE: &str = "session_id";
+
+/// How often a session's `updated_at` is bumped while it's in use. Bumping it
+/// on every request would be a write per request, and the idle timeout is far
+/// longer than this anyway
+fn touch_interval() -> chrono::Duration {
+    chrono::Duration::minutes(5)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SessionTimeouts {
+    /// Sessions that haven't been used for this long are logged out
+    pub idle: chrono::Duration,
+    /// Sessions are logged out this long after logging in, however active
+    /// they are
+    pub absolute: chrono::Duration,
+}
+
+impl SessionTimeouts {
+    pub fn from_env() -> miette::Result<Self> {
+        let idle_days = std::env::var("SESSION_IDLE_TIMEOUT_DAYS")
+            .unwrap_or_else(|_| "7".into())
+            .parse::<i64>()
+            .into_diagnostic()
+            .wrap_err("SESSION_IDLE_TIMEOUT_DAYS must be a number of days")?;
+        let absolute_days = std::env::var("SESSION_MAX_AGE_DAYS")
+            .unwrap_or_else(|_| "30".into())
+            .parse::<i64>()
+            .into_diagnostic()
+            .wrap_err("SESSION_MAX_AGE_DAYS must be a number of days")?;
+
+        Ok(Self {
+            idle: chrono::Duration::days(idle_days),
+            absolute: chrono::Duration::days(absolute_days),
+        })
+    }
+
+    /// When a session that was created and last used at these times will be
+    /// logged out
+    pub fn expires_at(
+        &self,
+        created_at: chrono::DateTime<Utc>,
+        updated_at: chrono::DateTime<Utc>,
+    ) -> chrono::DateTime<Utc> {
+        (created_at + self.absolute).min(updated_at + self.idle)
+    }
+}
+
+/// Deletes the session if it's past either timeout, otherwise marks it as
+/// used. Returns whether the session is still good
+async fn refresh_session(
+    db: &PgPool,
+    session_id: Uuid,
+    timeouts: SessionTimeouts,
+    user_agent: Option<&str>,
+) -> miette::Result<bool> {
+    let now = Utc::now();
+
+    let expired = sqlx::query!(
+        r#"
+    DELETE FROM Sessions
+    WHERE session_id = $1
+      AND (created_at < $2 OR updated_at < $3)
+  "#,
+        session_id,
+        now - timeouts.absolute,
+        now - timeouts.idle
+    )
+    .execute(db)
+    .await
+    .into_diagnostic()?
+    .rows_affected()
+        > 0;
+
+    if expired {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        r#"
+    UPDATE Sessions
+    SET updated_at = now(), user_agent = coalesce($2, user_agent)
+    WHERE session_id = $1 AND updated_at < $3
+  "#,
+        session_id,
+        user_agent,
+        now - touch_interval()
+    )
+    .execute(db)
+    .await
+    .into_diagnostic()?;
+
+    Ok(true)
+}
+
+/// Logs out expired sessions before the request gets to `DBSession`, which
+/// only checks that the session exists
+pub(crate) async fn expire_sessions(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(cookies) = request.extensions().get::<Cookies>().cloned() {
+        let private = cookies.private(state.cookie_key());
+        let session_id = private
+            .get(SESSION_COOKIE)
+            .and_then(|cookie| Uuid::parse_str(cookie.value()).ok());
+
+        if let Some(session_id) = session_id {
+            let user_agent = request
+                .headers()
+                .get(header::USER_AGENT)
+                .and_then(|h| h.to_str().ok());
+
+            match refresh_session(state.db(), session_id, state.session_timeouts, user_agent).await
+            {
+                Ok(true) => {}
+                Ok(false) => private.remove(Cookie::new(SESSION_COOKIE, "")),
+                // Letting the request through would accept a session that
+                // might have expired
+                Err(err) => {
+                    let err = AppError::from(err.wrap_err("Couldn't check the session's timeouts"));
+                    return errors::render_error_page(state, Some(cookies), err.into_response())
+                        .await;
+                }
+            }
+        }
+    }
+
+    next.run(request).await
+}
+
+/// Deletes sessions past either timeout that nobody came back to, which
+/// `expire_sessions` never sees
+pub async fn prune_sessions(db: &PgPool, timeouts: SessionTimeouts) -> miette::Result<u64> {
+    let now = Utc::now();
+
+    let result = sqlx::query!(
+        r#"
+    DELETE FROM Sessions
+    WHERE created_at < $1 OR updated_at < $2
+  "#,
+        now - timeouts.absolute,
+        now - timeouts.idle
+    )
+    .execute(db)
+    .await
+    .into_diagnostic()?;
+
+    Ok(result.rows_affected())
+}
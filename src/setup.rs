@@ -1,9 +1,14 @@
 use std::{collections::HashMap, time::Duration};
 
 use miette::{Context, IntoDiagnostic, Result};
-use opentelemetry_otlp::WithExportConfig;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
 use sentry::ClientInitGuard;
 use sqlx::{postgres::PgPoolOptions, PgPool};
+use tonic::{
+    metadata::{MetadataKey, MetadataMap},
+    transport::ClientTlsConfig,
+};
 use tracing_opentelemetry::OpenTelemetryLayer;
 use tracing_subscriber::{prelude::*, EnvFilter, Registry};
 use tracing_tree::HierarchicalLayer;
@@ -17,29 +22,19 @@ pub fn setup_tracing() -> Result<()> {
         .into_diagnostic()
         .wrap_err_with(|| miette::miette!("Couldn't create env filter from {}", rust_log))?;
 
-    let opentelemetry_layer = if let Ok(honeycomb_key) = std::env::var("HONEYCOMB_API_KEY") {
-        let mut map = HashMap::<String, String>::new();
-        map.insert("x-honeycomb-team".to_string(), honeycomb_key);
-        map.insert("x-honeycomb-dataset".to_string(), "UpGuardian".to_string());
+    let opentelemetry_layer = if let Some(otlp_config) = OtlpConfig::from_env()? {
+        println!(
+            "OTLP layer configured, exporting to {} over {:?}",
+            otlp_config.endpoint, otlp_config.protocol
+        );
 
-        let tracer = opentelemetry_otlp::new_pipeline()
-            .tracing()
-            .with_exporter(
-                opentelemetry_otlp::new_exporter()
-                    .http()
-                    .with_endpoint("https://api.honeycomb.io/v1/traces")
-                    .with_timeout(Duration::from_secs(3))
-                    .with_headers(map),
-            )
-            .install_batch(opentelemetry::runtime::Tokio)
-            .into_diagnostic()?;
-
-        let opentelemetry_layer = OpenTelemetryLayer::new(tracer);
-        println!("Honeycomb layer configured");
+        let tracer = otlp_config.install_tracer()?;
+        // Lets checkins send a traceparent header for the sites that want one
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
 
-        Some(opentelemetry_layer)
+        Some(OpenTelemetryLayer::new(tracer))
     } else {
-        println!("Skipping Honeycomb layer");
+        println!("Skipping OTLP layer");
 
         None
     };
@@ -70,6 +65,177 @@ pub fn setup_tracing() -> Result<()> {
     Ok(())
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OtlpProtocol {
+    Grpc,
+    HttpProtobuf,
+}
+
+/// Where and how to export traces, read from the standard `OTEL_EXPORTER_OTLP_*`
+/// variables. Sampling is left to the SDK, which reads `OTEL_TRACES_SAMPLER` and
+/// `OTEL_TRACES_SAMPLER_ARG` itself, as it does `OTEL_SERVICE_NAME`
+#[derive(Debug, Clone)]
+struct OtlpConfig {
+    endpoint: String,
+    protocol: OtlpProtocol,
+    headers: HashMap<String, String>,
+    timeout: Duration,
+}
+
+const OTLP_TIMEOUT_DEFAULT: Duration = Duration::from_secs(10);
+
+/// Reads the traces specific variant of an exporter variable first, ie
+/// `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` before `OTEL_EXPORTER_OTLP_ENDPOINT`
+fn otlp_var(name: &str) -> Option<(String, bool)> {
+    std::env::var(format!("OTEL_EXPORTER_OTLP_TRACES_{name}"))
+        .map(|value| (value, true))
+        .or_else(|_| {
+            std::env::var(format!("OTEL_EXPORTER_OTLP_{name}")).map(|value| (value, false))
+        })
+        .ok()
+}
+
+impl OtlpConfig {
+    /// Returns `None` when no endpoint is configured or the exporter has been
+    /// turned off with `OTEL_SDK_DISABLED` or `OTEL_TRACES_EXPORTER=none`
+    fn from_env() -> Result<Option<Self>> {
+        let disabled = std::env::var("OTEL_SDK_DISABLED").is_ok_and(|v| v == "true")
+            || std::env::var("OTEL_TRACES_EXPORTER").is_ok_and(|v| v == "none");
+        if disabled {
+            return Ok(None);
+        }
+
+        let protocol = match otlp_var("PROTOCOL").map(|(value, _)| value).as_deref() {
+            None | Some("http/protobuf") => OtlpProtocol::HttpProtobuf,
+            Some("grpc") => OtlpProtocol::Grpc,
+            Some(other) => {
+                return Err(miette::miette!(
+                    "Unsupported OTLP protocol {other}, expected grpc or http/protobuf"
+                ))
+            }
+        };
+
+        let mut headers = match otlp_var("HEADERS") {
+            Some((headers, _)) => parse_otlp_headers(&headers)?,
+            None => HashMap::new(),
+        };
+
+        let endpoint = match otlp_var("ENDPOINT") {
+            // The signal specific endpoint is used as is, but the generic one is
+            // a base URL that the HTTP exporter adds the traces path to
+            Some((endpoint, true)) => endpoint,
+            Some((endpoint, false)) => match protocol {
+                OtlpProtocol::HttpProtobuf => {
+                    format!("{}/v1/traces", endpoint.trim_end_matches('/'))
+                }
+                OtlpProtocol::Grpc => endpoint,
+            },
+            None => {
+                // Kept so existing deploys that only set the Honeycomb key keep
+                // sending their traces there
+                let Ok(honeycomb_key) = std::env::var("HONEYCOMB_API_KEY") else {
+                    return Ok(None);
+                };
+                headers.insert("x-honeycomb-team".to_string(), honeycomb_key);
+                headers.insert("x-honeycomb-dataset".to_string(), "UpGuardian".to_string());
+
+                "https://api.honeycomb.io/v1/traces".to_string()
+            }
+        };
+
+        let timeout = match otlp_var("TIMEOUT") {
+            Some((millis, _)) => Duration::from_millis(
+                millis
+                    .parse()
+                    .into_diagnostic()
+                    .wrap_err("OTEL_EXPORTER_OTLP_TIMEOUT must be a number of milliseconds")?,
+            ),
+            None => OTLP_TIMEOUT_DEFAULT,
+        };
+
+        Ok(Some(Self {
+            endpoint,
+            protocol,
+            headers,
+            timeout,
+        }))
+    }
+
+    fn install_tracer(self) -> Result<opentelemetry::sdk::trace::Tracer> {
+        let exporter: SpanExporterBuilder = match self.protocol {
+            OtlpProtocol::HttpProtobuf => opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(self.endpoint)
+                .with_timeout(self.timeout)
+                .with_headers(self.headers)
+                .into(),
+            OtlpProtocol::Grpc => {
+                let mut metadata = MetadataMap::new();
+                for (key, value) in self.headers {
+                    let key = MetadataKey::from_bytes(key.as_bytes()).into_diagnostic()?;
+                    metadata.insert(key, value.parse().into_diagnostic()?);
+                }
+
+                let mut exporter = opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_timeout(self.timeout)
+                    .with_metadata(metadata);
+                if self.endpoint.starts_with("https://") {
+                    exporter = exporter.with_tls_config(ClientTlsConfig::new());
+                }
+
+                exporter.with_endpoint(self.endpoint).into()
+            }
+        };
+
+        opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(exporter)
+            .with_trace_config(opentelemetry::sdk::trace::config())
+            .install_batch(opentelemetry::runtime::Tokio)
+            .into_diagnostic()
+    }
+}
+
+/// Parses `OTEL_EXPORTER_OTLP_HEADERS`, which is a comma separated list of
+/// `key=value` pairs with percent encoded values
+fn parse_otlp_headers(headers: &str) -> Result<HashMap<String, String>> {
+    headers
+        .split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| miette::miette!("Invalid OTLP header {pair}, expected key=value"))?;
+
+            Ok((key.trim().to_string(), percent_decode(value.trim())?))
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut chars = value.bytes();
+
+    while let Some(byte) = chars.next() {
+        if byte == b'%' {
+            let hex = [chars.next(), chars.next()];
+            let hex = match hex {
+                [Some(high), Some(low)] => std::str::from_utf8(&[high, low])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+                _ => None,
+            };
+
+            bytes.push(hex.ok_or_else(|| miette::miette!("Invalid percent encoding in {value}"))?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+
+    String::from_utf8(bytes).into_diagnostic()
+}
+
 pub fn setup_sentry() -> Option<ClientInitGuard> {
     let git_commit: Option<std::borrow::Cow<_>> = option_env!("VERGEN_GIT_SHA").map(|x| x.into());
     let release_name =
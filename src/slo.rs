@@ -0,0 +1,182 @@
+use chrono::{DateTime, Utc};
+use miette::IntoDiagnostic;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A one hour burn rate this high spends 2% of a 30 day error budget in that
+/// hour, which is the usual threshold for paging someone
+pub const FAST_BURN_RATE: f64 = 14.4;
+
+#[derive(Debug, Clone)]
+pub struct Slo {
+    pub slo_id: Uuid,
+    pub site_id: Uuid,
+    pub page_id: Option<Uuid>,
+    pub name: String,
+    pub target_percent: f64,
+    pub window_days: i32,
+    pub latency_threshold_ms: Option<i32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SloCounts {
+    pub total: i64,
+    pub good: i64,
+    pub recent_total: i64,
+    pub recent_good: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SloReport {
+    pub slo: Slo,
+    pub counts: SloCounts,
+}
+
+impl Slo {
+    pub async fn all(db: &PgPool) -> miette::Result<Vec<Slo>> {
+        sqlx::query_as!(
+            Slo,
+            r#"
+        SELECT slo_id, site_id, page_id, name, target_percent, window_days, latency_threshold_ms
+        FROM Slos
+      "#
+        )
+        .fetch_all(db)
+        .await
+        .into_diagnostic()
+    }
+
+    pub async fn for_site(db: &PgPool, site_id: Uuid) -> miette::Result<Vec<Slo>> {
+        sqlx::query_as!(
+            Slo,
+            r#"
+        SELECT slo_id, site_id, page_id, name, target_percent, window_days, latency_threshold_ms
+        FROM Slos
+        WHERE site_id = $1
+        ORDER BY created_at ASC
+      "#,
+            site_id
+        )
+        .fetch_all(db)
+        .await
+        .into_diagnostic()
+    }
+
+    /// Counts good and total checkins over the whole window, and over the last
+    /// hour to catch fast burns
+    pub async fn report(self, db: &PgPool) -> miette::Result<SloReport> {
+        let window_start = Utc::now() - chrono::Duration::days(self.window_days.into());
+        let latency_threshold_nanos = self
+            .latency_threshold_ms
+            .map(|ms| i64::from(ms) * 1_000_000);
+
+        let counts = sqlx::query_as!(
+            SloCounts,
+            r#"
+        SELECT
+          count(*) as "total!",
+          count(*) FILTER (
+            WHERE Checkins.outcome = 'success'
+            AND ($4::int8 IS NULL OR Checkins.duration_nanos <= $4)
+          ) as "good!",
+          count(*) FILTER (
+            WHERE Checkins.created_at >= now() - INTERVAL '1 hour'
+          ) as "recent_total!",
+          count(*) FILTER (
+            WHERE Checkins.created_at >= now() - INTERVAL '1 hour'
+            AND Checkins.outcome = 'success'
+            AND ($4::int8 IS NULL OR Checkins.duration_nanos <= $4)
+          ) as "recent_good!"
+        FROM Checkins
+        JOIN Pages USING (page_id)
+        WHERE Pages.site_id = $1
+          AND ($2::uuid IS NULL OR Checkins.page_id = $2)
+          AND Checkins.created_at >= $3
+      "#,
+            self.site_id,
+            self.page_id,
+            window_start,
+            latency_threshold_nanos
+        )
+        .fetch_one(db)
+        .await
+        .into_diagnostic()?;
+
+        Ok(SloReport { slo: self, counts })
+    }
+}
+
+impl SloReport {
+    /// The fraction of checkins we're allowed to fail, ie 0.001 for 99.9%
+    fn error_budget(&self) -> f64 {
+        1.0 - self.slo.target_percent / 100.0
+    }
+
+    pub fn attained_percent(&self) -> Option<f64> {
+        if self.counts.total == 0 {
+            return None;
+        }
+
+        Some(self.counts.good as f64 / self.counts.total as f64 * 100.0)
+    }
+
+    /// How much of the error budget for the window is left, as a fraction.
+    /// This goes negative once the SLO has been breached
+    pub fn budget_remaining(&self) -> Option<f64> {
+        self.burn_rate().map(|burn_rate| 1.0 - burn_rate)
+    }
+
+    /// How fast the budget is being spent over the window, where 1.0 spends
+    /// exactly the whole budget by the end of the window
+    pub fn burn_rate(&self) -> Option<f64> {
+        burn_rate(self.counts.good, self.counts.total, self.error_budget())
+    }
+
+    /// The burn rate over just the last hour
+    pub fn recent_burn_rate(&self) -> Option<f64> {
+        burn_rate(
+            self.counts.recent_good,
+            self.counts.recent_total,
+            self.error_budget(),
+        )
+    }
+
+    pub fn is_burning_fast(&self) -> bool {
+        self.recent_burn_rate()
+            .is_some_and(|burn_rate| burn_rate >= FAST_BURN_RATE)
+    }
+}
+
+fn burn_rate(good: i64, total: i64, error_budget: f64) -> Option<f64> {
+    if total == 0 {
+        return None;
+    }
+
+    let error_rate = 1.0 - good as f64 / total as f64;
+
+    Some(error_rate / error_budget)
+}
+
+#[derive(Debug, Clone)]
+pub struct SloAlert {
+    pub slo_alert_id: Uuid,
+    pub burn_rate: f64,
+    pub fired_at: DateTime<Utc>,
+}
+
+impl SloAlert {
+    pub async fn open_for_slo(db: &PgPool, slo_id: Uuid) -> miette::Result<Option<SloAlert>> {
+        sqlx::query_as!(
+            SloAlert,
+            r#"
+        SELECT slo_alert_id, burn_rate, fired_at
+        FROM SloAlerts
+        WHERE slo_id = $1 AND resolved_at IS NULL
+      "#,
+            slo_id
+        )
+        .fetch_optional(db)
+        .await
+        .into_diagnostic()
+    }
+}
@@ -0,0 +1,656 @@
+use std::{collections::HashMap, ops::Range};
+
+use chrono::{DateTime, Days, DurationRound, NaiveDate, Utc};
+use miette::IntoDiagnostic;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authz;
+
+/// Ranges up to this many days are aggregated straight from the raw `Checkins`
+const RAW_RANGE_LIMIT_DAYS: i64 = 2;
+/// Ranges up to this many days are aggregated from the hourly rollups,
+/// anything longer uses the daily rollups
+const HOURLY_RANGE_LIMIT_DAYS: i64 = 90;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Hour,
+    Day,
+}
+
+impl Resolution {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Resolution::Hour => "hour",
+            Resolution::Day => "day",
+        }
+    }
+
+    pub fn duration(&self) -> chrono::Duration {
+        match self {
+            Resolution::Hour => chrono::Duration::hours(1),
+            Resolution::Day => chrono::Duration::days(1),
+        }
+    }
+
+    /// The start of the bucket `time` falls in, matching `date_trunc` in UTC
+    pub fn truncate(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        time.duration_trunc(self.duration()).unwrap()
+    }
+
+    /// The rollup resolution to read `range` from, or `None` when it's short
+    /// enough to read the raw checkins. Ranges reaching back past
+    /// `checkin_retention` always use the rollups, the raw checkins from then
+    /// have already been pruned
+    pub fn for_range(
+        range: &Range<DateTime<Utc>>,
+        checkin_retention: chrono::Duration,
+    ) -> Option<Self> {
+        let length = range.end - range.start;
+        let pruned = range.start < Utc::now() - checkin_retention;
+
+        if length <= chrono::Duration::days(RAW_RANGE_LIMIT_DAYS) && !pruned {
+            None
+        } else if length <= chrono::Duration::days(HOURLY_RANGE_LIMIT_DAYS) {
+            Some(Resolution::Hour)
+        } else {
+            Some(Resolution::Day)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Percentile {
+    P50,
+    P95,
+    P99,
+}
+
+impl Percentile {
+    pub const ALL: [Percentile; 3] = [Percentile::P50, Percentile::P95, Percentile::P99];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Percentile::P50 => "p50",
+            Percentile::P95 => "p95",
+            Percentile::P99 => "p99",
+        }
+    }
+}
+
+/// The range covering every day from `start` through `end`, both inclusive,
+/// in UTC. `None` when `end` is the last day chrono can represent
+pub fn day_range(start: NaiveDate, end: NaiveDate) -> Option<Range<DateTime<Utc>>> {
+    let start = start.and_hms_opt(0, 0, 0)?.and_utc();
+    let end = end
+        .checked_add_days(Days::new(1))?
+        .and_hms_opt(0, 0, 0)?
+        .and_utc();
+
+    Some(start..end)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum StatsScope {
+    Page(Uuid),
+    Site(Uuid),
+    /// Every page on every site in the organizations the user is a member of
+    User(Uuid),
+}
+
+impl StatsScope {
+    pub fn page_id(&self) -> Option<Uuid> {
+        match self {
+            StatsScope::Page(page_id) => Some(*page_id),
+            StatsScope::Site(_) | StatsScope::User(_) => None,
+        }
+    }
+
+    pub fn site_id(&self) -> Option<Uuid> {
+        match self {
+            StatsScope::Site(site_id) => Some(*site_id),
+            StatsScope::Page(_) | StatsScope::User(_) => None,
+        }
+    }
+
+    /// The sites a user scope covers, for filtering with
+    /// `$n::uuid[] IS NULL OR Pages.site_id = ANY($n)`
+    pub async fn site_ids(&self, db: &PgPool) -> miette::Result<Option<Vec<Uuid>>> {
+        match self {
+            StatsScope::User(user_id) => Ok(Some(authz::accessible_site_ids(db, *user_id).await?)),
+            StatsScope::Page(_) | StatsScope::Site(_) => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CheckinStats {
+    pub count: i64,
+    pub success_count: i64,
+    pub avg_duration_nanos: Option<f64>,
+    pub p50_duration_nanos: Option<i64>,
+    pub p95_duration_nanos: Option<i64>,
+    pub p99_duration_nanos: Option<i64>,
+}
+
+impl CheckinStats {
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn success_percent(&self) -> f64 {
+        self.success_count as f64 / self.count as f64 * 100.0
+    }
+
+    pub fn avg_response_time_ms(&self) -> Option<f64> {
+        self.avg_duration_nanos.map(|nanos| nanos / 1_000_000.0)
+    }
+
+    pub fn percentile_duration_nanos(&self, percentile: Percentile) -> Option<i64> {
+        match percentile {
+            Percentile::P50 => self.p50_duration_nanos,
+            Percentile::P95 => self.p95_duration_nanos,
+            Percentile::P99 => self.p99_duration_nanos,
+        }
+    }
+
+    pub fn percentile_response_time_ms(&self, percentile: Percentile) -> Option<f64> {
+        self.percentile_duration_nanos(percentile)
+            .map(|nanos| nanos as f64 / 1_000_000.0)
+    }
+}
+
+/// Aggregates the checkins in `range`, reading from the raw table for short
+/// recent ranges and from the rollups for anything else
+pub async fn stats_for_range(
+    db: &PgPool,
+    scope: StatsScope,
+    range: Range<DateTime<Utc>>,
+    checkin_retention: chrono::Duration,
+) -> miette::Result<CheckinStats> {
+    match Resolution::for_range(&range, checkin_retention) {
+        None => raw_stats_for_range(db, scope, range).await,
+        Some(resolution) => rollup_stats_for_range(db, scope, range, resolution).await,
+    }
+}
+
+async fn raw_stats_for_range(
+    db: &PgPool,
+    scope: StatsScope,
+    range: Range<DateTime<Utc>>,
+) -> miette::Result<CheckinStats> {
+    let site_ids = scope.site_ids(db).await?;
+
+    sqlx::query_as!(
+        CheckinStats,
+        r#"
+    SELECT
+      count(*) as "count!",
+      count(*) FILTER (WHERE Checkins.outcome = 'success') as "success_count!",
+      avg(Checkins.duration_nanos)::float8 as avg_duration_nanos,
+      (percentile_cont(0.50) WITHIN GROUP (ORDER BY Checkins.duration_nanos))::int8 as p50_duration_nanos,
+      (percentile_cont(0.95) WITHIN GROUP (ORDER BY Checkins.duration_nanos))::int8 as p95_duration_nanos,
+      (percentile_cont(0.99) WITHIN GROUP (ORDER BY Checkins.duration_nanos))::int8 as p99_duration_nanos
+    FROM Checkins
+    JOIN Pages USING (page_id)
+    WHERE ($1::uuid IS NULL OR Checkins.page_id = $1)
+      AND ($2::uuid IS NULL OR Pages.site_id = $2)
+      AND ($3::uuid[] IS NULL OR Pages.site_id = ANY($3))
+      AND Checkins.created_at >= $4
+      AND Checkins.created_at < $5
+  "#,
+        scope.page_id(),
+        scope.site_id(),
+        site_ids.as_deref(),
+        range.start,
+        range.end
+    )
+    .fetch_one(db)
+    .await
+    .into_diagnostic()
+}
+
+/// Percentiles can't be merged exactly, so each percentile across buckets is
+/// approximated as the checkin weighted mean of that percentile per bucket.
+/// Buckets without a value for a percentile are left out of its weighting
+async fn rollup_stats_for_range(
+    db: &PgPool,
+    scope: StatsScope,
+    range: Range<DateTime<Utc>>,
+    resolution: Resolution,
+) -> miette::Result<CheckinStats> {
+    let site_ids = scope.site_ids(db).await?;
+
+    sqlx::query_as!(
+        CheckinStats,
+        r#"
+    SELECT
+      coalesce(sum(CheckinRollups.checkin_count), 0)::int8 as "count!",
+      coalesce(sum(CheckinRollups.success_count), 0)::int8 as "success_count!",
+      (sum(CheckinRollups.avg_duration_nanos::float8 * CheckinRollups.checkin_count) / nullif(sum(CheckinRollups.checkin_count), 0))::float8 as avg_duration_nanos,
+      (sum(CheckinRollups.p50_duration_nanos::float8 * CheckinRollups.checkin_count) / nullif(sum(CheckinRollups.checkin_count) FILTER (WHERE CheckinRollups.p50_duration_nanos IS NOT NULL), 0))::int8 as p50_duration_nanos,
+      (sum(CheckinRollups.p95_duration_nanos::float8 * CheckinRollups.checkin_count) / nullif(sum(CheckinRollups.checkin_count) FILTER (WHERE CheckinRollups.p95_duration_nanos IS NOT NULL), 0))::int8 as p95_duration_nanos,
+      (sum(CheckinRollups.p99_duration_nanos::float8 * CheckinRollups.checkin_count) / nullif(sum(CheckinRollups.checkin_count) FILTER (WHERE CheckinRollups.p99_duration_nanos IS NOT NULL), 0))::int8 as p99_duration_nanos
+    FROM CheckinRollups
+    JOIN Pages USING (page_id)
+    WHERE ($1::uuid IS NULL OR CheckinRollups.page_id = $1)
+      AND ($2::uuid IS NULL OR Pages.site_id = $2)
+      AND ($3::uuid[] IS NULL OR Pages.site_id = ANY($3))
+      AND CheckinRollups.resolution = $4
+      AND CheckinRollups.bucket_start >= $5
+      AND CheckinRollups.bucket_start < $6
+  "#,
+        scope.page_id(),
+        scope.site_id(),
+        site_ids.as_deref(),
+        resolution.as_str(),
+        range.start,
+        range.end
+    )
+    .fetch_one(db)
+    .await
+    .into_diagnostic()
+}
+
+/// The checkins in one bucket of a graph, aggregated in the database
+#[derive(Debug, Clone)]
+pub struct CheckinBucket {
+    pub bucket_start: DateTime<Utc>,
+    pub min_duration_nanos: Option<i64>,
+    pub avg_duration_nanos: Option<f64>,
+    pub max_duration_nanos: Option<i64>,
+    pub p50_duration_nanos: Option<i64>,
+    pub p95_duration_nanos: Option<i64>,
+    pub p99_duration_nanos: Option<i64>,
+    /// The average time spent in each phase of the request. These are only
+    /// kept for raw checkins, so they're always `None` for rolled up buckets
+    pub avg_dns_nanos: Option<f64>,
+    pub avg_connect_nanos: Option<f64>,
+    pub avg_tls_nanos: Option<f64>,
+    pub avg_ttfb_nanos: Option<f64>,
+    pub avg_download_nanos: Option<f64>,
+}
+
+impl CheckinBucket {
+    /// The average duration when `percentile` is `None`
+    pub fn duration_nanos(&self, percentile: Option<Percentile>) -> Option<f64> {
+        match percentile {
+            None => self.avg_duration_nanos,
+            Some(Percentile::P50) => self.p50_duration_nanos.map(|nanos| nanos as f64),
+            Some(Percentile::P95) => self.p95_duration_nanos.map(|nanos| nanos as f64),
+            Some(Percentile::P99) => self.p99_duration_nanos.map(|nanos| nanos as f64),
+        }
+    }
+}
+
+/// Splits `range` into roughly `bucket_count` equal buckets and aggregates the
+/// checkins in each one, so graphs never need to load the raw rows. Buckets
+/// are never narrower than the rollups they are read from, and buckets without
+/// any checkins are left out
+pub async fn checkin_buckets(
+    db: &PgPool,
+    scope: StatsScope,
+    range: Range<DateTime<Utc>>,
+    bucket_count: i32,
+    checkin_retention: chrono::Duration,
+) -> miette::Result<Vec<CheckinBucket>> {
+    let resolution = Resolution::for_range(&range, checkin_retention);
+
+    let mut bucket_width = (range.end - range.start) / bucket_count.max(1);
+    if let Some(resolution) = resolution {
+        bucket_width = bucket_width.max(resolution.duration());
+    }
+    let bucket_width = bucket_width.max(chrono::Duration::seconds(1));
+
+    match resolution {
+        None => raw_checkin_buckets(db, scope, range, bucket_width).await,
+        Some(resolution) => {
+            rollup_checkin_buckets(db, scope, range, resolution, bucket_width).await
+        }
+    }
+}
+
+async fn raw_checkin_buckets(
+    db: &PgPool,
+    scope: StatsScope,
+    range: Range<DateTime<Utc>>,
+    bucket_width: chrono::Duration,
+) -> miette::Result<Vec<CheckinBucket>> {
+    let site_ids = scope.site_ids(db).await?;
+
+    let rows = sqlx::query!(
+        r#"
+    SELECT
+      floor(extract(epoch FROM Checkins.created_at - $4)::float8 / $6)::int8 as "bucket!",
+      min(Checkins.duration_nanos) as min_duration_nanos,
+      avg(Checkins.duration_nanos)::float8 as avg_duration_nanos,
+      max(Checkins.duration_nanos) as max_duration_nanos,
+      (percentile_cont(0.50) WITHIN GROUP (ORDER BY Checkins.duration_nanos))::int8 as p50_duration_nanos,
+      (percentile_cont(0.95) WITHIN GROUP (ORDER BY Checkins.duration_nanos))::int8 as p95_duration_nanos,
+      (percentile_cont(0.99) WITHIN GROUP (ORDER BY Checkins.duration_nanos))::int8 as p99_duration_nanos,
+      avg(Checkins.dns_nanos)::float8 as avg_dns_nanos,
+      avg(Checkins.connect_nanos)::float8 as avg_connect_nanos,
+      avg(Checkins.tls_nanos)::float8 as avg_tls_nanos,
+      avg(Checkins.ttfb_nanos)::float8 as avg_ttfb_nanos,
+      avg(Checkins.download_nanos)::float8 as avg_download_nanos
+    FROM Checkins
+    JOIN Pages USING (page_id)
+    WHERE ($1::uuid IS NULL OR Checkins.page_id = $1)
+      AND ($2::uuid IS NULL OR Pages.site_id = $2)
+      AND ($3::uuid[] IS NULL OR Pages.site_id = ANY($3))
+      AND Checkins.created_at >= $4
+      AND Checkins.created_at < $5
+      AND Checkins.duration_nanos IS NOT NULL
+    GROUP BY 1
+    ORDER BY 1 ASC
+  "#,
+        scope.page_id(),
+        scope.site_id(),
+        site_ids.as_deref(),
+        range.start,
+        range.end,
+        bucket_width.num_milliseconds() as f64 / 1000.0
+    )
+    .fetch_all(db)
+    .await
+    .into_diagnostic()?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| CheckinBucket {
+            bucket_start: range.start + bucket_width * row.bucket as i32,
+            min_duration_nanos: row.min_duration_nanos,
+            avg_duration_nanos: row.avg_duration_nanos,
+            max_duration_nanos: row.max_duration_nanos,
+            p50_duration_nanos: row.p50_duration_nanos,
+            p95_duration_nanos: row.p95_duration_nanos,
+            p99_duration_nanos: row.p99_duration_nanos,
+            avg_dns_nanos: row.avg_dns_nanos,
+            avg_connect_nanos: row.avg_connect_nanos,
+            avg_tls_nanos: row.avg_tls_nanos,
+            avg_ttfb_nanos: row.avg_ttfb_nanos,
+            avg_download_nanos: row.avg_download_nanos,
+        })
+        .collect())
+}
+
+/// Merges the rollups into buckets the same way [`rollup_stats_for_range`]
+/// merges them into a single set of stats
+async fn rollup_checkin_buckets(
+    db: &PgPool,
+    scope: StatsScope,
+    range: Range<DateTime<Utc>>,
+    resolution: Resolution,
+    bucket_width: chrono::Duration,
+) -> miette::Result<Vec<CheckinBucket>> {
+    let site_ids = scope.site_ids(db).await?;
+
+    let rows = sqlx::query!(
+        r#"
+    SELECT
+      floor(extract(epoch FROM CheckinRollups.bucket_start - $5)::float8 / $7)::int8 as "bucket!",
+      min(CheckinRollups.min_duration_nanos) as min_duration_nanos,
+      (sum(CheckinRollups.avg_duration_nanos::float8 * CheckinRollups.checkin_count) / nullif(sum(CheckinRollups.checkin_count), 0))::float8 as avg_duration_nanos,
+      max(CheckinRollups.max_duration_nanos) as max_duration_nanos,
+      (sum(CheckinRollups.p50_duration_nanos::float8 * CheckinRollups.checkin_count) / nullif(sum(CheckinRollups.checkin_count) FILTER (WHERE CheckinRollups.p50_duration_nanos IS NOT NULL), 0))::int8 as p50_duration_nanos,
+      (sum(CheckinRollups.p95_duration_nanos::float8 * CheckinRollups.checkin_count) / nullif(sum(CheckinRollups.checkin_count) FILTER (WHERE CheckinRollups.p95_duration_nanos IS NOT NULL), 0))::int8 as p95_duration_nanos,
+      (sum(CheckinRollups.p99_duration_nanos::float8 * CheckinRollups.checkin_count) / nullif(sum(CheckinRollups.checkin_count) FILTER (WHERE CheckinRollups.p99_duration_nanos IS NOT NULL), 0))::int8 as p99_duration_nanos
+    FROM CheckinRollups
+    JOIN Pages USING (page_id)
+    WHERE ($1::uuid IS NULL OR CheckinRollups.page_id = $1)
+      AND ($2::uuid IS NULL OR Pages.site_id = $2)
+      AND ($3::uuid[] IS NULL OR Pages.site_id = ANY($3))
+      AND CheckinRollups.resolution = $4
+      AND CheckinRollups.bucket_start >= $5
+      AND CheckinRollups.bucket_start < $6
+      AND CheckinRollups.avg_duration_nanos IS NOT NULL
+    GROUP BY 1
+    ORDER BY 1 ASC
+  "#,
+        scope.page_id(),
+        scope.site_id(),
+        site_ids.as_deref(),
+        resolution.as_str(),
+        range.start,
+        range.end,
+        bucket_width.num_milliseconds() as f64 / 1000.0
+    )
+    .fetch_all(db)
+    .await
+    .into_diagnostic()?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| CheckinBucket {
+            bucket_start: range.start + bucket_width * row.bucket as i32,
+            min_duration_nanos: row.min_duration_nanos,
+            avg_duration_nanos: row.avg_duration_nanos,
+            max_duration_nanos: row.max_duration_nanos,
+            p50_duration_nanos: row.p50_duration_nanos,
+            p95_duration_nanos: row.p95_duration_nanos,
+            p99_duration_nanos: row.p99_duration_nanos,
+            avg_dns_nanos: None,
+            avg_connect_nanos: None,
+            avg_tls_nanos: None,
+            avg_ttfb_nanos: None,
+            avg_download_nanos: None,
+        })
+        .collect())
+}
+
+/// How many checkins there were in one hour or day, and how many of them
+/// were successful
+#[derive(Debug, Clone)]
+pub struct UptimeBucket {
+    pub bucket_start: DateTime<Utc>,
+    pub count: i64,
+    pub success_count: i64,
+}
+
+impl UptimeBucket {
+    /// `None` when there weren't any checkins in the bucket
+    pub fn success_percent(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.success_count as f64 / self.count as f64 * 100.0)
+    }
+}
+
+/// One bucket for every hour or day in `range`, read from the rollups. The
+/// buckets are in UTC, the same as the rollups, and ones without any checkins
+/// are included with a count of zero
+pub async fn uptime_buckets(
+    db: &PgPool,
+    scope: StatsScope,
+    range: Range<DateTime<Utc>>,
+    resolution: Resolution,
+) -> miette::Result<Vec<UptimeBucket>> {
+    let site_ids = scope.site_ids(db).await?;
+
+    let rows = sqlx::query!(
+        r#"
+    SELECT
+      CheckinRollups.bucket_start,
+      sum(CheckinRollups.checkin_count)::int8 as "count!",
+      sum(CheckinRollups.success_count)::int8 as "success_count!"
+    FROM CheckinRollups
+    JOIN Pages USING (page_id)
+    WHERE ($1::uuid IS NULL OR CheckinRollups.page_id = $1)
+      AND ($2::uuid IS NULL OR Pages.site_id = $2)
+      AND ($3::uuid[] IS NULL OR Pages.site_id = ANY($3))
+      AND CheckinRollups.resolution = $4
+      AND CheckinRollups.bucket_start >= date_trunc($4, $5::timestamptz)
+      AND CheckinRollups.bucket_start < $6
+    GROUP BY CheckinRollups.bucket_start
+  "#,
+        scope.page_id(),
+        scope.site_id(),
+        site_ids.as_deref(),
+        resolution.as_str(),
+        range.start,
+        range.end
+    )
+    .fetch_all(db)
+    .await
+    .into_diagnostic()?;
+
+    let counts = rows
+        .into_iter()
+        .map(|row| (row.bucket_start, (row.count, row.success_count)))
+        .collect::<HashMap<_, _>>();
+
+    let mut buckets = vec![];
+    let mut bucket_start = resolution.truncate(range.start);
+    while bucket_start < range.end {
+        let (count, success_count) = counts.get(&bucket_start).copied().unwrap_or_default();
+        buckets.push(UptimeBucket {
+            bucket_start,
+            count,
+            success_count,
+        });
+
+        bucket_start += resolution.duration();
+    }
+
+    Ok(buckets)
+}
+
+/// Upserts the rollup buckets at `resolution`, starting from the newest bucket
+/// we already have so that partially filled buckets get recomputed. When there
+/// are no rollups yet this backfills from the start of the raw data
+pub async fn rollup_checkins(db: &PgPool, resolution: Resolution) -> miette::Result<u64> {
+    let result = sqlx::query!(
+        r#"
+    INSERT INTO CheckinRollups (
+      page_id,
+      resolution,
+      bucket_start,
+      checkin_count,
+      success_count,
+      min_duration_nanos,
+      avg_duration_nanos,
+      max_duration_nanos,
+      p50_duration_nanos,
+      p95_duration_nanos,
+      p99_duration_nanos
+    )
+    SELECT
+      page_id,
+      $1,
+      date_trunc($1, created_at),
+      count(*),
+      count(*) FILTER (WHERE outcome = 'success'),
+      min(duration_nanos),
+      avg(duration_nanos)::int8,
+      max(duration_nanos),
+      (percentile_cont(0.50) WITHIN GROUP (ORDER BY duration_nanos))::int8,
+      (percentile_cont(0.95) WITHIN GROUP (ORDER BY duration_nanos))::int8,
+      (percentile_cont(0.99) WITHIN GROUP (ORDER BY duration_nanos))::int8
+    FROM Checkins
+    WHERE created_at >= (
+      SELECT coalesce(max(bucket_start), '-infinity'::timestamptz)
+      FROM CheckinRollups
+      WHERE resolution = $1
+    )
+    GROUP BY 1, 3
+    ON CONFLICT (page_id, resolution, bucket_start) DO UPDATE SET
+      checkin_count = excluded.checkin_count,
+      success_count = excluded.success_count,
+      min_duration_nanos = excluded.min_duration_nanos,
+      avg_duration_nanos = excluded.avg_duration_nanos,
+      max_duration_nanos = excluded.max_duration_nanos,
+      p50_duration_nanos = excluded.p50_duration_nanos,
+      p95_duration_nanos = excluded.p95_duration_nanos,
+      p99_duration_nanos = excluded.p99_duration_nanos
+  "#,
+        resolution.as_str()
+    )
+    .execute(db)
+    .await
+    .into_diagnostic()?;
+
+    Ok(result.rows_affected())
+}
+
+/// Deletes raw checkins older than `retention`. Rows newer than the latest
+/// daily rollup bucket are always kept, so nothing is pruned before it has
+/// been aggregated
+pub async fn prune_checkins(db: &PgPool, retention: chrono::Duration) -> miette::Result<u64> {
+    let cutoff = Utc::now() - retention;
+
+    let result = sqlx::query!(
+        r#"
+    DELETE FROM Checkins
+    WHERE created_at < $1
+      AND created_at < (
+        SELECT coalesce(max(bucket_start), '-infinity'::timestamptz)
+        FROM CheckinRollups
+        WHERE resolution = 'day'
+      )
+  "#,
+        cutoff
+    )
+    .execute(db)
+    .await
+    .into_diagnostic()?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn day_range_includes_the_last_day() {
+        let start = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let end = NaiveDate::from_ymd_opt(2024, 3, 31).unwrap();
+
+        let range = day_range(start, end).unwrap();
+
+        assert_eq!(range.start.date_naive(), start);
+        assert_eq!(
+            range.end.date_naive(),
+            NaiveDate::from_ymd_opt(2024, 4, 1).unwrap()
+        );
+    }
+
+    #[test]
+    fn day_range_ending_on_the_last_representable_day_is_none() {
+        let start = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+
+        assert!(day_range(start, NaiveDate::MAX).is_none());
+    }
+
+    #[test]
+    fn short_recent_ranges_read_the_raw_checkins() {
+        let now = Utc::now();
+        let retention = chrono::Duration::days(35);
+
+        assert_eq!(
+            Resolution::for_range(&(now - chrono::Duration::days(1)..now), retention),
+            None
+        );
+    }
+
+    #[test]
+    fn ranges_older_than_the_retention_read_the_rollups() {
+        let now = Utc::now();
+        let retention = chrono::Duration::days(35);
+        let start = now - chrono::Duration::days(60);
+
+        assert_eq!(
+            Resolution::for_range(&(start..start + chrono::Duration::days(1)), retention),
+            Some(Resolution::Hour)
+        );
+        assert_eq!(
+            Resolution::for_range(&(now - chrono::Duration::days(30)..now), retention),
+            Some(Resolution::Hour)
+        );
+        assert_eq!(
+            Resolution::for_range(&(now - chrono::Duration::days(120)..now), retention),
+            Some(Resolution::Day)
+        );
+    }
+}
@@ -3,7 +3,7 @@ use cja::{app_state::AppState as _, server::session::DBSession};
 use maud::{html, Markup, Render};
 use miette::IntoDiagnostic;
 
-use crate::app_state::AppState;
+use crate::{app_state::AppState, authz};
 
 pub struct Template {
     pub content: Markup,
@@ -67,6 +67,12 @@ impl IntoResponse for Template {
                                                         selected: false,
                                                         icon: "fa-solid fa-globe".to_string(),
                                                       },
+                                                      SideBarLink {
+                                                        text: "Organizations".to_string(),
+                                                        href: "/my/organizations".to_string(),
+                                                        selected: false,
+                                                        icon: "fa-solid fa-users".to_string(),
+                                                      },
                                                     ]
                                                   })
                                               }
@@ -112,6 +118,12 @@ impl IntoResponse for Template {
                                                 selected: false,
                                                 icon: "fa-solid fa-globe".to_string(),
                                               },
+                                              SideBarLink {
+                                                text: "Organizations".to_string(),
+                                                href: "/my/organizations".to_string(),
+                                                selected: false,
+                                                icon: "fa-solid fa-users".to_string(),
+                                              },
                                             ]
                                           })
                                       }
@@ -125,7 +137,7 @@ impl IntoResponse for Template {
                                       }
                                       li."-mx-6 mt-auto" {
                                           @if self.session.is_some() {
-                                            a."flex items-center gap-x-4 px-6 py-3 text-sm font-semibold leading-6 text-white hover:bg-indigo-700" href="#" {
+                                            a."flex items-center gap-x-4 px-6 py-3 text-sm font-semibold leading-6 text-white hover:bg-indigo-700" href="/my/sessions" {
                                               // img."h-8 w-8 rounded-full bg-indigo-700" src="https://images.unsplash.com/photo-1472099645785-5658abf4ff4e?ixlib=rb-1.2.1&ixid=eyJhcHBfaWQiOjEyMDd9&auto=format&fit=facearea&facepad=2&w=256&h=256&q=80" alt="" {}
                                               span {
                                                   "You are logged in"
@@ -298,14 +310,16 @@ impl IntoTemplate for Markup {
         session: Option<DBSession>,
     ) -> miette::Result<Template> {
         let sites = if let Some(session) = &session {
+            let site_ids = authz::accessible_site_ids(app_state.db(), session.user_id).await?;
+
             sqlx::query!(
                 r#"
       SELECT Sites.*
       FROM Sites
-      WHERE user_id = $1
+      WHERE site_id = ANY($1)
       LIMIT 5
       "#,
-                session.user_id
+                &site_ids
             )
             .fetch_all(app_state.db())
             .await
@@ -0,0 +1,183 @@
+use cja::app_state::AppState as _;
+use reqwest::{header, redirect::Policy, StatusCode};
+use sqlx::PgPool;
+use tower_cookies::{cookie::CookieJar, Cookie};
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    auth::{local::LocalConfig, AuthProvider},
+    authz::Role,
+    csrf::CSRF_FIELD,
+    sessions::SESSION_COOKIE,
+};
+
+/// A logged in user in their own organization, with one site that has one page
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub organization_id: Uuid,
+    pub site_id: Uuid,
+    pub page_id: Uuid,
+    /// The `Cookie` header that logs requests in as this user
+    pub cookie: String,
+    /// The session's CSRF token, which every POST needs
+    pub csrf_token: String,
+}
+
+pub fn test_state(pool: PgPool) -> AppState {
+    AppState::for_tests(
+        pool,
+        AuthProvider::Local(LocalConfig {
+            allow_signups: false,
+        }),
+    )
+}
+
+pub async fn create_user(app_state: &AppState, username: &str) -> TestUser {
+    let db = app_state.db();
+
+    let user_id = sqlx::query_scalar!(
+        r#"
+    INSERT INTO Users (user_id, username, password_hash)
+    VALUES ($1, $2, 'not a real hash')
+    RETURNING user_id
+  "#,
+        Uuid::new_v4(),
+        username
+    )
+    .fetch_one(db)
+    .await
+    .unwrap();
+
+    let organization_id = sqlx::query_scalar!(
+        r#"
+    INSERT INTO Organizations (name)
+    VALUES ($1)
+    RETURNING organization_id
+  "#,
+        username
+    )
+    .fetch_one(db)
+    .await
+    .unwrap();
+
+    sqlx::query!(
+        r#"
+    INSERT INTO OrganizationMemberships (organization_id, user_id, role)
+    VALUES ($1, $2, $3)
+  "#,
+        organization_id,
+        user_id,
+        Role::Owner.as_str()
+    )
+    .execute(db)
+    .await
+    .unwrap();
+
+    let site_id = sqlx::query_scalar!(
+        r#"
+    INSERT INTO Sites (organization_id, name, domain)
+    VALUES ($1, $2, $3)
+    RETURNING site_id
+  "#,
+        organization_id,
+        username,
+        format!("{username}.example.com")
+    )
+    .fetch_one(db)
+    .await
+    .unwrap();
+
+    let page_id = sqlx::query_scalar!(
+        r#"
+    INSERT INTO Pages (site_id, name, path)
+    VALUES ($1, 'Home', '/')
+    RETURNING page_id
+  "#,
+        site_id
+    )
+    .fetch_one(db)
+    .await
+    .unwrap();
+
+    let session = sqlx::query!(
+        r#"
+    INSERT INTO Sessions (session_id, user_id)
+    VALUES ($1, $2)
+    RETURNING session_id, csrf_token
+  "#,
+        Uuid::new_v4(),
+        user_id
+    )
+    .fetch_one(db)
+    .await
+    .unwrap();
+
+    // Encrypted the same way the cookie manager would
+    let mut jar = CookieJar::new();
+    jar.private_mut(app_state.cookie_key())
+        .add(Cookie::new(SESSION_COOKIE, session.session_id.to_string()));
+    let cookie = jar.get(SESSION_COOKIE).unwrap().stripped().to_string();
+
+    TestUser {
+        user_id,
+        organization_id,
+        site_id,
+        page_id,
+        cookie,
+        csrf_token: session.csrf_token,
+    }
+}
+
+/// Serves the whole app on a random local port, returning its base URL
+pub async fn serve(app_state: AppState) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+    tokio::spawn(async move { axum::serve(listener, crate::app(app_state)).await });
+
+    base_url
+}
+
+/// GETs `path` as `user` without following redirects
+pub async fn get_as(base_url: &str, user: &TestUser, path: &str) -> StatusCode {
+    get_body_as(base_url, user, path).await.0
+}
+
+/// Like [`get_as`], for when the test needs to look at what came back too
+pub async fn get_body_as(base_url: &str, user: &TestUser, path: &str) -> (StatusCode, String) {
+    let resp = reqwest::Client::builder()
+        .redirect(Policy::none())
+        .build()
+        .unwrap()
+        .get(format!("{base_url}{path}"))
+        .header(header::COOKIE, &user.cookie)
+        .send()
+        .await
+        .unwrap();
+
+    (resp.status(), resp.text().await.unwrap())
+}
+
+/// POSTs `form` to `path` as `user` without following redirects
+pub async fn post_as(
+    base_url: &str,
+    user: &TestUser,
+    path: &str,
+    form: &[(&str, &str)],
+) -> StatusCode {
+    let mut form = form.to_vec();
+    form.push((CSRF_FIELD, &user.csrf_token));
+
+    reqwest::Client::builder()
+        .redirect(Policy::none())
+        .build()
+        .unwrap()
+        .post(format!("{base_url}{path}"))
+        .header(header::COOKIE, &user.cookie)
+        .form(&form)
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
@@ -0,0 +1,23 @@
+/// Compares every byte so the time taken doesn't give away how much of the
+/// token was right. Use this for any secret a request is checked against
+pub fn tokens_match(submitted: &str, expected: &str) -> bool {
+    submitted.len() == expected.len()
+        && submitted
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_same_token_matches() {
+        assert!(tokens_match("abc123", "abc123"));
+        assert!(!tokens_match("abc124", "abc123"));
+        assert!(!tokens_match("abc12", "abc123"));
+        assert!(!tokens_match("", "abc123"));
+    }
+}
@@ -0,0 +1,254 @@
+use std::time::{Duration, Instant};
+
+use hyper::HeaderMap;
+use maud::{html, Markup, Render};
+use reqwest::Url;
+
+use crate::probe::probe;
+
+/// The longest a hostname can be, per RFC 1035
+const MAX_DOMAIN_LEN: usize = 253;
+
+/// How long the "Test" button waits, shorter than a checkin's own timeout so
+/// the form isn't left hanging
+const URL_TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The URL a page is checked at. Everything that builds one should go through
+/// here so what the form tests is what the checkins hit
+pub fn check_url(domain: &str, path: &str) -> String {
+    format!("https://{domain}{path}")
+}
+
+/// Turns what someone typed as a site's domain into the hostname checkins
+/// use. A pasted `https://` and trailing slash are dropped, the host is
+/// lowercased and internationalized names are converted to punycode. A port
+/// is kept. Returns a message for the form when it can't be a domain
+pub fn normalize_domain(input: &str) -> Result<String, String> {
+    let trimmed = input.trim();
+    let lowercase = trimmed.to_ascii_lowercase();
+    let without_scheme = ["https://", "http://"]
+        .into_iter()
+        .find(|scheme| lowercase.starts_with(scheme))
+        .map_or(trimmed, |scheme| &trimmed[scheme.len()..]);
+    let domain = without_scheme.strip_suffix('/').unwrap_or(without_scheme);
+
+    if domain.is_empty() {
+        return Err("Enter a domain, like example.com".to_string());
+    }
+    if domain.contains("://") {
+        return Err("Checkins always use https://, leave the scheme off".to_string());
+    }
+    if domain.chars().any(char::is_whitespace) {
+        return Err("Domains can't contain spaces".to_string());
+    }
+    if domain.contains(['/', '?', '#']) {
+        return Err("Enter just the domain. Paths go on the site's pages".to_string());
+    }
+    if domain.contains('@') {
+        return Err("Domains can't contain a username or password".to_string());
+    }
+
+    let url = Url::parse(&format!("https://{domain}/"))
+        .map_err(|_| "That isn't a valid domain".to_string())?;
+
+    // `domain` is only set for names, IP addresses are used as they are
+    let host = match url.domain() {
+        Some(host) => {
+            let host = host.trim_end_matches('.');
+            if !host.contains('.') {
+                return Err("Enter the full domain, like example.com".to_string());
+            }
+            if host.len() > MAX_DOMAIN_LEN || host.split('.').any(|label| label.len() > 63) {
+                return Err("That domain is too long".to_string());
+            }
+            host.to_string()
+        }
+        None => url
+            .host_str()
+            .ok_or_else(|| "That isn't a valid domain".to_string())?
+            .to_string(),
+    };
+
+    Ok(match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host,
+    })
+}
+
+/// Turns what someone typed as a page's path into one that's safe to put after
+/// the domain. A missing leading slash is added and anything that isn't
+/// allowed in a URL is percent-encoded. A query string is kept, a fragment is
+/// refused since it's never sent to the server
+pub fn normalize_path(input: &str) -> Result<String, String> {
+    let trimmed = input.trim();
+
+    if trimmed.contains("://") {
+        return Err("Enter just the path, like /about. The domain comes from the site".to_string());
+    }
+    if trimmed.starts_with("//") {
+        return Err("Paths can't start with //".to_string());
+    }
+    if trimmed.contains('#') {
+        return Err("Fragments aren't sent to the server, leave off the # part".to_string());
+    }
+
+    let path = if trimmed.starts_with('/') {
+        trimmed.to_string()
+    } else {
+        format!("/{trimmed}")
+    };
+
+    let url = Url::parse(&format!("https://example.com{path}"))
+        .map_err(|_| "That isn't a valid path".to_string())?;
+    // Browsers and `Url` read `\` as `/`, so check again once it's parsed
+    if url.path().starts_with("//") {
+        return Err("Paths can't start with //".to_string());
+    }
+
+    Ok(match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    })
+}
+
+/// What happened when a form's "Test" button checked a URL before saving
+pub enum UrlTest {
+    Responded {
+        url: String,
+        status: u16,
+        success: bool,
+        duration_ms: u128,
+    },
+    Failed {
+        url: String,
+        message: String,
+    },
+}
+
+impl UrlTest {
+    /// Runs a single check the same way `CreateCheckin` would, without
+    /// recording it. Why it failed is only logged, the page just says that it
+    /// did so the form can't be used to map out what the server can reach
+    pub async fn run(url: String) -> Self {
+        let started = Instant::now();
+
+        match tokio::time::timeout(URL_TEST_TIMEOUT, probe(&url, &HeaderMap::new())).await {
+            Ok(Ok(resp)) => UrlTest::Responded {
+                url,
+                status: resp.status.as_u16(),
+                success: resp.status.is_success(),
+                duration_ms: started.elapsed().as_millis(),
+            },
+            Ok(Err(err)) => {
+                tracing::info!(?err, url, "Testing a URL failed");
+                UrlTest::Failed {
+                    url,
+                    message: "it couldn't be reached. Check the domain and path are right and that the site is up.".to_string(),
+                }
+            }
+            Err(_) => UrlTest::Failed {
+                url,
+                message: format!(
+                    "it didn't respond within {} seconds.",
+                    URL_TEST_TIMEOUT.as_secs()
+                ),
+            },
+        }
+    }
+}
+
+impl Render for UrlTest {
+    fn render(&self) -> Markup {
+        html! {
+          @match self {
+            UrlTest::Responded { url, status, success, duration_ms } => {
+              @if *success {
+                p."text-green-700" {
+                  (url) " responded with " (status) " in " (duration_ms) "ms."
+                }
+              } @else {
+                p."text-red-700" {
+                  (url) " responded with " (status) " in " (duration_ms) "ms, which would count as a failed checkin."
+                }
+              }
+            }
+            UrlTest::Failed { url, message } => {
+              p."text-red-700" { "Couldn't check " (url) ", " (message) }
+            }
+          }
+        }
+    }
+}
+
+/// The message shown under a field that didn't validate
+pub fn field_error(error: Option<&str>) -> Markup {
+    html! {
+      @if let Some(error) = error {
+        p."text-sm text-red-600" { (error) }
+      }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn domains_are_normalized() {
+        for (input, expected) in [
+            ("Example.com", "example.com"),
+            (" https://example.com/ ", "example.com"),
+            ("example.com.", "example.com"),
+            ("bücher.example", "xn--bcher-kva.example"),
+            ("example.com:8443", "example.com:8443"),
+            ("example.com:443", "example.com"),
+        ] {
+            assert_eq!(normalize_domain(input).as_deref(), Ok(expected), "{input}");
+        }
+    }
+
+    #[test]
+    fn domains_with_more_than_a_host_are_refused() {
+        for input in [
+            "",
+            "localhost",
+            "example.com/about",
+            "example.com#top",
+            "user@example.com",
+            "ftp://example.com",
+            "exa mple.com",
+        ] {
+            assert!(normalize_domain(input).is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    fn paths_are_normalized() {
+        for (input, expected) in [
+            ("/", "/"),
+            ("about", "/about"),
+            ("/search?q=up guardian", "/search?q=up%20guardian"),
+            ("/a\\b", "/a/b"),
+        ] {
+            assert_eq!(normalize_path(input).as_deref(), Ok(expected), "{input}");
+        }
+    }
+
+    #[test]
+    fn paths_that_would_leave_the_site_are_refused() {
+        for input in [
+            "//evil.example",
+            "\\\\evil.example",
+            "/\\evil.example",
+            "\\evil.example",
+            "https://evil.example/",
+        ] {
+            assert!(normalize_path(input).is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    fn fragments_are_refused() {
+        assert!(normalize_path("/about#team").is_err());
+    }
+}